@@ -0,0 +1,16 @@
+create table if not exists users (
+    id integer primary key autoincrement,
+    username text not null unique,
+    password text not null,
+    is_admin boolean not null default false
+);
+
+create table if not exists sessions (
+    id integer primary key autoincrement,
+    user_id integer not null references users (id) on delete cascade,
+    token_hash text not null unique,
+    refresh_hash text not null unique,
+    expires_at integer not null,
+    refresh_expires_at integer not null,
+    revoked boolean not null default false
+);
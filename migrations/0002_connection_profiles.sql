@@ -0,0 +1,10 @@
+create table if not exists connection_profiles (
+    name text primary key,
+    label text,
+    driver text not null,
+    db_url text not null,
+    max_connections integer not null,
+    acquire_timeout integer,
+    idle_timeout integer,
+    is_default boolean not null default false
+);
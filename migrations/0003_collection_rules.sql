@@ -0,0 +1,10 @@
+create table if not exists collection_rules (
+    connection text not null,
+    collection text not null,
+    list_rule text,
+    view_rule text,
+    create_rule text,
+    update_rule text,
+    delete_rule text,
+    primary key (connection, collection)
+);
@@ -0,0 +1,6 @@
+create table if not exists schema_migrations (
+    id integer primary key autoincrement,
+    connection text not null,
+    sql text not null,
+    applied_at integer not null
+);
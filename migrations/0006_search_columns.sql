@@ -0,0 +1,6 @@
+create table if not exists search_columns (
+    connection text not null,
+    collection text not null,
+    columns text not null,
+    primary key (connection, collection)
+);
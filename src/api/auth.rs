@@ -1,53 +1,82 @@
-use actix_web::{get, post, web, HttpResponse, Responder};
+use actix_web::{get, http::header, post, web, HttpRequest, HttpResponse};
 use serde::Deserialize;
-use sqlx;
 
+use crate::api::error::ApiError;
+use crate::internal::auth::{self, AuthError, User};
 use crate::AppState;
 
-#[derive(sqlx::FromRow, Deserialize, Debug)]
+#[derive(Deserialize, Debug)]
 pub struct Credentials {
     pub username: String,
     pub password: String,
 }
 
+#[derive(Deserialize)]
+pub struct Refresh {
+    pub refresh_token: String,
+}
+
+/// Token from an `Authorization: Bearer <token>` header.
+pub fn bearer_token(req: &HttpRequest) -> Option<&str> {
+    req.headers()
+        .get(header::AUTHORIZATION)?
+        .to_str()
+        .ok()?
+        .strip_prefix("Bearer ")
+        .map(str::trim)
+}
+
+pub fn missing_token() -> ApiError {
+    ApiError::unauthorized("missing_token", "Missing bearer token")
+}
+
 #[post("/login")]
-pub async fn login(body: web::Json<Credentials>, state: web::Data<AppState>) -> impl Responder {
-    let sqlite_pool = &state.sqlite_pool;
-    let user =
-        sqlx::query_as::<_, Credentials>("SELECT * FROM users WHERE username = $1 AND password = $2")
-            .bind(&body.username)
-            .bind(&body.password)
-            .fetch_one(sqlite_pool)
-            .await;
-    if let Ok(user) = user {
-        return HttpResponse::Ok().body(format!("Logged in as {:#?}", user));
+pub async fn login(
+    body: web::Json<Credentials>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let session = auth::login(&state.sqlite_pool, &body.username, &body.password).await?;
+    Ok(HttpResponse::Ok().json(session))
+}
+
+#[post("/refresh")]
+pub async fn refresh(
+    body: web::Json<Refresh>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let session = auth::refresh(&state.sqlite_pool, &body.refresh_token).await?;
+    Ok(HttpResponse::Ok().json(session))
+}
+
+#[post("/logout")]
+pub async fn logout(
+    req: HttpRequest,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let token = bearer_token(&req).ok_or_else(missing_token)?;
+    if !auth::logout(&state.sqlite_pool, token).await? {
+        return Err(AuthError::InvalidToken.into());
     }
-    HttpResponse::BadRequest().body("Invalid username or password")
+    Ok(HttpResponse::Ok().body("Logged out"))
 }
 
-#[get("/logout")]
-pub async fn logout() -> impl Responder {
-    HttpResponse::Ok().body("Logged out")
+/// The user the bearer token belongs to.
+#[get("/me")]
+pub async fn me(user: User) -> HttpResponse {
+    HttpResponse::Ok().json(user)
 }
 
 #[post("/register")]
-pub async fn register(body: web::Json<Credentials>, state: web::Data<AppState>) -> impl Responder {
-    let sqlite_pool = &state.sqlite_pool;
-    let user = sqlx::query_as::<_, Credentials>("SELECT * FROM users WHERE username = $1")
-        .bind(&body.username)
-        .fetch_one(sqlite_pool)
-        .await;
-    if let Ok(_) = user {
-        return HttpResponse::Ok().body("User already exists");
-    }
-    let user =
-        sqlx::query_as::<_, Credentials>("INSERT INTO users (username, password) VALUES ($1, $2)")
-            .bind(&body.username)
-            .bind(&body.password)
-            .fetch_one(sqlite_pool)
-            .await;
-    if let Ok(user) = user {
-        return HttpResponse::Ok().body(format!("Registered as {:#?}", user));
+pub async fn register(
+    body: web::Json<Credentials>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    if body.username.is_empty() || body.password.is_empty() {
+        return Err(ApiError::bad_request(
+            "invalid_credentials",
+            "Username and password are required",
+        ));
     }
-    return HttpResponse::Ok().body("Failed to register");
+    let user = auth::register(&state.sqlite_pool, &body.username, &body.password).await?;
+    Ok(HttpResponse::Ok().json(user))
 }
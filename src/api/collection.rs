@@ -1,240 +1,466 @@
-use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
+use std::collections::HashMap;
+
+use actix_web::{delete, get, post, put, web, HttpResponse};
 use serde::Deserialize;
 
-use crate::{internal::db::DBQuery, AppState};
+use crate::{
+    api::stream::{self, StreamFormat},
+    api::{connection, error::ApiError},
+    internal::aggregate::{Aggregate, Metric},
+    internal::auth::User,
+    internal::db::{DBQuery, DBXError, DBX},
+    internal::de::{QueryResult, Shape, Table},
+    internal::expand::Relation,
+    internal::export::ExportFormat,
+    internal::fields::Field,
+    internal::filter::{Expr, Literal},
+    internal::page::{Page, Pagination},
+    internal::rules::{self, Access, Operation},
+    internal::search::{self, Search, RELEVANCE},
+    internal::sort::Sort,
+    AppState,
+};
 
 #[derive(Deserialize)]
 struct QueryFilter {
+    fields: Option<String>,
+    /// Superseded by `fields`.
     columns: Option<String>,
     r#where: Option<String>,
+    sort: Option<String>,
+    /// Superseded by `sort`.
     order_by: Option<String>,
     order: Option<String>,
     limit: Option<i32>,
     offset: Option<i32>,
+    page: Option<i64>,
+    per_page: Option<i64>,
+    cursor: Option<String>,
+    #[serde(default)]
+    skip_total: bool,
+    expand: Option<String>,
+    search: Option<String>,
+    #[serde(default)]
+    shape: Shape,
+    /// Streams every matching row, within `limit` and `offset`, instead of
+    /// a page; rows are streamed as objects.
+    stream: Option<StreamFormat>,
+}
+
+impl QueryFilter {
+    fn fields(&self) -> Result<Vec<Field>, DBXError> {
+        match self.fields.as_ref().or(self.columns.as_ref()) {
+            Some(fields) => Field::parse(fields),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    /// Searches sort by relevance unless sorted otherwise.
+    fn sort(&self) -> Result<Vec<Sort>, DBXError> {
+        match (&self.sort, &self.order_by) {
+            (Some(sort), _) => Sort::parse(sort),
+            (None, Some(order_by)) => Ok(vec![Sort::from_order(order_by, self.order.as_deref())?]),
+            (None, None) if self.search.is_some() => Ok(vec![Sort {
+                column: RELEVANCE.to_string(),
+                descending: true,
+                nulls: None,
+            }]),
+            (None, None) => Ok(Vec::new()),
+        }
+    }
+
+    fn pagination(&self) -> Pagination {
+        Pagination {
+            page: self.page,
+            per_page: self.per_page,
+            cursor: self.cursor.clone(),
+            skip_total: self.skip_total,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct ExportQuery {
+    format: ExportFormat,
+}
+
+#[derive(Deserialize)]
+struct AggregateFilter {
+    /// Defaults to `count`.
+    metrics: Option<String>,
+    group_by: Option<String>,
+    r#where: Option<String>,
+}
+
+fn record_not_found(collection: &str, id: &str) -> ApiError {
+    ApiError::not_found(
+        "record_not_found",
+        format!("Collection {} with id {} is not found", collection, id),
+    )
+}
+
+/// The filter `user` is restricted to for `operation` on the collection, or
+/// the error refusing the operation outright.
+async fn rule_filter(
+    state: &AppState,
+    connection: &str,
+    collection: &str,
+    operation: Operation,
+    user: &User,
+) -> Result<Option<Expr>, ApiError> {
+    match rules::access(&state.sqlite_pool, connection, collection, operation, user).await? {
+        Access::All => Ok(None),
+        Access::Filter(rule) => Ok(Some(rule)),
+        Access::Denied => Err(ApiError::forbidden(
+            "forbidden",
+            format!("Only admins can {} collection {}", operation, collection),
+        )),
+    }
+}
+
+/// Embeds the relations listed in `expand` into `rows`, each restricted to
+/// what `user` may list of its collection.
+async fn expand(
+    state: &AppState,
+    dbx: &DBX,
+    connection: &str,
+    collection: &str,
+    expand: Option<&str>,
+    rows: &mut [QueryResult],
+    user: &User,
+) -> Result<(), ApiError> {
+    let expand = match expand {
+        Some(expand) => expand,
+        None => return Ok(()),
+    };
+    let relations = dbx.relations(collection, expand)?;
+    let mut filters = HashMap::new();
+    for table in Relation::tables(&relations) {
+        if let Some(rule) = rule_filter(state, connection, &table, Operation::List, user).await? {
+            filters.insert(table, rule);
+        }
+    }
+    dbx.expand(rows, &relations, &filters).await?;
+    Ok(())
+}
+
+/// Resolves `term` against the columns configured for searching the
+/// collection, or its text columns.
+async fn search(
+    state: &AppState,
+    dbx: &DBX,
+    connection: &str,
+    collection: &str,
+    term: Option<&str>,
+) -> Result<Option<Search>, ApiError> {
+    let term = match term {
+        Some(term) => term,
+        None => return Ok(None),
+    };
+    let columns = search::find(&state.sqlite_pool, connection, collection)
+        .await?
+        .unwrap_or_default();
+    Ok(Some(dbx.search(collection, term, &columns).await?))
+}
+
+fn and(left: Option<Expr>, right: Option<Expr>) -> Option<Expr> {
+    match (left, right) {
+        (Some(left), Some(right)) => Some(Expr::And(Box::new(left), Box::new(right))),
+        (left, None) => left,
+        (None, right) => right,
+    }
+}
+
+fn paged(page: Page, shape: Shape) -> HttpResponse {
+    match shape {
+        Shape::Objects => HttpResponse::Ok().json(page),
+        Shape::Arrays => HttpResponse::Ok().json(page.map_items(Table::new)),
+    }
+}
+
+fn json_object(
+    body: serde_json::Value,
+) -> Result<serde_json::Map<String, serde_json::Value>, ApiError> {
+    match body {
+        serde_json::Value::Object(row) => Ok(row),
+        _ => Err(ApiError::bad_request(
+            "invalid_body",
+            "Expected a JSON object",
+        )),
+    }
+}
+
+/// The query listing what `filter` selects of the collection, within what
+/// `user` may list.
+async fn list_query(
+    state: &AppState,
+    dbx: &DBX,
+    path: &(String, String),
+    filter: &QueryFilter,
+    user: &User,
+) -> Result<DBQuery, ApiError> {
+    let r#where = filter
+        .r#where
+        .as_deref()
+        .map(|r#where| Expr::parse(r#where)?.resolve(&rules::auth_vars(user)))
+        .transpose()?;
+    let rule = rule_filter(state, &path.0, &path.1, Operation::List, user).await?;
+    let term = filter.search.as_deref();
+    Ok(DBQuery {
+        table: path.1.clone(),
+        fields: filter.fields()?,
+        r#where: and(r#where, rule),
+        search: search(state, dbx, &path.0, &path.1, term).await?,
+        sort: filter.sort()?,
+        limit: filter.limit,
+        offset: filter.offset,
+    })
 }
 
 #[get("/{collection}")]
 async fn get_all(
-    path: web::Path<String>,
+    path: web::Path<(String, String)>,
     filter: web::Query<QueryFilter>,
     state: web::Data<AppState>,
-) -> impl Responder {
-    let state_pg_pool = state.dbx.lock().ok();
-    if let Some(pg_pool) = state_pg_pool {
-        if let Some(dbx) = pg_pool.as_ref() {
-            let query = DBQuery {
-                table: path.clone(),
-                columns: filter.columns.clone(),
-                r#where: filter.r#where.clone(),
-                order_by: filter.order_by.clone(),
-                order: filter.order.clone(),
-                limit: filter.limit.clone(),
-                offset: filter.offset.clone(),
-            };
-            let result = dbx.select(&query).await;
-            return match result {
-                Ok(rows) => HttpResponse::Ok().json(rows),
-                Err(e) => HttpResponse::InternalServerError().body(e.to_string()),
-            };
+    user: User,
+) -> Result<HttpResponse, ApiError> {
+    let dbx = connection(&state, &path.0)?;
+    let query = list_query(&state, &dbx, &path, &filter, &user).await?;
+    if let Some(format) = filter.stream {
+        if filter.expand.is_some() {
+            return Err(ApiError::bad_request(
+                "invalid_query_string",
+                "Streamed rows cannot be expanded",
+            ));
+        }
+        if filter.shape == Shape::Arrays {
+            return Err(ApiError::bad_request(
+                "invalid_query_string",
+                "Streamed rows are always objects",
+            ));
         }
-        return HttpResponse::InternalServerError().body("Not connected to database");
+        return stream::respond(dbx.select_stream(query)?, format).await;
+    }
+    let mut page = dbx.select_page(&query, &filter.pagination()).await?;
+    let relations = filter.expand.as_deref();
+    let rows = &mut page.items;
+    expand(&state, &dbx, &path.0, &path.1, relations, rows, &user).await?;
+    Ok(paged(page, filter.shape))
+}
+
+/// Downloads every record `filter` selects as a `format` file.
+#[get("/{collection}/_export")]
+async fn export(
+    path: web::Path<(String, String)>,
+    filter: web::Query<QueryFilter>,
+    export: web::Query<ExportQuery>,
+    state: web::Data<AppState>,
+    user: User,
+) -> Result<HttpResponse, ApiError> {
+    if filter.expand.is_some() {
+        return Err(ApiError::bad_request(
+            "invalid_query_string",
+            "Exported rows cannot be expanded",
+        ));
     }
-    HttpResponse::InternalServerError().body("Failed to lock pool")
+    let dbx = connection(&state, &path.0)?;
+    let query = list_query(&state, &dbx, &path, &filter, &user).await?;
+    let columns = dbx.export_columns(&query)?;
+    let rows = dbx.select_stream(query)?;
+    stream::download(rows, export.format, columns, &path.1).await
+}
+
+/// Computes `metrics` such as `count,avg(age)` over the records matching
+/// `where`, per distinct value of the `group_by` columns.
+#[get("/{collection}/_aggregate")]
+async fn aggregate(
+    path: web::Path<(String, String)>,
+    filter: web::Query<AggregateFilter>,
+    state: web::Data<AppState>,
+    user: User,
+) -> Result<HttpResponse, ApiError> {
+    let r#where = filter
+        .r#where
+        .as_deref()
+        .map(|r#where| Expr::parse(r#where)?.resolve(&rules::auth_vars(&user)))
+        .transpose()?;
+    let dbx = connection(&state, &path.0)?;
+    let rule = rule_filter(&state, &path.0, &path.1, Operation::List, &user).await?;
+    let aggregate = Aggregate {
+        table: path.1.clone(),
+        metrics: Metric::parse(filter.metrics.as_deref().unwrap_or("count"))?,
+        group_by: filter
+            .group_by
+            .iter()
+            .flat_map(|group_by| group_by.split(','))
+            .map(str::trim)
+            .filter(|column| !column.is_empty())
+            .map(str::to_string)
+            .collect(),
+        r#where: and(r#where, rule),
+    };
+    let rows = dbx.aggregate(&aggregate).await?;
+    Ok(HttpResponse::Ok().json(rows))
 }
 
 #[get("/{collection}/{id}")]
 async fn get(
-    path: web::Path<(String, String)>,
+    path: web::Path<(String, String, String)>,
     filter: web::Query<QueryFilter>,
     state: web::Data<AppState>,
-) -> impl Responder {
-    let state_pg_pool = state.dbx.lock().ok();
-    if let Some(pg_pool) = state_pg_pool {
-        if let Some(dbx) = pg_pool.as_ref() {
-            let query = DBQuery {
-                table: path.0.clone(),
-                columns: filter.columns.clone(),
-                r#where: Some(format!("id = '{}'", path.1)),
-                order_by: filter.order_by.clone(),
-                order: filter.order.clone(),
-                limit: filter.limit.clone(),
-                offset: filter.offset.clone(),
-            };
-            let users = dbx.select_one(&query).await;
-            if let Ok(user) = users {
-                return HttpResponse::Ok().json(user);
-            }
-            return HttpResponse::NotFound().body(format!(
-                "Collection {} with id {} is not found",
-                path.0, path.1
-            ));
+    user: User,
+) -> Result<HttpResponse, ApiError> {
+    let dbx = connection(&state, &path.0)?;
+    let rule = rule_filter(&state, &path.0, &path.1, Operation::View, &user).await?;
+    let query = DBQuery {
+        table: path.1.clone(),
+        fields: filter.fields()?,
+        r#where: rule,
+        search: None,
+        sort: Vec::new(),
+        limit: None,
+        offset: None,
+    };
+    let mut row = match dbx.select_by_id(query, &path.2).await {
+        Ok(row) => row,
+        Err(DBXError::Sqlx(sqlx::Error::RowNotFound)) => {
+            return Err(record_not_found(&path.1, &path.2))
         }
-        return HttpResponse::InternalServerError().body("Not connected to database");
-    }
-    HttpResponse::InternalServerError().body("Failed to lock pool")
+        Err(e) => return Err(e.into()),
+    };
+    let relations = filter.expand.as_deref();
+    let rows = std::slice::from_mut(&mut row);
+    expand(&state, &dbx, &path.0, &path.1, relations, rows, &user).await?;
+    Ok(HttpResponse::Ok().json(row))
 }
 
 #[get("/{collection}/{where}/{value}")]
 async fn get_by_field(
-    path: web::Path<(String, String, String)>,
+    path: web::Path<(String, String, String, String)>,
     filter: web::Query<QueryFilter>,
     state: web::Data<AppState>,
-) -> impl Responder {
-    let state_pg_pool = state.dbx.lock().ok();
-    if let Some(pg_pool) = state_pg_pool {
-        if let Some(dbx) = pg_pool.as_ref() {
-            let query = DBQuery {
-                table: path.0.clone(),
-                columns: filter.columns.clone(),
-                r#where: Some(format!("{} = '{}'", path.1, path.2)),
-                order_by: filter.order_by.clone(),
-                order: filter.order.clone(),
-                limit: filter.limit.clone(),
-                offset: filter.offset.clone(),
-            };
-            let users = dbx.select(&query).await;
-            if let Ok(user) = users {
-                return HttpResponse::Ok().json(user);
-            }
-            return HttpResponse::NotFound().body(format!(
-                "Collection {} with {} {} is not found",
-                path.0, path.1, path.2
-            ));
-        }
-        return HttpResponse::InternalServerError().body("Not connected to database");
-    }
-    HttpResponse::InternalServerError().body("Failed to lock pool")
+    user: User,
+) -> Result<HttpResponse, ApiError> {
+    let dbx = connection(&state, &path.0)?;
+    let rule = rule_filter(&state, &path.0, &path.1, Operation::List, &user).await?;
+    let term = filter.search.as_deref();
+    let query = DBQuery {
+        table: path.1.clone(),
+        fields: filter.fields()?,
+        r#where: and(
+            Some(Expr::eq(&path.2, Literal::String(path.3.clone()))),
+            rule,
+        ),
+        search: search(&state, &dbx, &path.0, &path.1, term).await?,
+        sort: filter.sort()?,
+        limit: filter.limit,
+        offset: filter.offset,
+    };
+    let mut page = dbx.select_page(&query, &filter.pagination()).await?;
+    let relations = filter.expand.as_deref();
+    let rows = &mut page.items;
+    expand(&state, &dbx, &path.0, &path.1, relations, rows, &user).await?;
+    Ok(paged(page, filter.shape))
 }
 
 #[post("/{collection}")]
 async fn create(
-    path: web::Path<String>,
+    path: web::Path<(String, String)>,
     state: web::Data<AppState>,
     body: web::Json<serde_json::Value>,
-) -> impl Responder {
-    let state_pg_pool = state.dbx.lock().ok();
-    if let Some(pg_pool) = state_pg_pool {
-        if let Some(dbx) = pg_pool.as_ref() {
-            let users = dbx
-                .insert_one(format!("insert into {} values {}", path, body).as_str())
-                .await;
-            if let Ok(user) = users {
-                return HttpResponse::Ok().json(user);
-            }
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to collections: {}", path));
-        }
-        return HttpResponse::InternalServerError().body("Not connected to database");
-    }
-    HttpResponse::InternalServerError().body("Failed to lock pool")
+    user: User,
+) -> Result<HttpResponse, ApiError> {
+    let row = json_object(body.into_inner())?;
+    let dbx = connection(&state, &path.0)?;
+    let rule = rule_filter(&state, &path.0, &path.1, Operation::Create, &user).await?;
+    let row = dbx.insert_one(&path.1, &row, rule.as_ref()).await?;
+    Ok(HttpResponse::Ok().json(row))
 }
 
 #[post("/batch/{collection}")]
 async fn create_batch(
-    path: web::Path<String>,
+    path: web::Path<(String, String)>,
     state: web::Data<AppState>,
     body: web::Json<serde_json::Value>,
-) -> impl Responder {
-    let state_pg_pool = state.dbx.lock().ok();
-    if let Some(pg_pool) = state_pg_pool {
-        if let Some(dbx) = pg_pool.as_ref() {
-            let users = dbx
-                .insert(format!("insert into {} values {}", path, body).as_str())
-                .await;
-            if let Ok(user) = users {
-                return HttpResponse::Ok().json(user);
-            }
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to collections: {}", path));
-        }
-        return HttpResponse::InternalServerError().body("Not connected to database");
+    user: User,
+) -> Result<HttpResponse, ApiError> {
+    let rows = match body.into_inner() {
+        serde_json::Value::Array(rows) => rows
+            .into_iter()
+            .map(json_object)
+            .collect::<Result<Vec<_>, _>>()
+            .ok(),
+        _ => None,
     }
-    HttpResponse::InternalServerError().body("Failed to lock pool")
+    .ok_or_else(|| ApiError::bad_request("invalid_body", "Expected a JSON array of objects"))?;
+    let dbx = connection(&state, &path.0)?;
+    let rule = rule_filter(&state, &path.0, &path.1, Operation::Create, &user).await?;
+    let rows = dbx.insert(&path.1, &rows, rule.as_ref()).await?;
+    Ok(HttpResponse::Ok().json(rows))
 }
 
 #[put("/{collection}/{id}")]
 async fn update(
-    path: web::Path<(String, String)>,
+    path: web::Path<(String, String, String)>,
     state: web::Data<AppState>,
     body: web::Json<serde_json::Value>,
-) -> impl Responder {
-    let state_pg_pool = state.dbx.lock().ok();
-    if let Some(pg_pool) = state_pg_pool {
-        if let Some(dbx) = pg_pool.as_ref() {
-            let users = dbx
-                .update(format!("update {} set {} where id = {}", path.0, body, path.1).as_str())
-                .await;
-            if let Ok(user) = users {
-                return HttpResponse::Ok().json(user);
-            }
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to collections: {}", path.0));
-        }
-        return HttpResponse::InternalServerError().body("Not connected to database");
+    user: User,
+) -> Result<HttpResponse, ApiError> {
+    let row = json_object(body.into_inner())?;
+    let dbx = connection(&state, &path.0)?;
+    let rule = rule_filter(&state, &path.0, &path.1, Operation::Update, &user).await?;
+    let mut rows = dbx
+        .update(&path.1, &[path.2.as_str()], &row, rule.as_ref())
+        .await?;
+    if rows.is_empty() {
+        return Err(record_not_found(&path.1, &path.2));
     }
-    HttpResponse::InternalServerError().body("Failed to lock pool")
+    Ok(HttpResponse::Ok().json(rows.remove(0)))
 }
 
-#[put("/batch/{collection}/{id}")]
+/// Applies the same changes to every record in the comma-separated `{ids}`.
+#[put("/batch/{collection}/{ids}")]
 async fn update_batch(
-    path: web::Path<(String, String)>,
+    path: web::Path<(String, String, String)>,
     state: web::Data<AppState>,
     body: web::Json<serde_json::Value>,
-) -> impl Responder {
-    let state_pg_pool = state.dbx.lock().ok();
-    if let Some(pg_pool) = state_pg_pool {
-        if let Some(dbx) = pg_pool.as_ref() {
-            let users = dbx
-                .update(format!("update {} set {} where id = {}", path.0, body, path.1).as_str())
-                .await;
-            if let Ok(user) = users {
-                return HttpResponse::Ok().json(user);
-            }
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to collections: {}", path.0));
-        }
-        return HttpResponse::InternalServerError().body("Not connected to database");
-    }
-    HttpResponse::InternalServerError().body("Failed to lock pool")
+    user: User,
+) -> Result<HttpResponse, ApiError> {
+    let row = json_object(body.into_inner())?;
+    let dbx = connection(&state, &path.0)?;
+    let rule = rule_filter(&state, &path.0, &path.1, Operation::Update, &user).await?;
+    let ids: Vec<&str> = path.2.split(',').collect();
+    let rows = dbx.update(&path.1, &ids, &row, rule.as_ref()).await?;
+    Ok(HttpResponse::Ok().json(rows))
 }
 
 #[delete("/{collection}/{id}")]
-async fn delete(path: web::Path<(String, String)>, state: web::Data<AppState>) -> impl Responder {
-    let state_pg_pool = state.dbx.lock().ok();
-    if let Some(pg_pool) = state_pg_pool {
-        if let Some(dbx) = pg_pool.as_ref() {
-            let users = dbx
-                .delete(format!("delete from {} where id = {}", path.0, path.1).as_str())
-                .await;
-            if let Ok(user) = users {
-                return HttpResponse::Ok().json(user);
-            }
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to collections: {}", path.0));
-        }
-        return HttpResponse::InternalServerError().body("Not connected to database");
+async fn delete(
+    path: web::Path<(String, String, String)>,
+    state: web::Data<AppState>,
+    user: User,
+) -> Result<HttpResponse, ApiError> {
+    let dbx = connection(&state, &path.0)?;
+    let rule = rule_filter(&state, &path.0, &path.1, Operation::Delete, &user).await?;
+    let mut rows = dbx
+        .delete(&path.1, &[path.2.as_str()], rule.as_ref())
+        .await?;
+    if rows.is_empty() {
+        return Err(record_not_found(&path.1, &path.2));
     }
-    HttpResponse::InternalServerError().body("Failed to lock pool")
+    Ok(HttpResponse::Ok().json(rows.remove(0)))
 }
 
-#[delete("/batch/{collection}/{id}")]
+/// Deletes every record in the comma-separated `{ids}`.
+#[delete("/batch/{collection}/{ids}")]
 async fn delete_batch(
-    path: web::Path<(String, String)>,
+    path: web::Path<(String, String, String)>,
     state: web::Data<AppState>,
-) -> impl Responder {
-    let state_pg_pool = state.dbx.lock().ok();
-    if let Some(pg_pool) = state_pg_pool {
-        if let Some(dbx) = pg_pool.as_ref() {
-            let users = dbx
-                .delete(format!("delete from {} where id = {}", path.0, path.1).as_str())
-                .await;
-            if let Ok(user) = users {
-                return HttpResponse::Ok().json(user);
-            }
-            return HttpResponse::InternalServerError()
-                .body(format!("Failed to collections: {}", path.0));
-        }
-        return HttpResponse::InternalServerError().body("Not connected to database");
-    }
-    HttpResponse::InternalServerError().body("Failed to lock pool")
+    user: User,
+) -> Result<HttpResponse, ApiError> {
+    let dbx = connection(&state, &path.0)?;
+    let rule = rule_filter(&state, &path.0, &path.1, Operation::Delete, &user).await?;
+    let ids: Vec<&str> = path.2.split(',').collect();
+    let rows = dbx.delete(&path.1, &ids, rule.as_ref()).await?;
+    Ok(HttpResponse::Ok().json(rows))
 }
@@ -1,128 +1,337 @@
-use actix_web::{delete, get, post, put, web, HttpResponse, Responder};
-use serde::Deserialize;
+use std::time::Duration;
 
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::api::stream::{self, StreamFormat};
+use crate::api::{connection, error::ApiError};
 use crate::internal::db::DBX;
-use crate::models::schema::{Table, VColumn};
+use crate::internal::de::{Shape, Table};
+use crate::internal::profiles::{self, Profile};
+use crate::internal::rules::{self, Rules};
+use crate::internal::search;
+use crate::utils::db::PoolConfig;
 use crate::AppState;
 
 #[derive(Deserialize)]
 struct Connect {
     max_connections: u32,
     db_url: String,
+    acquire_timeout: Option<u32>,
+    idle_timeout: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct SaveProfile {
+    label: Option<String>,
+    max_connections: u32,
+    db_url: String,
+    acquire_timeout: Option<u32>,
+    idle_timeout: Option<u32>,
+    #[serde(default)]
+    is_default: bool,
+}
+
+/// Names that a connection would share with a static route segment, under
+/// `/api/db` or at the top of `/api`, and so could never be reached.
+const RESERVED_NAMES: [&str; 5] = ["connections", "profiles", "db", "auth", "settings"];
+
+fn validate_name(name: &str) -> Result<(), ApiError> {
+    if RESERVED_NAMES.contains(&name) {
+        return Err(ApiError::bad_request(
+            "invalid_name",
+            format!("Connection name {} is reserved", name),
+        ));
+    }
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+    {
+        return Err(ApiError::bad_request(
+            "invalid_name",
+            "Connection names may only contain letters, digits, _ and -",
+        ));
+    }
+    Ok(())
+}
+
+fn connection_exists(name: &str) -> ApiError {
+    ApiError::conflict(
+        "connection_exists",
+        format!("Connection {} already exists", name),
+    )
+}
+
+fn profile_not_found(name: &str) -> ApiError {
+    ApiError::not_found("profile_not_found", format!("Profile {} not found", name))
 }
 
-#[post("/connect")]
-async fn connect(body: web::Json<Connect>, state: web::Data<AppState>) -> impl Responder {
-    let dbx = state.dbx.lock().ok();
-    if let Some(mut dbx) = dbx {
-        if dbx.is_some() {
-            return HttpResponse::Ok().body("Already connected to database");
-        }
-        let db_url = body.db_url.clone();
-        let max_connections = body.max_connections.clone();
-        let new_dbx = DBX::new(max_connections, db_url.as_str()).await;
-        if let Ok(new_dbx) = new_dbx {
-            *dbx = Some(new_dbx);
-            return HttpResponse::Ok().body("Connected to database");
-        }
-        return HttpResponse::InternalServerError().body("Failed to connect to database");
+fn rules_not_found(collection: &str) -> ApiError {
+    ApiError::not_found(
+        "rules_not_found",
+        format!("Collection {} has no rules", collection),
+    )
+}
+
+fn search_not_found(collection: &str) -> ApiError {
+    ApiError::not_found(
+        "search_not_found",
+        format!("Collection {} has no search columns", collection),
+    )
+}
+
+/// Connects to `db_url` and registers the pool as `name`.
+async fn open_connection(
+    state: &AppState,
+    name: &str,
+    config: &PoolConfig,
+    db_url: &str,
+) -> Result<HttpResponse, ApiError> {
+    if state.connections.contains(name) {
+        return Err(connection_exists(name));
+    }
+    let dbx = DBX::new(config, db_url).await?;
+    if let Err(dbx) = state.connections.insert(name, dbx) {
+        dbx.disconnect().await.ok();
+        return Err(connection_exists(name));
+    }
+    Ok(HttpResponse::Ok().body(format!("Connected to database as {}", name)))
+}
+
+#[get("/connections")]
+async fn list_connections(state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(state.connections.list())
+}
+
+#[post("/connections/{name}")]
+async fn create_connection(
+    path: web::Path<String>,
+    body: web::Json<Connect>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    validate_name(&path)?;
+    let config = PoolConfig {
+        max_connections: body.max_connections,
+        acquire_timeout: body.acquire_timeout.map(|s| Duration::from_secs(s.into())),
+        idle_timeout: body.idle_timeout.map(|s| Duration::from_secs(s.into())),
     };
-    HttpResponse::InternalServerError().body("Failed to lock dbx")
-}
-
-#[delete("/disconnect")]
-async fn disconnect(state: web::Data<AppState>) -> impl Responder {
-    let dbx = state.dbx.lock().ok();
-    if let Some(mut mtx_dbx) = dbx {
-        if let Some(dbx) = mtx_dbx.as_mut() {
-            match dbx.disconnect().await {
-                Ok(_) => {
-                    *mtx_dbx = None;
-                    return HttpResponse::Ok().body("Disconnected from database");
-                }
-                Err(_) => {
-                    return HttpResponse::InternalServerError()
-                        .body("Failed to disconnect from database");
-                }
-            }
-        }
-        return HttpResponse::Ok().body("You're not connected to database");
+    open_connection(&state, &path, &config, &body.db_url).await
+}
+
+#[get("/connections/{name}")]
+async fn test_connection(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    connection(&state, &path)?.raw("select 1").await?;
+    Ok(HttpResponse::Ok().body("Connected to database"))
+}
+
+#[delete("/connections/{name}")]
+async fn drop_connection(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let dbx = state.connections.remove(&path).ok_or_else(|| {
+        ApiError::not_found(
+            "connection_not_found",
+            format!("Connection {} not found", path),
+        )
+    })?;
+    dbx.disconnect().await?;
+    Ok(HttpResponse::Ok().body("Disconnected from database"))
+}
+
+#[get("/profiles")]
+async fn list_profiles(state: web::Data<AppState>) -> Result<HttpResponse, ApiError> {
+    let profiles = profiles::list(&state.sqlite_pool).await?;
+    Ok(HttpResponse::Ok().json(profiles))
+}
+
+#[put("/profiles/{name}")]
+async fn save_profile(
+    path: web::Path<String>,
+    body: web::Json<SaveProfile>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    validate_name(&path)?;
+    let body = body.into_inner();
+    let profile = Profile {
+        name: path.into_inner(),
+        label: body.label,
+        db_url: body.db_url,
+        max_connections: body.max_connections,
+        acquire_timeout: body.acquire_timeout,
+        idle_timeout: body.idle_timeout,
+        is_default: body.is_default,
+    };
+    profiles::save(&state.sqlite_pool, &state.secret_key, &profile).await?;
+    Ok(HttpResponse::Ok().body(format!("Saved profile {}", profile.name)))
+}
+
+#[delete("/profiles/{name}")]
+async fn delete_profile(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    if !profiles::remove(&state.sqlite_pool, &path).await? {
+        return Err(profile_not_found(&path));
+    }
+    Ok(HttpResponse::Ok().body(format!("Deleted profile {}", path)))
+}
+
+/// Opens the saved profile as a connection of the same name.
+#[post("/profiles/{name}/connect")]
+async fn connect_profile(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let profile = profiles::find(&state.sqlite_pool, &state.secret_key, &path)
+        .await?
+        .ok_or_else(|| profile_not_found(&path))?;
+    open_connection(
+        &state,
+        &profile.name,
+        &profile.pool_config(),
+        &profile.db_url,
+    )
+    .await
+}
+
+#[get("/{connection}/rules")]
+async fn list_rules(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let rules = rules::list(&state.sqlite_pool, &path).await?;
+    Ok(HttpResponse::Ok().json(rules))
+}
+
+#[get("/{connection}/rules/{collection}")]
+async fn get_rules(
+    path: web::Path<(String, String)>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let rules = rules::find(&state.sqlite_pool, &path.0, &path.1)
+        .await?
+        .ok_or_else(|| rules_not_found(&path.1))?;
+    Ok(HttpResponse::Ok().json(rules))
+}
+
+#[put("/{connection}/rules/{collection}")]
+async fn save_rules(
+    path: web::Path<(String, String)>,
+    body: web::Json<Rules>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    body.validate()?;
+    rules::save(&state.sqlite_pool, &path.0, &path.1, &body).await?;
+    Ok(HttpResponse::Ok().json(body.into_inner()))
+}
+
+#[delete("/{connection}/rules/{collection}")]
+async fn delete_rules(
+    path: web::Path<(String, String)>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    if !rules::remove(&state.sqlite_pool, &path.0, &path.1).await? {
+        return Err(rules_not_found(&path.1));
     }
-    HttpResponse::InternalServerError().body("Failed to lock dbx")
-}
-
-#[put("/introspect")]
-async fn introspect(state: web::Data<AppState>) -> impl Responder {
-    let state_pg_pool = state.dbx.lock().ok();
-    if let Some(pg_pool) = state_pg_pool {
-        if let Some(dbx) = pg_pool.as_ref() {
-            let tables = sqlx::query_as::<_, Table>(
-                r#"select t.table_name as name from information_schema.tables t
-                where t.table_schema = 'public'
-                and t.table_catalog = 'db_las'
-                and t.table_type = 'BASE TABLE'"#,
-            )
-            .fetch_all(&dbx.pool)
-            .await;
-            if let Ok(tables) = tables {
-                let mut tables_with_columns: Vec<VColumn> = Vec::new();
-                for table in tables {
-                    let columns = sqlx::query_as::<_, VColumn>(
-                        r#"select t.column_name as name, t.data_type, t.is_nullable, t.column_default as default_value, t.character_maximum_length as maximum_length from information_schema.columns t
-                        where t.table_schema = 'public'
-                        and t.table_catalog = 'db_las'
-                        and t.table_name = $1"#,
-                    )
-                    .bind(table.name)
-                    .fetch_all(&dbx.pool)
-                    .await;
-                    if let Ok(columns) = columns {
-                        tables_with_columns.extend(columns);
-                    }
-                }
-                return HttpResponse::Ok().json(tables_with_columns);
-            }
-            return HttpResponse::InternalServerError().body("Failed to introspect database");
-        }
-        return HttpResponse::InternalServerError().body("Not connected to database");
+    Ok(HttpResponse::Ok().body(format!("Deleted rules of collection {}", path.1)))
+}
+
+#[derive(Serialize, Deserialize)]
+struct SearchColumns {
+    columns: Vec<String>,
+}
+
+#[get("/{connection}/search/{collection}")]
+async fn get_search(
+    path: web::Path<(String, String)>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let columns = search::find(&state.sqlite_pool, &path.0, &path.1)
+        .await?
+        .ok_or_else(|| search_not_found(&path.1))?;
+    Ok(HttpResponse::Ok().json(SearchColumns { columns }))
+}
+
+/// Sets the columns `search=` looks in, instead of all text columns.
+#[put("/{connection}/search/{collection}")]
+async fn save_search(
+    path: web::Path<(String, String)>,
+    body: web::Json<SearchColumns>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    if body.columns.is_empty() {
+        return Err(ApiError::bad_request(
+            "invalid_body",
+            "Expected at least one column",
+        ));
     }
-    HttpResponse::InternalServerError().body("Failed to lock pool")
-}
-
-#[get("/status")]
-async fn status(state: web::Data<AppState>) -> impl Responder {
-    let state_pg_pool = state.dbx.lock().ok();
-    if let Some(pg_pool) = state_pg_pool {
-        if let Some(dbx) = pg_pool.as_ref() {
-            let status = dbx.raw("select 1").await;
-            if let Ok(_) = status {
-                return HttpResponse::Ok().body("Connected to database");
-            }
-            return HttpResponse::InternalServerError().body("Failed to connect to database");
-        }
-        return HttpResponse::InternalServerError().body("Not connected to database");
+    let dbx = connection(&state, &path.0)?;
+    dbx.search(&path.1, "", &body.columns).await?;
+    search::save(&state.sqlite_pool, &path.0, &path.1, &body.columns).await?;
+    Ok(HttpResponse::Ok().json(body.into_inner()))
+}
+
+#[delete("/{connection}/search/{collection}")]
+async fn delete_search(
+    path: web::Path<(String, String)>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    if !search::remove(&state.sqlite_pool, &path.0, &path.1).await? {
+        return Err(search_not_found(&path.1));
     }
-    HttpResponse::InternalServerError().body("Failed to lock pool")
+    Ok(HttpResponse::Ok().body(format!("Deleted search columns of collection {}", path.1)))
+}
+
+#[derive(Deserialize)]
+struct Introspect {
+    /// Comma-separated schemas, or `*` for all of them.
+    schemas: Option<String>,
+}
+
+#[put("/{connection}/introspect")]
+async fn introspect(
+    path: web::Path<String>,
+    query: web::Query<Introspect>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let schemas: Vec<&str> = query
+        .schemas
+        .iter()
+        .flat_map(|schemas| schemas.split(','))
+        .map(str::trim)
+        .filter(|schema| !schema.is_empty())
+        .collect();
+    let database = connection(&state, &path)?.introspect(&schemas).await?;
+    Ok(HttpResponse::Ok().json(database))
 }
 
 #[derive(Deserialize)]
 struct Query {
     query: String,
+    #[serde(default)]
+    shape: Shape,
+    /// Streams the rows instead of returning them at once.
+    stream: Option<StreamFormat>,
 }
 
-#[post("/select")]
-async fn select(body: web::Json<Query>, state: web::Data<AppState>) -> impl Responder {
-    let state_pg_pool = state.dbx.lock().ok();
-    let query = body.query.clone();
-    if let Some(pg_pool) = state_pg_pool {
-        if let Some(dbx) = pg_pool.as_ref() {
-            let users = dbx.raw(&query).await;
-            if let Ok(user) = users {
-                return HttpResponse::Ok().json(user);
-            }
-            return HttpResponse::InternalServerError().body("Failed to connect to database");
-        }
-        return HttpResponse::InternalServerError().body("Not connected to database");
+#[post("/{connection}/select")]
+async fn select(
+    path: web::Path<String>,
+    body: web::Json<Query>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let dbx = connection(&state, &path)?;
+    if let Some(format) = body.stream {
+        return stream::respond(dbx.raw_stream(body.into_inner().query), format).await;
+    }
+    let rows = dbx.raw(&body.query).await?;
+    match body.shape {
+        Shape::Objects => Ok(HttpResponse::Ok().json(rows)),
+        Shape::Arrays => Ok(HttpResponse::Ok().json(Table::new(rows))),
     }
-    HttpResponse::InternalServerError().body("Failed to lock pool")
 }
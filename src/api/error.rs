@@ -0,0 +1,275 @@
+use std::fmt;
+
+use actix_web::{http::StatusCode, HttpResponse, ResponseError};
+use serde_json::{json, Value};
+use sqlx::mysql::MySqlDatabaseError;
+
+use crate::internal::auth::AuthError;
+use crate::internal::db::DBXError;
+use crate::internal::filter::FilterError;
+use crate::internal::profiles::ProfileError;
+use crate::internal::rules::RuleError;
+
+/// Error returned by every API handler, rendered as
+/// `{"code": ..., "message": ..., "details": ...}`.
+///
+/// `code` is stable and meant for clients to branch on; `message` is for
+/// humans and may change.
+#[derive(Debug)]
+pub struct ApiError {
+    status: StatusCode,
+    code: &'static str,
+    message: String,
+    details: Option<Value>,
+}
+
+impl ApiError {
+    pub fn new(status: StatusCode, code: &'static str, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            code,
+            message: message.into(),
+            details: None,
+        }
+    }
+
+    pub fn with_details(mut self, details: Value) -> Self {
+        self.details = Some(details);
+        self
+    }
+
+    pub fn bad_request(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, code, message)
+    }
+
+    pub fn unauthorized(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::UNAUTHORIZED, code, message)
+    }
+
+    pub fn forbidden(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::FORBIDDEN, code, message)
+    }
+
+    pub fn not_found(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::NOT_FOUND, code, message)
+    }
+
+    pub fn conflict(code: &'static str, message: impl Into<String>) -> Self {
+        Self::new(StatusCode::CONFLICT, code, message)
+    }
+
+    pub fn internal(message: impl Into<String>) -> Self {
+        Self::new(StatusCode::INTERNAL_SERVER_ERROR, "internal_error", message)
+    }
+}
+
+impl fmt::Display for ApiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl ResponseError for ApiError {
+    fn status_code(&self) -> StatusCode {
+        self.status
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        HttpResponse::build(self.status).json(json!({
+            "code": self.code,
+            "message": self.message,
+            "details": self.details,
+        }))
+    }
+}
+
+impl From<sqlx::Error> for ApiError {
+    fn from(e: sqlx::Error) -> Self {
+        match e {
+            sqlx::Error::RowNotFound => ApiError::not_found("not_found", "Record not found"),
+            sqlx::Error::Database(ref db_error) => database_error(db_error.as_ref()),
+            sqlx::Error::PoolTimedOut | sqlx::Error::PoolClosed => ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "database_unavailable",
+                e.to_string(),
+            ),
+            sqlx::Error::Io(_) | sqlx::Error::Tls(_) => ApiError::new(
+                StatusCode::SERVICE_UNAVAILABLE,
+                "database_unavailable",
+                e.to_string(),
+            ),
+            sqlx::Error::Configuration(_) => {
+                ApiError::bad_request("invalid_configuration", e.to_string())
+            }
+            sqlx::Error::ColumnDecode { .. } | sqlx::Error::Decode(_) => {
+                ApiError::internal(e.to_string())
+            }
+            _ => ApiError::new(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "database_error",
+                e.to_string(),
+            ),
+        }
+    }
+}
+
+/// Database error categories, whatever backend reported them.
+enum Violation {
+    Unique,
+    ForeignKey,
+    NotNull,
+    Check,
+    InvalidQuery,
+    InvalidValue,
+    Other,
+}
+
+fn database_error(db_error: &dyn sqlx::error::DatabaseError) -> ApiError {
+    let code = db_error.code().map(|code| code.into_owned());
+    // MySQL reports most integrity errors as SQLSTATE 23000, so its error
+    // numbers are more telling.
+    let violation = match db_error.try_downcast_ref::<MySqlDatabaseError>() {
+        Some(mysql_error) => match mysql_error.number() {
+            1062 | 1586 => Violation::Unique,
+            1216 | 1217 | 1451 | 1452 => Violation::ForeignKey,
+            1048 | 1364 => Violation::NotNull,
+            3819 => Violation::Check,
+            1054 | 1064 | 1146 | 1149 => Violation::InvalidQuery,
+            1264 | 1292 | 1366 | 1406 => Violation::InvalidValue,
+            _ => Violation::Other,
+        },
+        None => match code.as_deref() {
+            // PostgreSQL SQLSTATEs, then SQLite extended result codes.
+            Some("23505") | Some("2067") | Some("1555") => Violation::Unique,
+            Some("23503") | Some("787") => Violation::ForeignKey,
+            Some("23502") | Some("1299") => Violation::NotNull,
+            Some("23514") | Some("275") => Violation::Check,
+            Some(code) if code.starts_with("42") || code == "1" => Violation::InvalidQuery,
+            Some(code) if code.starts_with("22") || code == "20" => Violation::InvalidValue,
+            _ => Violation::Other,
+        },
+    };
+    let error = match violation {
+        Violation::Unique => ApiError::conflict("unique_violation", db_error.message()),
+        Violation::ForeignKey => ApiError::conflict("foreign_key_violation", db_error.message()),
+        Violation::NotNull => ApiError::bad_request("not_null_violation", db_error.message()),
+        Violation::Check => ApiError::bad_request("check_violation", db_error.message()),
+        Violation::InvalidQuery => ApiError::bad_request("invalid_query", db_error.message()),
+        Violation::InvalidValue => ApiError::bad_request("invalid_value", db_error.message()),
+        Violation::Other => ApiError::new(
+            StatusCode::INTERNAL_SERVER_ERROR,
+            "database_error",
+            db_error.message(),
+        ),
+    };
+    error.with_details(json!({
+        "database_code": code,
+        "constraint": db_error.constraint(),
+    }))
+}
+
+impl From<FilterError> for ApiError {
+    fn from(e: FilterError) -> Self {
+        let details = match &e {
+            FilterError::Syntax { position, .. } => json!({ "position": position }),
+            FilterError::UnknownColumn(column) | FilterError::InvalidValue { column, .. } => {
+                json!({ "column": column })
+            }
+            FilterError::UnknownVariable(name) => json!({ "variable": name }),
+        };
+        ApiError::bad_request("invalid_filter", e.to_string()).with_details(details)
+    }
+}
+
+impl From<DBXError> for ApiError {
+    fn from(e: DBXError) -> Self {
+        match e {
+            DBXError::Sqlx(e) => e.into(),
+            DBXError::Filter(e) => e.into(),
+            DBXError::UnknownSchema(ref schema) => {
+                ApiError::not_found("unknown_schema", e.to_string())
+                    .with_details(json!({ "schema": schema }))
+            }
+            DBXError::UnknownTable(ref table) => {
+                ApiError::bad_request("unknown_collection", e.to_string())
+                    .with_details(json!({ "collection": table }))
+            }
+            DBXError::UnknownColumn(ref column) => {
+                ApiError::bad_request("unknown_column", e.to_string())
+                    .with_details(json!({ "column": column }))
+            }
+            DBXError::InvalidValue(ref value) => {
+                ApiError::bad_request("invalid_value", e.to_string())
+                    .with_details(json!({ "column": value.column }))
+            }
+            DBXError::NoPrimaryKey(ref table) => {
+                ApiError::bad_request("no_primary_key", e.to_string())
+                    .with_details(json!({ "collection": table }))
+            }
+            DBXError::EmptyRecord => ApiError::bad_request("empty_record", e.to_string()),
+            DBXError::CheckFailed => ApiError::forbidden("rule_violation", e.to_string()),
+            DBXError::InvalidDefinition(_) => {
+                ApiError::bad_request("invalid_definition", e.to_string())
+            }
+            DBXError::UnknownRelation(ref relation) => {
+                ApiError::bad_request("unknown_relation", e.to_string())
+                    .with_details(json!({ "relation": relation }))
+            }
+            DBXError::ExpandTooDeep(ref path) => {
+                ApiError::bad_request("expand_too_deep", e.to_string())
+                    .with_details(json!({ "relation": path }))
+            }
+            DBXError::InvalidPagination(_) => {
+                ApiError::bad_request("invalid_pagination", e.to_string())
+            }
+            DBXError::InvalidSort(_) => ApiError::bad_request("invalid_sort", e.to_string()),
+            DBXError::InvalidField(ref field) => {
+                ApiError::bad_request("invalid_field", e.to_string())
+                    .with_details(json!({ "field": field }))
+            }
+            DBXError::InvalidAggregate(_) => {
+                ApiError::bad_request("invalid_aggregate", e.to_string())
+            }
+            DBXError::NotSearchable(ref table) => {
+                ApiError::bad_request("not_searchable", e.to_string())
+                    .with_details(json!({ "collection": table }))
+            }
+        }
+    }
+}
+
+impl From<AuthError> for ApiError {
+    fn from(e: AuthError) -> Self {
+        match e {
+            AuthError::Sqlx(e) => e.into(),
+            AuthError::Hash(_) | AuthError::LegacyUsers(_) => ApiError::internal(e.to_string()),
+            AuthError::UserExists => ApiError::conflict("user_exists", e.to_string()),
+            AuthError::InvalidCredentials => {
+                ApiError::unauthorized("invalid_credentials", e.to_string())
+            }
+            AuthError::InvalidToken => ApiError::unauthorized("invalid_token", e.to_string()),
+        }
+    }
+}
+
+impl From<ProfileError> for ApiError {
+    fn from(e: ProfileError) -> Self {
+        match e {
+            ProfileError::Sqlx(e) => e.into(),
+            ProfileError::Secret(_) => ApiError::internal(e.to_string()),
+            ProfileError::UnsupportedUrl => {
+                ApiError::bad_request("unsupported_database", e.to_string())
+            }
+        }
+    }
+}
+
+impl From<RuleError> for ApiError {
+    fn from(e: RuleError) -> Self {
+        match e {
+            RuleError::Sqlx(e) => e.into(),
+            // Rules are validated when saved, so this is a server-side problem.
+            RuleError::Filter(_) => ApiError::internal(e.to_string()),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::rc::Rc;
+
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::{web, Error, FromRequest, HttpMessage, HttpRequest};
+use serde::Deserialize;
+
+use crate::api::auth::{bearer_token, missing_token};
+use crate::api::error::ApiError;
+use crate::internal::auth::{self, AuthError, User};
+use crate::internal::temporal::TemporalFormat;
+use crate::AppState;
+
+type LocalBoxFuture<T> = Pin<Box<dyn Future<Output = T>>>;
+
+/// Rejects requests without a valid session token and makes the session's
+/// [`User`] available to handlers.
+pub struct Authentication {
+    admin_only: bool,
+}
+
+impl Authentication {
+    pub fn user() -> Self {
+        Self { admin_only: false }
+    }
+
+    pub fn admin() -> Self {
+        Self { admin_only: true }
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for Authentication
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = AuthenticationMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(AuthenticationMiddleware {
+            service: Rc::new(service),
+            admin_only: self.admin_only,
+        }))
+    }
+}
+
+pub struct AuthenticationMiddleware<S> {
+    service: Rc<S>,
+    admin_only: bool,
+}
+
+impl<S, B> Service<ServiceRequest> for AuthenticationMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        let admin_only = self.admin_only;
+        Box::pin(async move {
+            let user = authenticate(req.request()).await?;
+            if admin_only && !user.is_admin {
+                return Err(ApiError::forbidden("admin_required", "Admin access required").into());
+            }
+            req.extensions_mut().insert(user);
+            service.call(req).await
+        })
+    }
+}
+
+/// Handlers behind [`Authentication`] get the user it resolved; elsewhere the
+/// bearer token is checked on demand.
+impl FromRequest for User {
+    type Error = Error;
+    type Future = LocalBoxFuture<Result<Self, Self::Error>>;
+
+    fn from_request(req: &HttpRequest, _: &mut Payload) -> Self::Future {
+        if let Some(user) = req.extensions().get::<User>() {
+            return Box::pin(ready(Ok(user.clone())));
+        }
+        let req = req.clone();
+        Box::pin(async move { authenticate(&req).await })
+    }
+}
+
+async fn authenticate(req: &HttpRequest) -> Result<User, Error> {
+    let token = bearer_token(req).ok_or_else(missing_token)?;
+    let state = req
+        .app_data::<web::Data<AppState>>()
+        .ok_or_else(|| ApiError::internal("Application state is not configured"))?;
+    match auth::authenticate(&state.sqlite_pool, token).await {
+        Ok(Some(user)) => Ok(user),
+        Ok(None) => Err(ApiError::from(AuthError::InvalidToken).into()),
+        Err(e) => Err(ApiError::from(e).into()),
+    }
+}
+
+/// Reads and writes timestamps in the `temporal_format` of the query string,
+/// or else the server's.
+pub struct Temporal;
+
+impl<S, B> Transform<S, ServiceRequest> for Temporal
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Transform = TemporalMiddleware<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(TemporalMiddleware {
+            service: Rc::new(service),
+        }))
+    }
+}
+
+pub struct TemporalMiddleware<S> {
+    service: Rc<S>,
+}
+
+#[derive(Deserialize)]
+struct TemporalQuery {
+    temporal_format: Option<String>,
+}
+
+impl<S, B> Service<ServiceRequest> for TemporalMiddleware<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error> + 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<B>;
+    type Error = Error;
+    type Future = LocalBoxFuture<Result<Self::Response, Self::Error>>;
+
+    forward_ready!(service);
+
+    fn call(&self, req: ServiceRequest) -> Self::Future {
+        let service = Rc::clone(&self.service);
+        Box::pin(async move {
+            let format = temporal_format(req.request())?;
+            format.scope(service.call(req)).await
+        })
+    }
+}
+
+fn temporal_format(req: &HttpRequest) -> Result<TemporalFormat, Error> {
+    let query = web::Query::<TemporalQuery>::from_query(req.query_string())
+        .map_err(|e| ApiError::bad_request("invalid_query_string", e.to_string()))?;
+    match query.into_inner().temporal_format {
+        Some(format) => {
+            TemporalFormat::parse(&format).ok_or_else(|| invalid_temporal_format().into())
+        }
+        None => Ok(req
+            .app_data::<web::Data<AppState>>()
+            .map(|state| state.temporal_format())
+            .unwrap_or_default()),
+    }
+}
+
+pub fn invalid_temporal_format() -> ApiError {
+    ApiError::bad_request(
+        "invalid_temporal_format",
+        "Expected epoch_seconds, epoch_millis, epoch_micros or rfc3339",
+    )
+}
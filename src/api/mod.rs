@@ -1,33 +1,105 @@
 mod auth;
 mod collection;
 mod db;
+mod error;
+mod middleware;
+mod schema;
+mod settings;
+mod stream;
 
-use actix_web::web;
+use actix_web::{web, HttpRequest};
+
+use crate::api::error::ApiError;
+use crate::api::middleware::{Authentication, Temporal};
+use crate::internal::db::DBX;
+use crate::AppState;
 
 pub fn init_routes(cfg: &mut web::ServiceConfig) {
+    // Malformed bodies, queries and paths get the same JSON errors as
+    // everything else.
+    cfg.app_data(
+        web::JsonConfig::default().error_handler(|e, _: &HttpRequest| {
+            ApiError::bad_request("invalid_body", e.to_string()).into()
+        }),
+    );
+    cfg.app_data(
+        web::QueryConfig::default().error_handler(|e, _: &HttpRequest| {
+            ApiError::bad_request("invalid_query_string", e.to_string()).into()
+        }),
+    );
+    cfg.app_data(
+        web::PathConfig::default().error_handler(|e, _: &HttpRequest| {
+            ApiError::bad_request("invalid_path", e.to_string()).into()
+        }),
+    );
     cfg.service(
         web::scope("/db")
+            .wrap(Temporal)
+            .wrap(Authentication::admin())
+            .service(db::list_connections)
+            .service(db::create_connection)
+            .service(db::test_connection)
+            .service(db::drop_connection)
+            .service(db::list_profiles)
+            .service(db::save_profile)
+            .service(db::delete_profile)
+            .service(db::connect_profile)
+            .service(db::list_rules)
+            .service(db::get_rules)
+            .service(db::save_rules)
+            .service(db::delete_rules)
+            .service(db::get_search)
+            .service(db::save_search)
+            .service(db::delete_search)
             .service(db::introspect)
-            .service(db::connect)
-            .service(db::disconnect)
-            .service(db::select),
+            .service(db::select)
+            .service(schema::create_table)
+            .service(schema::drop_table)
+            .service(schema::add_column)
+            .service(schema::rename_column)
+            .service(schema::drop_column)
+            .service(schema::diff)
+            .service(schema::list_migrations),
     );
     cfg.service(
-        web::scope("/collection")
-            .service(collection::get_all)
-            .service(collection::get)
-            .service(collection::get_by_field)
-            .service(collection::create)
-            .service(collection::create_batch)
-            .service(collection::update)
-            .service(collection::update_batch)
-            .service(collection::delete)
-            .service(collection::delete_batch),
+        web::scope("/settings")
+            .wrap(Authentication::admin())
+            .service(settings::get_settings)
+            .service(settings::save_setting)
+            .service(settings::delete_setting),
     );
     cfg.service(
         web::scope("/auth")
             .service(auth::login)
+            .service(auth::refresh)
             .service(auth::logout)
+            .service(auth::me)
             .service(auth::register),
     );
+    cfg.service(
+        web::scope("/{connection}/collection")
+            .wrap(Temporal)
+            .wrap(Authentication::user())
+            .service(collection::get_all)
+            // Paths under `_` are reserved for these, not taken as ids.
+            .service(collection::aggregate)
+            .service(collection::export)
+            .service(collection::get)
+            .service(collection::get_by_field)
+            .service(collection::create)
+            .service(collection::create_batch)
+            .service(collection::update_batch)
+            .service(collection::update)
+            .service(collection::delete_batch)
+            .service(collection::delete),
+    );
+}
+
+fn connection(state: &AppState, name: &str) -> Result<DBX, ApiError> {
+    state.connections.get(name).ok_or_else(|| {
+        ApiError::not_found(
+            "connection_not_found",
+            format!("Connection {} not found", name),
+        )
+    })
 }
@@ -0,0 +1,128 @@
+use actix_web::{delete, get, post, put, web, HttpResponse};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::api::{connection, error::ApiError};
+use crate::internal::migrations;
+use crate::models::schema::{ForeignKey, Schema, Table, VColumn};
+use crate::AppState;
+
+#[derive(Deserialize)]
+struct AddColumn {
+    #[serde(flatten)]
+    column: VColumn,
+    foreign_key: Option<ForeignKey>,
+}
+
+#[derive(Deserialize)]
+struct RenameColumn {
+    name: String,
+}
+
+#[derive(Deserialize)]
+struct Diff {
+    #[serde(default)]
+    drop: bool,
+    #[serde(default)]
+    apply: bool,
+}
+
+/// Records the DDL run against `connection` and responds with it.
+async fn executed(
+    state: &AppState,
+    connection: &str,
+    statements: Vec<String>,
+) -> Result<HttpResponse, ApiError> {
+    migrations::record(&state.sqlite_pool, connection, &statements).await?;
+    Ok(HttpResponse::Ok().json(json!({ "ddl": statements })))
+}
+
+#[post("/{connection}/schema/tables")]
+async fn create_table(
+    path: web::Path<String>,
+    body: web::Json<Table>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let statements = connection(&state, &path)?.create_table(&body).await?;
+    executed(&state, &path, statements).await
+}
+
+#[delete("/{connection}/schema/tables/{table}")]
+async fn drop_table(
+    path: web::Path<(String, String)>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let statements = connection(&state, &path.0)?.drop_table(&path.1).await?;
+    executed(&state, &path.0, statements).await
+}
+
+#[post("/{connection}/schema/tables/{table}/columns")]
+async fn add_column(
+    path: web::Path<(String, String)>,
+    body: web::Json<AddColumn>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let statements = connection(&state, &path.0)?
+        .add_column(&path.1, &body.column, body.foreign_key.as_ref())
+        .await?;
+    executed(&state, &path.0, statements).await
+}
+
+#[put("/{connection}/schema/tables/{table}/columns/{column}")]
+async fn rename_column(
+    path: web::Path<(String, String, String)>,
+    body: web::Json<RenameColumn>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let statements = connection(&state, &path.0)?
+        .rename_column(&path.1, &path.2, &body.name)
+        .await?;
+    executed(&state, &path.0, statements).await
+}
+
+#[delete("/{connection}/schema/tables/{table}/columns/{column}")]
+async fn drop_column(
+    path: web::Path<(String, String, String)>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let statements = connection(&state, &path.0)?
+        .drop_column(&path.1, &path.2)
+        .await?;
+    executed(&state, &path.0, statements).await
+}
+
+/// Diffs the posted schema against the live one. Tables and columns left
+/// out of it are only dropped with `drop`. Dry-run unless `apply`, which
+/// runs the DDL in one transaction and records it as a migration.
+#[post("/{connection}/schema/diff")]
+async fn diff(
+    path: web::Path<String>,
+    query: web::Query<Diff>,
+    body: web::Json<Schema>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    let (diff, statements) = connection(&state, &path)?
+        .migrate(&body, query.drop, query.apply)
+        .await?;
+    let applied = query.apply && !statements.is_empty();
+    let migration = match applied {
+        true => Some(migrations::record(&state.sqlite_pool, &path, &statements).await?),
+        false => None,
+    };
+    Ok(HttpResponse::Ok().json(json!({
+        "diff": diff,
+        "ddl": statements,
+        "applied": applied,
+        "migration": migration,
+    })))
+}
+
+#[get("/{connection}/schema/migrations")]
+async fn list_migrations(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    connection(&state, &path)?;
+    let migrations = migrations::list(&state.sqlite_pool, &path).await?;
+    Ok(HttpResponse::Ok().json(migrations))
+}
@@ -0,0 +1,70 @@
+use actix_web::{delete, get, put, web, HttpResponse};
+use serde::{Deserialize, Serialize};
+
+use crate::api::error::ApiError;
+use crate::api::middleware::invalid_temporal_format;
+use crate::internal::settings;
+use crate::internal::temporal::TemporalFormat;
+use crate::AppState;
+
+/// The settings in effect.
+#[derive(Serialize)]
+struct Settings {
+    temporal_format: &'static str,
+}
+
+impl Settings {
+    fn of(state: &AppState) -> Self {
+        Settings {
+            temporal_format: state.temporal_format().name(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct SaveSetting {
+    value: String,
+}
+
+fn setting_not_found(key: &str) -> ApiError {
+    ApiError::not_found("setting_not_found", format!("Setting {} is not found", key))
+}
+
+#[get("")]
+async fn get_settings(state: web::Data<AppState>) -> HttpResponse {
+    HttpResponse::Ok().json(Settings::of(&state))
+}
+
+#[put("/{key}")]
+async fn save_setting(
+    path: web::Path<String>,
+    body: web::Json<SaveSetting>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    match path.as_str() {
+        settings::TEMPORAL_FORMAT => {
+            let format = TemporalFormat::parse(&body.value).ok_or_else(invalid_temporal_format)?;
+            settings::save(&state.sqlite_pool, &path, &body.value).await?;
+            state.set_temporal_format(format);
+        }
+        key => return Err(setting_not_found(key)),
+    }
+    Ok(HttpResponse::Ok().json(Settings::of(&state)))
+}
+
+/// Resets a setting to its default.
+#[delete("/{key}")]
+async fn delete_setting(
+    path: web::Path<String>,
+    state: web::Data<AppState>,
+) -> Result<HttpResponse, ApiError> {
+    match path.as_str() {
+        settings::TEMPORAL_FORMAT => {
+            let format = TemporalFormat::from_env().map_err(ApiError::internal)?;
+            settings::remove(&state.sqlite_pool, &path).await?;
+            state.set_temporal_format(format);
+        }
+        key => return Err(setting_not_found(key)),
+    }
+    Ok(HttpResponse::Ok().json(Settings::of(&state)))
+}
@@ -0,0 +1,119 @@
+use actix_web::{
+    http::header::{ContentDisposition, DispositionParam, DispositionType},
+    web::Bytes,
+    HttpResponse,
+};
+use futures_util::{future, stream, StreamExt};
+use serde::Deserialize;
+
+use crate::api::error::ApiError;
+use crate::internal::export::{ExportColumn, ExportFormat};
+use crate::internal::stream::RowStream;
+
+/// How a streamed response writes its rows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StreamFormat {
+    /// One JSON object per line.
+    Ndjson,
+    /// A JSON array, written a row at a time.
+    Json,
+}
+
+/// Writes `rows` as they arrive. A failure before the first row is an error
+/// response; a later one cuts the body short, leaving it incomplete.
+pub async fn respond(mut rows: RowStream, format: StreamFormat) -> Result<HttpResponse, ApiError> {
+    let first = rows.next().await.transpose()?;
+    let content_type = match format {
+        StreamFormat::Ndjson => "application/x-ndjson",
+        StreamFormat::Json => "application/json",
+    };
+    let first = match first {
+        Some(first) => first,
+        None => {
+            let empty = if format == StreamFormat::Json {
+                "[]"
+            } else {
+                ""
+            };
+            return Ok(HttpResponse::Ok().content_type(content_type).body(empty));
+        }
+    };
+    let body = stream::once(async { Ok(first) })
+        .chain(rows)
+        .enumerate()
+        .map(move |(i, row)| {
+            let row = row.map_err(|e| {
+                log::error!("Streaming rows failed: {}", e);
+                e
+            })?;
+            let mut bytes = Vec::new();
+            if format == StreamFormat::Json {
+                bytes.push(if i == 0 { b'[' } else { b',' });
+            }
+            serde_json::to_writer(&mut bytes, &row)?;
+            if format == StreamFormat::Ndjson {
+                bytes.push(b'\n');
+            }
+            Ok::<_, Box<dyn std::error::Error>>(Bytes::from(bytes))
+        })
+        .chain(stream::once(async move {
+            let end: &[u8] = if format == StreamFormat::Json {
+                b"]"
+            } else {
+                b""
+            };
+            Ok(Bytes::from_static(end))
+        }));
+    Ok(HttpResponse::Ok()
+        .content_type(content_type)
+        .streaming(body))
+}
+
+/// Writes `rows` as a `format` file downloaded as `name`, encoding them as
+/// they arrive. Failures are handled as in `respond`.
+pub async fn download(
+    mut rows: RowStream,
+    format: ExportFormat,
+    columns: Vec<ExportColumn>,
+    name: &str,
+) -> Result<HttpResponse, ApiError> {
+    let first = rows.next().await.transpose()?;
+    let rows = match first {
+        Some(first) => stream::iter([Ok(first)]).chain(rows).left_stream(),
+        None => stream::empty().right_stream(),
+    };
+    let encoder = Some(format.encoder(columns));
+    let body = stream::unfold((rows, encoder), |(mut rows, encoder)| async move {
+        let mut encoder = encoder?;
+        let chunk: Result<Vec<u8>, Box<dyn std::error::Error>> = match rows.next().await {
+            Some(Ok(row)) => encoder.row(&row).map_err(Into::into),
+            Some(Err(e)) => Err(e.into()),
+            None => {
+                let end = encoder.finish().map_err(Into::into);
+                return Some((end, (rows, None)));
+            }
+        };
+        let encoder = chunk.is_ok().then_some(encoder);
+        Some((chunk, (rows, encoder)))
+    })
+    .filter(|chunk| future::ready(!matches!(chunk, Ok(chunk) if chunk.is_empty())))
+    .map(|chunk| {
+        chunk.map(Bytes::from).map_err(|e| {
+            log::error!("Exporting rows failed: {}", e);
+            e
+        })
+    });
+    let disposition = ContentDisposition {
+        disposition: DispositionType::Attachment,
+        parameters: vec![DispositionParam::Filename(format!(
+            "{}.{}",
+            name,
+            format.extension()
+        ))],
+    };
+    Ok(HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(disposition)
+        .streaming(body))
+}
@@ -0,0 +1,172 @@
+use std::collections::HashMap;
+
+use sqlx::QueryBuilder;
+
+use crate::{
+    internal::db::{with_pool, DBXError, Pool, DBX},
+    internal::de::QueryResult,
+    internal::dialect::Dialect,
+    internal::filter::Expr,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Count,
+    Sum,
+    Avg,
+    Min,
+    Max,
+}
+
+/// An aggregate such as `sum(price)`; a bare `count` counts rows.
+#[derive(Debug, Clone)]
+pub struct Metric {
+    pub function: Function,
+    pub column: Option<String>,
+}
+
+pub struct Aggregate {
+    pub table: String,
+    pub metrics: Vec<Metric>,
+    pub group_by: Vec<String>,
+    pub r#where: Option<Expr>,
+}
+
+impl Metric {
+    /// Parses a list such as `count,sum(price),max(created_at)`.
+    pub fn parse(metrics: &str) -> Result<Vec<Metric>, DBXError> {
+        metrics
+            .split(',')
+            .map(str::trim)
+            .filter(|metric| !metric.is_empty())
+            .map(|metric| {
+                let (function, column) = match metric.split_once('(') {
+                    Some((function, rest)) => {
+                        let column = rest.strip_suffix(')').map(str::trim).ok_or_else(|| {
+                            DBXError::InvalidAggregate(format!("Unclosed ( in {}", metric))
+                        })?;
+                        (function.trim(), Some(column.to_string()))
+                    }
+                    None => (metric, None),
+                };
+                let function = match function.to_lowercase().as_str() {
+                    "count" => Function::Count,
+                    "sum" => Function::Sum,
+                    "avg" => Function::Avg,
+                    "min" => Function::Min,
+                    "max" => Function::Max,
+                    _ => {
+                        return Err(DBXError::InvalidAggregate(format!(
+                            "Unknown aggregate function {}",
+                            function
+                        )))
+                    }
+                };
+                match column {
+                    Some(column) if column.is_empty() => Err(DBXError::InvalidAggregate(format!(
+                        "Missing column in {}",
+                        metric
+                    ))),
+                    None if function != Function::Count => Err(DBXError::InvalidAggregate(
+                        format!("{} needs a column, as in {}(column)", metric, metric),
+                    )),
+                    column => Ok(Metric { function, column }),
+                }
+            })
+            .collect()
+    }
+
+    /// The key the result is returned under, e.g. `sum_price`.
+    pub fn name(&self) -> String {
+        let function = match self.function {
+            Function::Count => "count",
+            Function::Sum => "sum",
+            Function::Avg => "avg",
+            Function::Min => "min",
+            Function::Max => "max",
+        };
+        match &self.column {
+            Some(column) => format!("{}_{}", function, column),
+            None => function.to_string(),
+        }
+    }
+
+    fn expression<DB: Dialect>(&self) -> String {
+        let column = match &self.column {
+            Some(column) => DB::quote_ident(column),
+            None => "*".to_string(),
+        };
+        match self.function {
+            Function::Count => format!("count({})", column),
+            Function::Sum => format!("sum({})", column),
+            Function::Avg => DB::average(&column),
+            Function::Min => format!("min({})", column),
+            Function::Max => format!("max({})", column),
+        }
+    }
+}
+
+impl DBX {
+    /// Computes the metrics of `aggregate` over the matching rows, one row
+    /// per group ordered by the grouping columns.
+    pub async fn aggregate(&self, aggregate: &Aggregate) -> Result<Vec<QueryResult>, DBXError> {
+        let columns = self.columns(&aggregate.table)?;
+        let used = aggregate
+            .metrics
+            .iter()
+            .filter_map(|metric| metric.column.as_ref())
+            .chain(&aggregate.group_by);
+        for column in used {
+            if !columns.contains_key(column) {
+                return Err(DBXError::UnknownColumn(column.clone()));
+            }
+        }
+        let rows = with_pool!(&self.pool, pool => {
+            let mut query_builder = QueryBuilder::new("");
+            Self::aggregate_query(aggregate, &columns, &mut query_builder)?;
+            query_builder
+                .build_query_as::<QueryResult>()
+                .fetch_all(pool)
+                .await?
+        });
+        Ok(rows)
+    }
+
+    fn aggregate_query<DB: Dialect>(
+        aggregate: &Aggregate,
+        columns: &HashMap<String, String>,
+        query_builder: &mut QueryBuilder<'_, DB>,
+    ) -> Result<(), DBXError> {
+        let group_by: Vec<String> = aggregate
+            .group_by
+            .iter()
+            .map(|column| DB::quote_ident(column))
+            .collect();
+        let selected: Vec<String> = group_by
+            .iter()
+            .cloned()
+            .chain(aggregate.metrics.iter().map(|metric| {
+                format!(
+                    "{} as {}",
+                    metric.expression::<DB>(),
+                    DB::quote_ident(&metric.name())
+                )
+            }))
+            .collect();
+        query_builder.push("select ");
+        query_builder.push(selected.join(", "));
+        query_builder.push(" from ");
+        query_builder.push(DB::quote_ident(&aggregate.table));
+        if let Some(r#where) = &aggregate.r#where {
+            query_builder.push(" where ");
+            r#where.push(query_builder, columns)?;
+        }
+        if !group_by.is_empty() {
+            query_builder.push(" group by ");
+            query_builder.push(group_by.join(", "));
+            query_builder.push(" order by ");
+            query_builder.push(group_by.join(", "));
+        }
+        Ok(())
+    }
+}
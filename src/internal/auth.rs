@@ -0,0 +1,273 @@
+use std::fmt;
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::Argon2;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use rand::RngCore;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use sqlx::SqlitePool;
+
+/// Lifetime of an access token, in seconds.
+pub const SESSION_TTL: i64 = 60 * 60;
+/// Lifetime of the refresh token issued alongside it, in seconds.
+pub const REFRESH_TTL: i64 = 30 * 24 * 60 * 60;
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct User {
+    pub id: i64,
+    pub username: String,
+    pub is_admin: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct UserRow {
+    id: i64,
+    username: String,
+    password: String,
+    is_admin: bool,
+}
+
+/// Tokens handed to the client. Only their SHA-256 digests are stored, so a
+/// leaked database doesn't leak live sessions.
+#[derive(Serialize)]
+pub struct Session {
+    pub token: String,
+    pub expires_at: i64,
+    pub refresh_token: String,
+    pub refresh_expires_at: i64,
+    pub user: User,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    Sqlx(sqlx::Error),
+    Hash(argon2::password_hash::Error),
+    UserExists,
+    InvalidCredentials,
+    InvalidToken,
+    LegacyUsers(String),
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthError::Sqlx(e) => write!(f, "{}", e),
+            AuthError::Hash(e) => write!(f, "Failed to hash password: {}", e),
+            AuthError::UserExists => write!(f, "User already exists"),
+            AuthError::InvalidCredentials => write!(f, "Invalid username or password"),
+            AuthError::InvalidToken => write!(f, "Invalid or expired token"),
+            AuthError::LegacyUsers(reason) => {
+                write!(f, "Cannot upgrade the existing users table: {}", reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AuthError {}
+
+impl From<sqlx::Error> for AuthError {
+    fn from(e: sqlx::Error) -> Self {
+        AuthError::Sqlx(e)
+    }
+}
+
+impl From<argon2::password_hash::Error> for AuthError {
+    fn from(e: argon2::password_hash::Error) -> Self {
+        AuthError::Hash(e)
+    }
+}
+
+fn hash_password(password: &str) -> Result<String, AuthError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string())
+}
+
+/// Brings a `users` table written by hand before the migrations existed up
+/// to date, before they run and skip it: adds `is_admin`, making the oldest
+/// user the admin as `register` would have, and replaces plain text
+/// passwords with their Argon2id hashes.
+pub async fn upgrade_legacy_users(pool: &SqlitePool) -> Result<(), AuthError> {
+    let columns: Vec<(String,)> = sqlx::query_as("select name from pragma_table_info('users')")
+        .fetch_all(pool)
+        .await?;
+    if columns.is_empty() {
+        return Ok(());
+    }
+    let has = |name: &str| columns.iter().any(|(column,)| column == name);
+    if !has("username") || !has("password") {
+        return Err(AuthError::LegacyUsers(
+            "it has no username and password columns".to_string(),
+        ));
+    }
+    let mut tx = pool.begin().await?;
+    if !has("is_admin") {
+        sqlx::query("alter table users add column is_admin boolean not null default false")
+            .execute(&mut tx)
+            .await?;
+        sqlx::query("update users set is_admin = true where id = (select min(id) from users)")
+            .execute(&mut tx)
+            .await?;
+        log::warn!("Added is_admin to the users table, the oldest user is now an admin");
+    }
+    let plain: Vec<(i64, String)> =
+        sqlx::query_as("select id, password from users where password not like '$argon2%'")
+            .fetch_all(&mut tx)
+            .await?;
+    for (id, password) in &plain {
+        sqlx::query("update users set password = $1 where id = $2")
+            .bind(hash_password(password)?)
+            .bind(id)
+            .execute(&mut tx)
+            .await?;
+    }
+    if !plain.is_empty() {
+        log::warn!(
+            "Hashed {} plain text passwords in the users table",
+            plain.len()
+        );
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Creates a user with an Argon2id hash of `password`. The first user to
+/// register becomes an admin.
+pub async fn register(
+    pool: &SqlitePool,
+    username: &str,
+    password: &str,
+) -> Result<User, AuthError> {
+    let hash = hash_password(password)?;
+    let mut tx = pool.begin().await?;
+    let taken = sqlx::query("select 1 from users where username = $1")
+        .bind(username)
+        .fetch_optional(&mut tx)
+        .await?;
+    if taken.is_some() {
+        return Err(AuthError::UserExists);
+    }
+    let user = sqlx::query_as::<_, User>(
+        "insert into users (username, password, is_admin)
+        values ($1, $2, not exists (select 1 from users))
+        returning id, username, is_admin",
+    )
+    .bind(username)
+    .bind(hash)
+    .fetch_one(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(user)
+}
+
+pub async fn login(
+    pool: &SqlitePool,
+    username: &str,
+    password: &str,
+) -> Result<Session, AuthError> {
+    let row = sqlx::query_as::<_, UserRow>(
+        "select id, username, password, is_admin from users where username = $1",
+    )
+    .bind(username)
+    .fetch_optional(pool)
+    .await?
+    .ok_or(AuthError::InvalidCredentials)?;
+    let hash = PasswordHash::new(&row.password)?;
+    if Argon2::default()
+        .verify_password(password.as_bytes(), &hash)
+        .is_err()
+    {
+        return Err(AuthError::InvalidCredentials);
+    }
+    let user = User {
+        id: row.id,
+        username: row.username,
+        is_admin: row.is_admin,
+    };
+    create_session(pool, user).await
+}
+
+/// Exchanges a refresh token for a fresh session, revoking the old one so
+/// each refresh token works only once.
+pub async fn refresh(pool: &SqlitePool, refresh_token: &str) -> Result<Session, AuthError> {
+    let mut tx = pool.begin().await?;
+    let user_id: i64 = sqlx::query_scalar(
+        "update sessions set revoked = true
+        where refresh_hash = $1 and not revoked and refresh_expires_at > $2
+        returning user_id",
+    )
+    .bind(digest(refresh_token))
+    .bind(chrono::Utc::now().timestamp())
+    .fetch_optional(&mut tx)
+    .await?
+    .ok_or(AuthError::InvalidToken)?;
+    let user = sqlx::query_as::<_, User>("select id, username, is_admin from users where id = $1")
+        .bind(user_id)
+        .fetch_one(&mut tx)
+        .await?;
+    tx.commit().await?;
+    create_session(pool, user).await
+}
+
+/// Returns whether a live session with `token` existed.
+pub async fn logout(pool: &SqlitePool, token: &str) -> Result<bool, sqlx::Error> {
+    let result =
+        sqlx::query("update sessions set revoked = true where token_hash = $1 and not revoked")
+            .bind(digest(token))
+            .execute(pool)
+            .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Resolves an access token to the user it was issued to.
+pub async fn authenticate(pool: &SqlitePool, token: &str) -> Result<Option<User>, sqlx::Error> {
+    sqlx::query_as::<_, User>(
+        "select users.id, users.username, users.is_admin from sessions
+        join users on users.id = sessions.user_id
+        where sessions.token_hash = $1 and not sessions.revoked and sessions.expires_at > $2",
+    )
+    .bind(digest(token))
+    .bind(chrono::Utc::now().timestamp())
+    .fetch_optional(pool)
+    .await
+}
+
+async fn create_session(pool: &SqlitePool, user: User) -> Result<Session, AuthError> {
+    let now = chrono::Utc::now().timestamp();
+    let session = Session {
+        token: generate_token(),
+        expires_at: now + SESSION_TTL,
+        refresh_token: generate_token(),
+        refresh_expires_at: now + REFRESH_TTL,
+        user,
+    };
+    sqlx::query(
+        "insert into sessions (user_id, token_hash, refresh_hash, expires_at, refresh_expires_at)
+        values ($1, $2, $3, $4, $5)",
+    )
+    .bind(session.user.id)
+    .bind(digest(&session.token))
+    .bind(digest(&session.refresh_token))
+    .bind(session.expires_at)
+    .bind(session.refresh_expires_at)
+    .execute(pool)
+    .await?;
+    Ok(session)
+}
+
+fn generate_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+fn digest(token: &str) -> String {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
@@ -0,0 +1,63 @@
+use std::collections::HashMap;
+use std::sync::{RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use serde::Serialize;
+
+use crate::internal::db::DBX;
+
+/// Registry of the named target databases penkr is connected to.
+#[derive(Debug, Default)]
+pub struct Connections(RwLock<HashMap<String, DBX>>);
+
+#[derive(Serialize)]
+pub struct ConnectionInfo {
+    pub name: String,
+    pub driver: &'static str,
+}
+
+impl Connections {
+    pub fn get(&self, name: &str) -> Option<DBX> {
+        self.read().get(name).cloned()
+    }
+
+    /// Registers `dbx` under `name`, handing it back if the name is taken.
+    pub fn insert(&self, name: &str, dbx: DBX) -> Result<(), DBX> {
+        let mut connections = self.write();
+        if connections.contains_key(name) {
+            return Err(dbx);
+        }
+        connections.insert(name.to_string(), dbx);
+        Ok(())
+    }
+
+    pub fn remove(&self, name: &str) -> Option<DBX> {
+        self.write().remove(name)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.read().contains_key(name)
+    }
+
+    pub fn list(&self) -> Vec<ConnectionInfo> {
+        let mut connections: Vec<ConnectionInfo> = self
+            .read()
+            .iter()
+            .map(|(name, dbx)| ConnectionInfo {
+                name: name.clone(),
+                driver: dbx.driver(),
+            })
+            .collect();
+        connections.sort_by(|a, b| a.name.cmp(&b.name));
+        connections
+    }
+
+    // A poisoned lock only means a panic happened mid-access; the map itself
+    // is always left in a consistent state.
+    fn read(&self) -> RwLockReadGuard<'_, HashMap<String, DBX>> {
+        self.0.read().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn write(&self) -> RwLockWriteGuard<'_, HashMap<String, DBX>> {
+        self.0.write().unwrap_or_else(|e| e.into_inner())
+    }
+}
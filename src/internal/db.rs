@@ -1,133 +1,914 @@
-use sqlx::{Postgres, QueryBuilder};
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
 
-use crate::{internal::de::QueryResult, utils::db::get_pg_pool};
+use serde_json::{Map, Value};
+use sqlx::{MySql, QueryBuilder};
 
-#[derive(Debug)]
+use crate::{
+    internal::de::QueryResult,
+    internal::dialect::{Bind, Dialect, InvalidValue},
+    internal::fields::Field,
+    internal::filter::{Expr, FilterError, Literal},
+    internal::search::{Search, RELEVANCE},
+    internal::sort::Sort,
+    models::schema::{Schema, Table},
+    utils::db::{get_mysql_pool, get_pg_pool, get_sqlite_pool, PoolConfig},
+};
+
+/// Runs `$body` once per backend with `$name` bound to the concrete pool.
+macro_rules! with_pool {
+    ($pool:expr, $name:ident => $body:expr) => {
+        match $pool {
+            Pool::Postgres($name) => $body,
+            Pool::MySql($name) => $body,
+            Pool::Sqlite($name) => $body,
+        }
+    };
+}
+
+pub(crate) use with_pool;
+
+#[derive(Debug, Clone)]
+pub enum Pool {
+    Postgres(sqlx::PgPool),
+    MySql(sqlx::MySqlPool),
+    Sqlite(sqlx::SqlitePool),
+}
+
+/// Maps the scheme of `db_url` to the driver that serves it.
+pub fn driver_for_url(db_url: &str) -> Option<&'static str> {
+    match db_url.split(':').next() {
+        Some("postgres" | "postgresql") => Some("postgres"),
+        Some("mysql" | "mariadb") => Some("mysql"),
+        Some("sqlite") => Some("sqlite"),
+        _ => None,
+    }
+}
+
+/// The scheme of `db_url`, the only part safe to report: the rest may hold
+/// a password.
+fn url_scheme(db_url: &str) -> &str {
+    db_url
+        .split_once(':')
+        .map(|(scheme, _)| scheme)
+        .filter(|scheme| {
+            scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '+')
+        })
+        .unwrap_or("none")
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone)]
 pub struct DBX {
-    pub pool: sqlx::Pool<sqlx::Postgres>,
+    pub pool: Pool,
+    /// Snapshot of the current schema, shared by every clone of the
+    /// connection.
+    schema: Arc<RwLock<Arc<Schema>>>,
 }
 
+#[derive(Clone)]
 pub struct DBQuery {
     pub table: String,
-    pub columns: Option<String>,
-    pub r#where: Option<String>,
-    pub order_by: Option<String>,
-    pub order: Option<String>,
+    /// All columns when empty.
+    pub fields: Vec<Field>,
+    pub r#where: Option<Expr>,
+    /// Adds the relevance of each row, which `sort` may use.
+    pub search: Option<Search>,
+    pub sort: Vec<Sort>,
     pub limit: Option<i32>,
     pub offset: Option<i32>,
 }
 
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug)]
+pub enum DBXError {
+    Sqlx(sqlx::Error),
+    Filter(FilterError),
+    UnknownSchema(String),
+    UnknownTable(String),
+    UnknownColumn(String),
+    InvalidValue(InvalidValue),
+    NoPrimaryKey(String),
+    EmptyRecord,
+    CheckFailed,
+    InvalidDefinition(String),
+    UnknownRelation(String),
+    ExpandTooDeep(String),
+    InvalidPagination(String),
+    InvalidSort(String),
+    InvalidField(String),
+    InvalidAggregate(String),
+    NotSearchable(String),
+}
+
+impl fmt::Display for DBXError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DBXError::Sqlx(e) => e.fmt(f),
+            DBXError::Filter(e) => e.fmt(f),
+            DBXError::UnknownSchema(schema) => write!(f, "Schema {} is not found", schema),
+            DBXError::UnknownTable(table) => write!(f, "Collection {} is not found", table),
+            DBXError::UnknownColumn(column) => write!(f, "Unknown column: {}", column),
+            DBXError::InvalidValue(e) => e.fmt(f),
+            DBXError::NoPrimaryKey(table) => {
+                write!(f, "Collection {} has no single-column primary key", table)
+            }
+            DBXError::EmptyRecord => f.write_str("Record has no fields"),
+            DBXError::CheckFailed => f.write_str("Record is not allowed by the collection rules"),
+            DBXError::InvalidDefinition(message) => f.write_str(message),
+            DBXError::UnknownRelation(relation) => write!(f, "Unknown relation: {}", relation),
+            DBXError::ExpandTooDeep(path) => write!(
+                f,
+                "Relation {} is nested deeper than {} levels",
+                path,
+                crate::internal::expand::MAX_EXPAND_DEPTH
+            ),
+            DBXError::InvalidPagination(message) => f.write_str(message),
+            DBXError::InvalidSort(message) => f.write_str(message),
+            DBXError::InvalidField(field) => write!(f, "Invalid field: {}", field),
+            DBXError::InvalidAggregate(message) => f.write_str(message),
+            DBXError::NotSearchable(table) => {
+                write!(f, "Collection {} has no text columns to search", table)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DBXError {}
+
+impl From<sqlx::Error> for DBXError {
+    fn from(e: sqlx::Error) -> Self {
+        DBXError::Sqlx(e)
+    }
+}
+
+impl From<InvalidValue> for DBXError {
+    fn from(e: InvalidValue) -> Self {
+        DBXError::InvalidValue(e)
+    }
+}
+
+impl From<FilterError> for DBXError {
+    fn from(e: FilterError) -> Self {
+        DBXError::Filter(e)
+    }
+}
+
 impl DBX {
-    pub async fn new(max_connections: u32, db_url: &str) -> Result<Self, sqlx::Error> {
-        let pool = get_pg_pool(max_connections, db_url).await?;
-        Ok(Self { pool })
+    /// Connects to the database at `db_url`, picking the driver from its
+    /// scheme (`postgres://`, `mysql://` or `sqlite://`), and takes a
+    /// snapshot of its current schema.
+    pub async fn new(config: &PoolConfig, db_url: &str) -> Result<Self, DBXError> {
+        let pool = match driver_for_url(db_url) {
+            Some("postgres") => Pool::Postgres(get_pg_pool(config, db_url).await?),
+            Some("mysql") => Pool::MySql(get_mysql_pool(config, db_url).await?),
+            Some("sqlite") => Pool::Sqlite(get_sqlite_pool(config, db_url).await?),
+            _ => {
+                return Err(DBXError::Sqlx(sqlx::Error::Configuration(
+                    format!("Unsupported database url scheme: {}", url_scheme(db_url)).into(),
+                )));
+            }
+        };
+        let dbx = Self {
+            pool,
+            schema: Arc::new(RwLock::new(Arc::new(Schema {
+                name: String::new(),
+                tables: Vec::new(),
+                views: Vec::new(),
+                materialized_views: Vec::new(),
+            }))),
+        };
+        if let Err(e) = dbx.introspect(&[]).await {
+            dbx.disconnect().await.ok();
+            return Err(e);
+        }
+        Ok(dbx)
+    }
+
+    pub fn driver(&self) -> &'static str {
+        match self.pool {
+            Pool::Postgres(_) => "postgres",
+            Pool::MySql(_) => "mysql",
+            Pool::Sqlite(_) => "sqlite",
+        }
     }
 
     pub async fn disconnect(&self) -> Result<(), sqlx::Error> {
-        Ok(self.pool.close().await)
+        with_pool!(&self.pool, pool => pool.close().await);
+        Ok(())
     }
 
     pub async fn raw(&self, query: &str) -> Result<Vec<QueryResult>, sqlx::Error> {
-        let result = sqlx::query_as::<_, QueryResult>(query)
-            .fetch_all(&self.pool)
-            .await?;
+        let result = with_pool!(&self.pool, pool => {
+            sqlx::query_as::<_, QueryResult>(query)
+                .fetch_all(pool)
+                .await?
+        });
         Ok(result)
     }
 
-    pub async fn select(&self, query: &DBQuery) -> Result<Vec<QueryResult>, sqlx::Error> {
-        let mut query_builder = QueryBuilder::new("select ");
+    /// The last snapshot taken of the current schema.
+    pub fn schema(&self) -> Arc<Schema> {
+        Arc::clone(&self.schema.read().unwrap_or_else(|e| e.into_inner()))
+    }
 
-        let query = self
-            .query_filter(query, &mut query_builder)
-            .build_query_as::<QueryResult>()
-            .fetch_all(&self.pool)
-            .await?;
+    pub(crate) fn set_schema(&self, schema: Schema) {
+        *self.schema.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(schema);
+    }
 
-        Ok(query)
+    /// Looks `table` up in the schema snapshot, among tables and views.
+    pub fn table(&self, table: &str) -> Result<Table, DBXError> {
+        let schema = self.schema();
+        schema
+            .tables
+            .iter()
+            .chain(&schema.views)
+            .chain(&schema.materialized_views)
+            .find(|t| t.name == table)
+            .cloned()
+            .ok_or_else(|| DBXError::UnknownTable(table.to_string()))
     }
 
-    pub async fn select_one(&self, query: &DBQuery) -> Result<QueryResult, sqlx::Error> {
-        let mut query_builder = QueryBuilder::new("select ");
+    /// Returns the columns of `table` keyed by name, with the column type
+    /// the backend's `Dialect` expects as value.
+    pub fn columns(&self, table: &str) -> Result<HashMap<String, String>, DBXError> {
+        Ok(self
+            .table(table)?
+            .columns
+            .into_iter()
+            .map(|column| (column.name, column.column_type))
+            .collect())
+    }
 
-        let query = self
-            .query_filter(query, &mut query_builder)
-            .build_query_as::<QueryResult>()
-            .fetch_one(&self.pool)
-            .await?;
+    /// Returns the single-column primary key of `table`, falling back to an
+    /// `id` column for tables without one.
+    pub fn primary_key(
+        &self,
+        table: &str,
+        columns: &HashMap<String, String>,
+    ) -> Result<String, DBXError> {
+        match self.table(table)?.primary_key.as_slice() {
+            [primary_key] => Ok(primary_key.clone()),
+            [] if columns.contains_key("id") => Ok("id".to_string()),
+            _ => Err(DBXError::NoPrimaryKey(table.to_string())),
+        }
+    }
 
-        Ok(query)
+    pub async fn select(&self, query: &DBQuery) -> Result<Vec<QueryResult>, DBXError> {
+        let columns = self.columns(&query.table)?;
+        Self::check_columns(query, &columns)?;
+        let rows = with_pool!(&self.pool, pool => {
+            let mut query_builder = QueryBuilder::new("select ");
+            Self::query_filter(query, &columns, &mut query_builder)?;
+            query_builder
+                .build_query_as::<QueryResult>()
+                .fetch_all(pool)
+                .await?
+        });
+        Ok(rows)
     }
 
-    pub async fn insert(&self, query: &str) -> Result<Vec<QueryResult>, sqlx::Error> {
-        let user = sqlx::query_as::<_, QueryResult>(&query)
-            .fetch_all(&self.pool)
-            .await?;
-        Ok(user)
+    /// Selects the row whose primary key equals `id`, on top of any filter
+    /// already in `query`.
+    pub async fn select_by_id(
+        &self,
+        mut query: DBQuery,
+        id: &str,
+    ) -> Result<QueryResult, DBXError> {
+        let columns = self.columns(&query.table)?;
+        Self::check_columns(&query, &columns)?;
+        let primary_key = self.primary_key(&query.table, &columns)?;
+        let by_id = Expr::eq(&primary_key, Literal::String(id.to_string()));
+        query.r#where = Some(match query.r#where.take() {
+            Some(r#where) => Expr::And(Box::new(by_id), Box::new(r#where)),
+            None => by_id,
+        });
+
+        let row = with_pool!(&self.pool, pool => {
+            let mut query_builder = QueryBuilder::new("select ");
+            Self::query_filter(&query, &columns, &mut query_builder)?;
+            query_builder
+                .build_query_as::<QueryResult>()
+                .fetch_one(pool)
+                .await?
+        });
+        Ok(row)
     }
 
-    pub async fn insert_one(&self, query: &str) -> Result<QueryResult, sqlx::Error> {
-        let user = sqlx::query_as::<_, QueryResult>(&query)
-            .fetch_one(&self.pool)
-            .await?;
-        Ok(user)
+    /// Inserts `rows` and returns them as stored, in a single transaction.
+    ///
+    /// With a `check`, the transaction is rolled back unless every inserted
+    /// row matches it.
+    pub async fn insert(
+        &self,
+        table: &str,
+        rows: &[Map<String, Value>],
+        check: Option<&Expr>,
+    ) -> Result<Vec<QueryResult>, DBXError> {
+        if rows.is_empty() {
+            return Ok(Vec::new());
+        }
+        let columns = self.columns(table)?;
+        let primary_key = match check {
+            Some(_) => Some(self.primary_key(table, &columns)?),
+            None => None,
+        };
+        let inserted = match &self.pool {
+            Pool::Postgres(pool) => {
+                let mut tx = pool.begin().await?;
+                let mut query_builder = QueryBuilder::new("");
+                Self::insert_query(table, rows, &columns, &mut query_builder)?;
+                query_builder.push(" returning *");
+                let inserted = query_builder
+                    .build_query_as::<QueryResult>()
+                    .fetch_all(&mut tx)
+                    .await?;
+                if let (Some(check), Some(primary_key)) = (check, &primary_key) {
+                    let ids = Self::ids_of(&inserted, primary_key);
+                    let mut query_builder = QueryBuilder::new("");
+                    Self::count_query(
+                        table,
+                        primary_key,
+                        &columns,
+                        &ids,
+                        check,
+                        &mut query_builder,
+                    )?;
+                    let (matched,): (i64,) =
+                        query_builder.build_query_as().fetch_one(&mut tx).await?;
+                    if matched as usize != inserted.len() {
+                        return Err(DBXError::CheckFailed);
+                    }
+                }
+                tx.commit().await?;
+                inserted
+            }
+            // SQLite has no `default` keyword in `values`, so each row gets
+            // its own statement with only the columns it sets.
+            Pool::Sqlite(pool) => {
+                let mut tx = pool.begin().await?;
+                let mut inserted = Vec::with_capacity(rows.len());
+                for row in rows {
+                    let mut query_builder = QueryBuilder::new("");
+                    Self::insert_query(
+                        table,
+                        std::slice::from_ref(row),
+                        &columns,
+                        &mut query_builder,
+                    )?;
+                    query_builder.push(" returning *");
+                    inserted.push(
+                        query_builder
+                            .build_query_as::<QueryResult>()
+                            .fetch_one(&mut tx)
+                            .await?,
+                    );
+                }
+                if let (Some(check), Some(primary_key)) = (check, &primary_key) {
+                    let ids = Self::ids_of(&inserted, primary_key);
+                    let mut query_builder = QueryBuilder::new("");
+                    Self::count_query(
+                        table,
+                        primary_key,
+                        &columns,
+                        &ids,
+                        check,
+                        &mut query_builder,
+                    )?;
+                    let (matched,): (i64,) =
+                        query_builder.build_query_as().fetch_one(&mut tx).await?;
+                    if matched as usize != inserted.len() {
+                        return Err(DBXError::CheckFailed);
+                    }
+                }
+                tx.commit().await?;
+                inserted
+            }
+            // MySQL has no `returning`: rows are inserted one at a time to
+            // learn each generated id, then read back.
+            Pool::MySql(pool) => {
+                let primary_key = match primary_key {
+                    Some(primary_key) => primary_key,
+                    None => self.primary_key(table, &columns)?,
+                };
+                // Only an auto-increment key can be learned back from
+                // `last_insert_id()`; any other key has to be in the body.
+                let generated = self
+                    .table(table)?
+                    .columns
+                    .iter()
+                    .any(|column| column.name == primary_key && column.is_auto_increment);
+                if !generated
+                    && rows
+                        .iter()
+                        .any(|row| row.get(&primary_key).is_none_or(Value::is_null))
+                {
+                    return Err(InvalidValue {
+                        column: primary_key,
+                        message: "is required because it is not auto-increment".to_string(),
+                    }
+                    .into());
+                }
+                let mut tx = pool.begin().await?;
+                let mut ids = Vec::with_capacity(rows.len());
+                for row in rows {
+                    let mut query_builder = QueryBuilder::new("");
+                    Self::insert_query(
+                        table,
+                        std::slice::from_ref(row),
+                        &columns,
+                        &mut query_builder,
+                    )?;
+                    let result = query_builder.build().execute(&mut tx).await?;
+                    ids.push(match row.get(&primary_key) {
+                        Some(id) if !id.is_null() => id.clone(),
+                        _ => Value::from(result.last_insert_id()),
+                    });
+                }
+                let inserted =
+                    Self::mysql_select_ids(&mut tx, table, &primary_key, &columns, &ids, check)
+                        .await?;
+                if check.is_some() && inserted.len() != rows.len() {
+                    return Err(DBXError::CheckFailed);
+                }
+                tx.commit().await?;
+                inserted
+            }
+        };
+        Ok(inserted)
     }
 
-    pub async fn update(&self, query: &str) -> Result<QueryResult, sqlx::Error> {
-        let user = sqlx::query_as::<_, QueryResult>(&query)
-            .fetch_one(&self.pool)
-            .await?;
-        Ok(user)
+    pub async fn insert_one(
+        &self,
+        table: &str,
+        row: &Map<String, Value>,
+        check: Option<&Expr>,
+    ) -> Result<QueryResult, DBXError> {
+        let mut rows = self.insert(table, std::slice::from_ref(row), check).await?;
+        rows.pop().ok_or(DBXError::Sqlx(sqlx::Error::RowNotFound))
     }
 
-    pub async fn delete(&self, query: &str) -> Result<QueryResult, sqlx::Error> {
-        let user = sqlx::query_as::<_, QueryResult>(&query)
-            .fetch_one(&self.pool)
-            .await?;
-        Ok(user)
+    /// Updates every row whose primary key is in `ids` and that matches
+    /// `filter` with the values in `row`. The update is rolled back when any
+    /// row no longer matches `filter` afterwards.
+    pub async fn update(
+        &self,
+        table: &str,
+        ids: &[&str],
+        row: &Map<String, Value>,
+        filter: Option<&Expr>,
+    ) -> Result<Vec<QueryResult>, DBXError> {
+        if row.is_empty() {
+            return Err(DBXError::EmptyRecord);
+        }
+        let columns = self.columns(table)?;
+        let primary_key = self.primary_key(table, &columns)?;
+        let ids: Vec<Value> = ids.iter().map(|id| Value::from(*id)).collect();
+
+        let updated = match &self.pool {
+            Pool::Postgres(pool) => {
+                let mut query_builder = QueryBuilder::new("");
+                Self::update_query(
+                    table,
+                    &primary_key,
+                    &columns,
+                    &ids,
+                    row,
+                    filter,
+                    &mut query_builder,
+                )?;
+                query_builder.push(" returning *");
+                let mut tx = pool.begin().await?;
+                let updated = query_builder
+                    .build_query_as::<QueryResult>()
+                    .fetch_all(&mut tx)
+                    .await?;
+                if let (Some(filter), false) = (filter, updated.is_empty()) {
+                    let ids = Self::ids_of(&updated, &primary_key);
+                    let mut query_builder = QueryBuilder::new("");
+                    Self::count_query(
+                        table,
+                        &primary_key,
+                        &columns,
+                        &ids,
+                        filter,
+                        &mut query_builder,
+                    )?;
+                    let (matched,): (i64,) =
+                        query_builder.build_query_as().fetch_one(&mut tx).await?;
+                    if matched as usize != updated.len() {
+                        return Err(DBXError::CheckFailed);
+                    }
+                }
+                tx.commit().await?;
+                updated
+            }
+            Pool::Sqlite(pool) => {
+                let mut query_builder = QueryBuilder::new("");
+                Self::update_query(
+                    table,
+                    &primary_key,
+                    &columns,
+                    &ids,
+                    row,
+                    filter,
+                    &mut query_builder,
+                )?;
+                query_builder.push(" returning *");
+                let mut tx = pool.begin().await?;
+                let updated = query_builder
+                    .build_query_as::<QueryResult>()
+                    .fetch_all(&mut tx)
+                    .await?;
+                if let (Some(filter), false) = (filter, updated.is_empty()) {
+                    let ids = Self::ids_of(&updated, &primary_key);
+                    let mut query_builder = QueryBuilder::new("");
+                    Self::count_query(
+                        table,
+                        &primary_key,
+                        &columns,
+                        &ids,
+                        filter,
+                        &mut query_builder,
+                    )?;
+                    let (matched,): (i64,) =
+                        query_builder.build_query_as().fetch_one(&mut tx).await?;
+                    if matched as usize != updated.len() {
+                        return Err(DBXError::CheckFailed);
+                    }
+                }
+                tx.commit().await?;
+                updated
+            }
+            Pool::MySql(pool) => {
+                let mut tx = pool.begin().await?;
+                // Narrow `ids` down to the rows `filter` allows first, as the
+                // rows are read back by id alone.
+                let ids = match filter {
+                    Some(_) => {
+                        let allowed = Self::mysql_select_ids(
+                            &mut tx,
+                            table,
+                            &primary_key,
+                            &columns,
+                            &ids,
+                            filter,
+                        )
+                        .await?;
+                        Self::ids_of(&allowed, &primary_key)
+                    }
+                    None => ids,
+                };
+                if ids.is_empty() {
+                    return Ok(Vec::new());
+                }
+                let mut query_builder = QueryBuilder::new("");
+                Self::update_query(
+                    table,
+                    &primary_key,
+                    &columns,
+                    &ids,
+                    row,
+                    None,
+                    &mut query_builder,
+                )?;
+                query_builder.build().execute(&mut tx).await?;
+                // The update may have changed the primary key itself.
+                let ids = match row.get(&primary_key) {
+                    Some(id) => vec![id.clone()],
+                    None => ids,
+                };
+                let updated =
+                    Self::mysql_select_ids(&mut tx, table, &primary_key, &columns, &ids, None)
+                        .await?;
+                if let (Some(filter), false) = (filter, updated.is_empty()) {
+                    let ids = Self::ids_of(&updated, &primary_key);
+                    let mut query_builder = QueryBuilder::new("");
+                    Self::count_query(
+                        table,
+                        &primary_key,
+                        &columns,
+                        &ids,
+                        filter,
+                        &mut query_builder,
+                    )?;
+                    let (matched,): (i64,) =
+                        query_builder.build_query_as().fetch_one(&mut tx).await?;
+                    if matched as usize != updated.len() {
+                        return Err(DBXError::CheckFailed);
+                    }
+                }
+                tx.commit().await?;
+                updated
+            }
+        };
+        Ok(updated)
     }
 
-    fn query_filter<'a>(
+    /// Deletes every row whose primary key is in `ids` and that matches
+    /// `filter`.
+    pub async fn delete(
         &self,
-        query: &'a DBQuery,
-        query_builder: &'a mut QueryBuilder<'a, Postgres>,
-    ) -> &'a mut QueryBuilder<'a, Postgres> {
-        match &query.columns {
-            Some(columns) => {
-                query_builder.push(columns);
+        table: &str,
+        ids: &[&str],
+        filter: Option<&Expr>,
+    ) -> Result<Vec<QueryResult>, DBXError> {
+        let columns = self.columns(table)?;
+        let primary_key = self.primary_key(table, &columns)?;
+        let ids: Vec<Value> = ids.iter().map(|id| Value::from(*id)).collect();
+
+        let deleted = match &self.pool {
+            Pool::Postgres(pool) => {
+                let mut query_builder = QueryBuilder::new("");
+                Self::delete_query(
+                    table,
+                    &primary_key,
+                    &columns,
+                    &ids,
+                    filter,
+                    &mut query_builder,
+                )?;
+                query_builder.push(" returning *");
+                query_builder
+                    .build_query_as::<QueryResult>()
+                    .fetch_all(pool)
+                    .await?
+            }
+            Pool::Sqlite(pool) => {
+                let mut query_builder = QueryBuilder::new("");
+                Self::delete_query(
+                    table,
+                    &primary_key,
+                    &columns,
+                    &ids,
+                    filter,
+                    &mut query_builder,
+                )?;
+                query_builder.push(" returning *");
+                query_builder
+                    .build_query_as::<QueryResult>()
+                    .fetch_all(pool)
+                    .await?
+            }
+            Pool::MySql(pool) => {
+                let mut tx = pool.begin().await?;
+                let deleted =
+                    Self::mysql_select_ids(&mut tx, table, &primary_key, &columns, &ids, filter)
+                        .await?;
+                let mut query_builder = QueryBuilder::new("");
+                Self::delete_query(
+                    table,
+                    &primary_key,
+                    &columns,
+                    &ids,
+                    filter,
+                    &mut query_builder,
+                )?;
+                query_builder.build().execute(&mut tx).await?;
+                tx.commit().await?;
+                deleted
+            }
+        };
+        Ok(deleted)
+    }
+
+    /// Reads rows back by primary key, for MySQL which has no `returning`.
+    async fn mysql_select_ids(
+        tx: &mut sqlx::Transaction<'_, MySql>,
+        table: &str,
+        primary_key: &str,
+        columns: &HashMap<String, String>,
+        ids: &[Value],
+        filter: Option<&Expr>,
+    ) -> Result<Vec<QueryResult>, DBXError> {
+        let mut query_builder = QueryBuilder::new("select * from ");
+        query_builder.push(MySql::quote_ident(table));
+        Self::push_where_ids(&mut query_builder, primary_key, columns, ids, filter)?;
+        let rows = query_builder
+            .build_query_as::<QueryResult>()
+            .fetch_all(tx)
+            .await?;
+        Ok(rows)
+    }
+
+    fn insert_query<DB: Dialect>(
+        table: &str,
+        rows: &[Map<String, Value>],
+        columns: &HashMap<String, String>,
+        query_builder: &mut QueryBuilder<'_, DB>,
+    ) -> Result<(), DBXError> {
+        let mut names: Vec<&String> = Vec::new();
+        for name in rows.iter().flat_map(|row| row.keys()) {
+            if !columns.contains_key(name) {
+                return Err(DBXError::UnknownColumn(name.clone()));
             }
-            None => {
-                query_builder.push("*");
+            if !names.contains(&name) {
+                names.push(name);
             }
         }
 
-        query_builder.push(" from ");
-        query_builder.push(query.table.as_str());
+        query_builder.push("insert into ");
+        query_builder.push(DB::quote_ident(table));
+        if names.is_empty() && rows.len() == 1 {
+            query_builder.push(DB::DEFAULT_VALUES);
+            return Ok(());
+        }
 
-        if let Some(r#where) = &query.r#where {
-            query_builder.push(" where ");
-            query_builder.push(r#where.as_str());
+        // Multi-row inserts of empty records still need a column to put
+        // `default` into.
+        if names.is_empty() {
+            names.extend(columns.keys().take(1));
         }
 
-        if let Some(order_by) = &query.order_by {
-            query_builder.push(" order by ");
-            query_builder.push_bind(order_by.as_str());
+        query_builder.push(" (");
+        for (i, name) in names.iter().enumerate() {
+            if i > 0 {
+                query_builder.push(", ");
+            }
+            query_builder.push(DB::quote_ident(name));
         }
+        query_builder.push(") values ");
+        for (i, row) in rows.iter().enumerate() {
+            query_builder.push(if i > 0 { ", (" } else { "(" });
+            for (j, name) in names.iter().enumerate() {
+                if j > 0 {
+                    query_builder.push(", ");
+                }
+                match row.get(name.as_str()) {
+                    Some(value) => {
+                        DB::push_value(query_builder, name, &columns[name.as_str()], value)?
+                    }
+                    None => {
+                        query_builder.push("default");
+                    }
+                }
+            }
+            query_builder.push(")");
+        }
+        Ok(())
+    }
 
-        if let Some(order) = &query.order {
-            query_builder.push(" ");
-            query_builder.push_bind(order.as_str());
+    fn update_query<DB: Dialect>(
+        table: &str,
+        primary_key: &str,
+        columns: &HashMap<String, String>,
+        ids: &[Value],
+        row: &Map<String, Value>,
+        filter: Option<&Expr>,
+        query_builder: &mut QueryBuilder<'_, DB>,
+    ) -> Result<(), DBXError> {
+        query_builder.push("update ");
+        query_builder.push(DB::quote_ident(table));
+        query_builder.push(" set ");
+        for (i, (column, value)) in row.iter().enumerate() {
+            let column_type = columns
+                .get(column)
+                .ok_or_else(|| DBXError::UnknownColumn(column.clone()))?;
+            if i > 0 {
+                query_builder.push(", ");
+            }
+            query_builder.push(format!("{} = ", DB::quote_ident(column)));
+            DB::push_value(query_builder, column, column_type, value)?;
         }
+        Self::push_where_ids(query_builder, primary_key, columns, ids, filter)
+    }
+
+    fn delete_query<DB: Dialect>(
+        table: &str,
+        primary_key: &str,
+        columns: &HashMap<String, String>,
+        ids: &[Value],
+        filter: Option<&Expr>,
+        query_builder: &mut QueryBuilder<'_, DB>,
+    ) -> Result<(), DBXError> {
+        query_builder.push("delete from ");
+        query_builder.push(DB::quote_ident(table));
+        Self::push_where_ids(query_builder, primary_key, columns, ids, filter)
+    }
+
+    /// Counts the rows among `ids` that match `filter`.
+    fn count_query<DB: Dialect>(
+        table: &str,
+        primary_key: &str,
+        columns: &HashMap<String, String>,
+        ids: &[Value],
+        filter: &Expr,
+        query_builder: &mut QueryBuilder<'_, DB>,
+    ) -> Result<(), DBXError> {
+        query_builder.push("select count(*) from ");
+        query_builder.push(DB::quote_ident(table));
+        Self::push_where_ids(query_builder, primary_key, columns, ids, Some(filter))
+    }
+
+    fn ids_of(rows: &[QueryResult], primary_key: &str) -> Vec<Value> {
+        rows.iter()
+            .filter_map(|row| row.get(primary_key).cloned())
+            .collect()
+    }
 
-        if let Some(limit) = &query.limit {
+    pub(super) fn push_where_ids<DB: Dialect>(
+        query_builder: &mut QueryBuilder<'_, DB>,
+        primary_key: &str,
+        columns: &HashMap<String, String>,
+        ids: &[Value],
+        filter: Option<&Expr>,
+    ) -> Result<(), DBXError> {
+        query_builder.push(format!(" where {} in (", DB::quote_ident(primary_key)));
+        for (i, id) in ids.iter().enumerate() {
+            if i > 0 {
+                query_builder.push(", ");
+            }
+            DB::push_value(query_builder, primary_key, &columns[primary_key], id)?;
+        }
+        query_builder.push(")");
+        if let Some(filter) = filter {
+            query_builder.push(" and ");
+            filter.push(query_builder, columns)?;
+        }
+        Ok(())
+    }
+
+    /// Rejects selected or sorted columns `table` does not have, before
+    /// they get near any SQL.
+    pub(super) fn check_columns(
+        query: &DBQuery,
+        columns: &HashMap<String, String>,
+    ) -> Result<(), DBXError> {
+        for column in query
+            .fields
+            .iter()
+            .map(|field| field.column.as_str())
+            .chain(query.sort.iter().map(|sort| sort.column.as_str()))
+        {
+            let relevance = query.search.is_some() && column == RELEVANCE;
+            if !columns.contains_key(column) && !relevance {
+                return Err(DBXError::UnknownColumn(column.to_string()));
+            }
+        }
+        Ok(())
+    }
+
+    pub(super) fn query_filter<DB: Dialect>(
+        query: &DBQuery,
+        columns: &HashMap<String, String>,
+        query_builder: &mut QueryBuilder<'_, DB>,
+    ) -> Result<(), DBXError> {
+        Self::push_select_list(query, columns, query_builder)?;
+        query_builder.push(" from ");
+        query_builder.push(DB::quote_ident(&query.table));
+        Self::push_where(query, columns, query_builder)?;
+
+        Sort::push(&query.sort, query_builder);
+
+        if let Some(limit) = query.limit {
             query_builder.push(" limit ");
-            query_builder.push_bind(limit);
+            DB::push_bind(query_builder, Bind::Int(limit.into()));
         }
 
-        if let Some(offset) = &query.offset {
+        if let Some(offset) = query.offset {
             query_builder.push(" offset ");
-            query_builder.push_bind(offset);
+            DB::push_bind(query_builder, Bind::Int(offset.into()));
         }
 
-        query_builder
+        Ok(())
+    }
+
+    /// Pushes the fields of `query`, and the relevance when it searches.
+    pub(super) fn push_select_list<DB: Dialect>(
+        query: &DBQuery,
+        columns: &HashMap<String, String>,
+        query_builder: &mut QueryBuilder<'_, DB>,
+    ) -> Result<(), DBXError> {
+        Field::push_list(&query.fields, columns, query_builder)?;
+        if let Some(search) = &query.search {
+            query_builder.push(", ");
+            search.push_relevance(&query.table, query_builder);
+        }
+        Ok(())
+    }
+
+    /// Pushes ` where` with the filter and search of `query`, if any, and
+    /// returns whether it did.
+    pub(super) fn push_where<DB: Dialect>(
+        query: &DBQuery,
+        columns: &HashMap<String, String>,
+        query_builder: &mut QueryBuilder<'_, DB>,
+    ) -> Result<bool, DBXError> {
+        if let Some(r#where) = &query.r#where {
+            query_builder.push(" where (");
+            r#where.push(query_builder, columns)?;
+            query_builder.push(")");
+        }
+        if let Some(search) = &query.search {
+            query_builder.push(match query.r#where {
+                Some(_) => " and ",
+                None => " where ",
+            });
+            search.push_condition(&query.table, query_builder);
+        }
+        Ok(query.r#where.is_some() || query.search.is_some())
     }
 }
@@ -0,0 +1,644 @@
+use sqlx::{MySql, Postgres, Sqlite};
+
+use crate::{
+    internal::db::{with_pool, DBXError, Pool, DBX},
+    internal::dialect::Dialect,
+    models::schema::{Check, ForeignKey, Index, Table, VColumn},
+};
+
+const ACTIONS: [&str; 5] = [
+    "NO ACTION",
+    "RESTRICT",
+    "CASCADE",
+    "SET NULL",
+    "SET DEFAULT",
+];
+
+pub(super) fn invalid(message: String) -> DBXError {
+    DBXError::InvalidDefinition(message)
+}
+
+/// Runs `$build::<Dialect>(...)` for the backend of `$pool`.
+macro_rules! for_dialect {
+    ($pool:expr, $build:ident($($arg:expr),*)) => {
+        match $pool {
+            Pool::Postgres(_) => $build::<Postgres>($($arg),*),
+            Pool::MySql(_) => $build::<MySql>($($arg),*),
+            Pool::Sqlite(_) => $build::<Sqlite>($($arg),*),
+        }
+    };
+}
+
+pub(super) use for_dialect;
+
+/// Changes to existing tables, which the backends spell differently or not
+/// at all.
+pub(super) trait Ddl: Dialect {
+    /// Adds a table `constraint` to `table`.
+    fn add_constraint(table: &str, constraint: &str) -> Result<String, DBXError> {
+        Ok(format!(
+            "alter table {} add {}",
+            Self::quote_ident(table),
+            constraint
+        ))
+    }
+
+    fn drop_foreign_key(table: &str, name: &str) -> Result<String, DBXError>;
+
+    fn drop_check(table: &str, name: &str) -> Result<String, DBXError>;
+
+    fn drop_index(table: &str, name: &str) -> String {
+        let _ = table;
+        format!("drop index {}", Self::quote_ident(name))
+    }
+
+    /// Statements turning column `from` of `table` into `to`, given the
+    /// names of the fields that differ.
+    fn alter_column(
+        table: &str,
+        from: &VColumn,
+        to: &VColumn,
+        changes: &[&str],
+    ) -> Result<Vec<String>, DBXError>;
+
+    /// The type introspection reports for a column declared as
+    /// `native_type`, so declared and live types can be compared.
+    fn reported_type(native_type: &str) -> String {
+        native_type.to_string()
+    }
+
+    /// The `default` expression as introspection reports it, so declared
+    /// and live defaults can be compared.
+    fn reported_default(default: &str) -> String {
+        default.trim().to_string()
+    }
+}
+
+impl Ddl for Postgres {
+    fn drop_foreign_key(table: &str, name: &str) -> Result<String, DBXError> {
+        Ok(format!(
+            "alter table {} drop constraint {}",
+            Self::quote_ident(table),
+            Self::quote_ident(name)
+        ))
+    }
+
+    fn drop_check(table: &str, name: &str) -> Result<String, DBXError> {
+        Ok(format!(
+            "alter table {} drop constraint {}",
+            Self::quote_ident(table),
+            Self::quote_ident(name)
+        ))
+    }
+
+    fn alter_column(
+        table: &str,
+        _from: &VColumn,
+        to: &VColumn,
+        changes: &[&str],
+    ) -> Result<Vec<String>, DBXError> {
+        let alter = format!(
+            "alter table {} alter column {}",
+            Self::quote_ident(table),
+            Self::quote_ident(&to.name)
+        );
+        let mut statements = Vec::new();
+        for change in changes {
+            statements.push(match *change {
+                "data_type" => {
+                    let data_type = native_type::<Self>(to)?;
+                    format!(
+                        "{} type {} using {}::{}",
+                        alter,
+                        data_type,
+                        Self::quote_ident(&to.name),
+                        data_type
+                    )
+                }
+                "is_nullable" if to.is_nullable.eq_ignore_ascii_case("NO") => {
+                    format!("{} set not null", alter)
+                }
+                "is_nullable" => format!("{} drop not null", alter),
+                "default_value" => match &to.default_value {
+                    Some(default_value) => format!("{} set default {}", alter, default_value),
+                    None => format!("{} drop default", alter),
+                },
+                _ => continue,
+            });
+        }
+        Ok(statements)
+    }
+
+    // The catalog reports types without their modifiers, other than the
+    // lengths of character types.
+    fn reported_type(native_type: &str) -> String {
+        let data_type = without_modifiers(native_type);
+        Self::native_type(&data_type, None).unwrap_or(data_type)
+    }
+
+    // `pg_get_expr` adds casts, as in `'x'::character varying`, and quotes
+    // negative numbers.
+    fn reported_default(default: &str) -> String {
+        let mut default = default.trim();
+        while let Some((expression, cast)) = default.rsplit_once("::") {
+            let is_type = cast
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || " _[]()\",.".contains(c));
+            if !is_type {
+                break;
+            }
+            default = expression.trim_end();
+        }
+        let number = default
+            .strip_prefix('\'')
+            .and_then(|d| d.strip_suffix('\''))
+            .filter(|number| number.parse::<f64>().is_ok());
+        number.unwrap_or(default).to_string()
+    }
+}
+
+impl Ddl for MySql {
+    fn drop_foreign_key(table: &str, name: &str) -> Result<String, DBXError> {
+        Ok(format!(
+            "alter table {} drop foreign key {}",
+            Self::quote_ident(table),
+            Self::quote_ident(name)
+        ))
+    }
+
+    fn drop_check(table: &str, name: &str) -> Result<String, DBXError> {
+        Ok(format!(
+            "alter table {} drop check {}",
+            Self::quote_ident(table),
+            Self::quote_ident(name)
+        ))
+    }
+
+    fn drop_index(table: &str, name: &str) -> String {
+        format!(
+            "drop index {} on {}",
+            Self::quote_ident(name),
+            Self::quote_ident(table)
+        )
+    }
+
+    // `modify` restates the whole column; its keys are left alone.
+    fn alter_column(
+        table: &str,
+        _from: &VColumn,
+        to: &VColumn,
+        _changes: &[&str],
+    ) -> Result<Vec<String>, DBXError> {
+        Ok(vec![format!(
+            "alter table {} modify column {}",
+            Self::quote_ident(table),
+            column_definition::<Self>(to, false, true)?
+        )])
+    }
+
+    // `information_schema` reports types without their modifiers, other
+    // than the lengths of character types, and booleans as `tinyint`.
+    fn reported_type(native_type: &str) -> String {
+        match without_modifiers(native_type).as_str() {
+            "bool" | "boolean" => "tinyint".to_string(),
+            data_type => data_type.to_string(),
+        }
+    }
+
+    // `information_schema` reports string defaults unquoted, and boolean
+    // ones as numbers.
+    fn reported_default(default: &str) -> String {
+        let default = default.trim();
+        if default.eq_ignore_ascii_case("true") {
+            return "1".to_string();
+        }
+        if default.eq_ignore_ascii_case("false") {
+            return "0".to_string();
+        }
+        match default
+            .strip_prefix('\'')
+            .and_then(|d| d.strip_suffix('\''))
+        {
+            Some(text) => text.replace("''", "'"),
+            None => default.to_string(),
+        }
+    }
+}
+
+/// SQLite can only add, rename and drop columns of an existing table.
+impl Ddl for Sqlite {
+    fn add_constraint(table: &str, _constraint: &str) -> Result<String, DBXError> {
+        Err(sqlite_unsupported("add constraints to", table))
+    }
+
+    fn drop_foreign_key(table: &str, _name: &str) -> Result<String, DBXError> {
+        Err(sqlite_unsupported("drop foreign keys of", table))
+    }
+
+    fn drop_check(table: &str, _name: &str) -> Result<String, DBXError> {
+        Err(sqlite_unsupported("drop check constraints of", table))
+    }
+
+    fn alter_column(
+        table: &str,
+        _from: &VColumn,
+        _to: &VColumn,
+        _changes: &[&str],
+    ) -> Result<Vec<String>, DBXError> {
+        Err(sqlite_unsupported("change columns of", table))
+    }
+}
+
+/// `native_type` without its modifiers, such as the precision of
+/// `datetime(6)`, unless it is a sized character type.
+fn without_modifiers(native_type: &str) -> String {
+    if native_type.starts_with("varchar(") || native_type.starts_with("char(") {
+        return native_type.to_string();
+    }
+    match (native_type.find('('), native_type.rfind(')')) {
+        (Some(start), Some(end)) if start < end => {
+            format!("{}{}", &native_type[..start], &native_type[end + 1..])
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+        }
+        _ => native_type.to_string(),
+    }
+}
+
+fn sqlite_unsupported(action: &str, table: &str) -> DBXError {
+    invalid(format!("SQLite cannot {} existing table {}", action, table))
+}
+
+impl DBX {
+    /// Creates `table` with its keys, constraints and indexes, returning
+    /// the statements that did it.
+    pub async fn create_table(&self, table: &Table) -> Result<Vec<String>, DBXError> {
+        let schema = self.schema();
+        let statements = for_dialect!(&self.pool, create_table(table, &schema.name))?;
+        self.execute_ddl(&statements).await?;
+        Ok(statements)
+    }
+
+    pub async fn drop_table(&self, table: &str) -> Result<Vec<String>, DBXError> {
+        self.table(table)?;
+        let statements = vec![for_dialect!(&self.pool, drop_table(table))];
+        self.execute_ddl(&statements).await?;
+        Ok(statements)
+    }
+
+    /// Adds `column` to `table`, referencing `foreign_key` if given.
+    pub async fn add_column(
+        &self,
+        table: &str,
+        column: &VColumn,
+        foreign_key: Option<&ForeignKey>,
+    ) -> Result<Vec<String>, DBXError> {
+        self.table(table)?;
+        let schema = self.schema();
+        let statements = vec![for_dialect!(
+            &self.pool,
+            add_column(table, column, foreign_key, &schema.name)
+        )?];
+        self.execute_ddl(&statements).await?;
+        Ok(statements)
+    }
+
+    pub async fn rename_column(
+        &self,
+        table: &str,
+        column: &str,
+        name: &str,
+    ) -> Result<Vec<String>, DBXError> {
+        self.check_column(table, column)?;
+        check_name("column", name)?;
+        let statements = vec![for_dialect!(&self.pool, rename_column(table, column, name))];
+        self.execute_ddl(&statements).await?;
+        Ok(statements)
+    }
+
+    pub async fn drop_column(&self, table: &str, column: &str) -> Result<Vec<String>, DBXError> {
+        self.check_column(table, column)?;
+        let statements = vec![for_dialect!(&self.pool, drop_column(table, column))];
+        self.execute_ddl(&statements).await?;
+        Ok(statements)
+    }
+
+    fn check_column(&self, table: &str, column: &str) -> Result<(), DBXError> {
+        if !self.columns(table)?.contains_key(column) {
+            return Err(DBXError::UnknownColumn(column.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Runs `statements` in one transaction where the backend allows DDL in
+    /// transactions, then refreshes the schema snapshot.
+    pub(super) async fn execute_ddl(&self, statements: &[String]) -> Result<(), DBXError> {
+        with_pool!(&self.pool, pool => {
+            let mut tx = pool.begin().await?;
+            for statement in statements {
+                sqlx::query(statement).execute(&mut tx).await?;
+            }
+            tx.commit().await?;
+        });
+        self.introspect(&[]).await?;
+        Ok(())
+    }
+}
+
+fn check_name(kind: &str, name: &str) -> Result<(), DBXError> {
+    if name.trim().is_empty() {
+        return Err(invalid(format!("A {} needs a name", kind)));
+    }
+    Ok(())
+}
+
+fn quote_list<DB: Dialect>(names: &[String]) -> String {
+    names
+        .iter()
+        .map(|name| DB::quote_ident(name))
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+pub(super) fn create_table<DB: Dialect>(
+    table: &Table,
+    schema: &str,
+) -> Result<Vec<String>, DBXError> {
+    check_name("table", &table.name)?;
+    if table.columns.is_empty() {
+        return Err(invalid(format!("Table {} has no columns", table.name)));
+    }
+    for (i, column) in table.columns.iter().enumerate() {
+        check_name("column", &column.name)?;
+        if table.columns[..i].iter().any(|c| c.name == column.name) {
+            return Err(invalid(format!("Column {} is declared twice", column.name)));
+        }
+    }
+    let primary_key: Vec<String> = if table.primary_key.is_empty() {
+        table
+            .columns
+            .iter()
+            .filter(|column| column.is_primary_key)
+            .map(|column| column.name.clone())
+            .collect()
+    } else {
+        table.primary_key.clone()
+    };
+    let has_column = |name: &String| table.columns.iter().any(|column| &column.name == name);
+    if let Some(unknown) = primary_key.iter().find(|name| !has_column(name)) {
+        return Err(DBXError::UnknownColumn(unknown.clone()));
+    }
+
+    let mut definitions = Vec::new();
+    for column in &table.columns {
+        let key = primary_key.contains(&column.name);
+        definitions.push(column_definition::<DB>(
+            column,
+            key && primary_key.len() == 1,
+            key,
+        )?);
+    }
+    if primary_key.len() > 1 {
+        definitions.push(format!("primary key ({})", quote_list::<DB>(&primary_key)));
+    }
+    for foreign_key in &table.foreign_keys {
+        if let Some(unknown) = foreign_key.columns.iter().find(|name| !has_column(name)) {
+            return Err(DBXError::UnknownColumn(unknown.clone()));
+        }
+        definitions.push(foreign_key_constraint::<DB>(
+            foreign_key,
+            &foreign_key.columns,
+            schema,
+        )?);
+    }
+    for check in &table.checks {
+        definitions.push(check_constraint::<DB>(check));
+    }
+
+    let mut statements = vec![format!(
+        "create table {} (\n  {}\n)",
+        DB::quote_ident(&table.name),
+        definitions.join(",\n  ")
+    )];
+    for index in table
+        .indexes
+        .iter()
+        .filter(|index| !is_implied(table, index))
+    {
+        if let Some(unknown) = index.columns.iter().find(|name| !has_column(name)) {
+            return Err(DBXError::UnknownColumn(unknown.clone()));
+        }
+        statements.push(create_index::<DB>(&table.name, index)?);
+    }
+    Ok(statements)
+}
+
+/// Whether `index` comes with `table` already, backing its primary key or
+/// a unique column.
+pub(super) fn is_implied(table: &Table, index: &Index) -> bool {
+    let unique_column = match index.columns.as_slice() {
+        [name] => table
+            .columns
+            .iter()
+            .any(|column| &column.name == name && column.is_unique),
+        _ => false,
+    };
+    index.is_primary
+        || (index.is_unique && unique_column)
+        || index.name.starts_with("sqlite_autoindex_")
+}
+
+pub(super) fn create_index<DB: Dialect>(table: &str, index: &Index) -> Result<String, DBXError> {
+    check_name("index", &index.name)?;
+    Ok(format!(
+        "create {}index {} on {} ({})",
+        if index.is_unique { "unique " } else { "" },
+        DB::quote_ident(&index.name),
+        DB::quote_ident(table),
+        quote_list::<DB>(&index.columns)
+    ))
+}
+
+pub(super) fn check_constraint<DB: Dialect>(check: &Check) -> String {
+    match &check.name {
+        Some(name) => format!(
+            "constraint {} check ({})",
+            DB::quote_ident(name),
+            check.expression
+        ),
+        None => format!("check ({})", check.expression),
+    }
+}
+
+/// The type `column` is declared with on the backend.
+pub(super) fn native_type<DB: Dialect>(column: &VColumn) -> Result<String, DBXError> {
+    DB::native_type(&column.data_type, column.maximum_length).ok_or_else(|| {
+        invalid(format!(
+            "Unsupported type {} for column {}",
+            column.data_type, column.name
+        ))
+    })
+}
+
+/// The definition of `column`, declaring it the primary key if `inline_key`.
+/// Members of a `key` are unique already.
+pub(super) fn column_definition<DB: Dialect>(
+    column: &VColumn,
+    inline_key: bool,
+    key: bool,
+) -> Result<String, DBXError> {
+    let mut definition = format!(
+        "{} {}",
+        DB::quote_ident(&column.name),
+        native_type::<DB>(column)?
+    );
+    match column.is_nullable.to_uppercase().as_str() {
+        "YES" => {}
+        "NO" => definition.push_str(" not null"),
+        _ => {
+            return Err(invalid(format!(
+                "is_nullable of column {} must be YES or NO",
+                column.name
+            )))
+        }
+    }
+    // Defaults of generated columns refer to sequences of their own.
+    if let (Some(default_value), false) = (&column.default_value, column.is_auto_increment) {
+        definition.push_str(&format!(" default {}", default_value));
+    }
+    if inline_key {
+        definition.push_str(" primary key");
+    }
+    if column.is_auto_increment {
+        definition.push_str(DB::AUTO_INCREMENT);
+    }
+    if column.is_unique && !key {
+        definition.push_str(" unique");
+    }
+    Ok(definition)
+}
+
+/// `references ...` with the actions of `foreign_key`, qualifying the table
+/// when it lives outside the current `schema`.
+fn references<DB: Dialect>(foreign_key: &ForeignKey, schema: &str) -> Result<String, DBXError> {
+    check_name("referenced table", &foreign_key.referenced_table)?;
+    let mut target = DB::quote_ident(&foreign_key.referenced_table);
+    if !foreign_key.referenced_schema.is_empty() && foreign_key.referenced_schema != schema {
+        target = format!(
+            "{}.{}",
+            DB::quote_ident(&foreign_key.referenced_schema),
+            target
+        );
+    }
+    let mut clause = format!("references {}", target);
+    if !foreign_key.referenced_columns.is_empty() {
+        clause.push_str(&format!(
+            " ({})",
+            quote_list::<DB>(&foreign_key.referenced_columns)
+        ));
+    }
+    for (event, action) in [
+        ("delete", &foreign_key.on_delete),
+        ("update", &foreign_key.on_update),
+    ] {
+        let action = action.to_uppercase();
+        if !ACTIONS.contains(&action.as_str()) {
+            return Err(invalid(format!(
+                "on_{} must be one of {}",
+                event,
+                ACTIONS.join(", ")
+            )));
+        }
+        clause.push_str(&format!(" on {} {}", event, action.to_lowercase()));
+    }
+    Ok(clause)
+}
+
+pub(super) fn foreign_key_constraint<DB: Dialect>(
+    foreign_key: &ForeignKey,
+    columns: &[String],
+    schema: &str,
+) -> Result<String, DBXError> {
+    if columns.is_empty() {
+        return Err(invalid(format!(
+            "Foreign key to {} has no columns",
+            foreign_key.referenced_table
+        )));
+    }
+    if !foreign_key.referenced_columns.is_empty()
+        && foreign_key.referenced_columns.len() != columns.len()
+    {
+        return Err(invalid(format!(
+            "Foreign key to {} references {} columns from {}",
+            foreign_key.referenced_table,
+            foreign_key.referenced_columns.len(),
+            columns.len()
+        )));
+    }
+    let mut constraint = String::new();
+    if let Some(name) = &foreign_key.name {
+        constraint.push_str(&format!("constraint {} ", DB::quote_ident(name)));
+    }
+    constraint.push_str(&format!(
+        "foreign key ({}) {}",
+        quote_list::<DB>(columns),
+        references::<DB>(foreign_key, schema)?
+    ));
+    Ok(constraint)
+}
+
+pub(super) fn add_column<DB: Dialect>(
+    table: &str,
+    column: &VColumn,
+    foreign_key: Option<&ForeignKey>,
+    schema: &str,
+) -> Result<String, DBXError> {
+    check_name("column", &column.name)?;
+    let mut statement = format!(
+        "alter table {} add column {}",
+        DB::quote_ident(table),
+        column_definition::<DB>(column, column.is_primary_key, column.is_primary_key)?
+    );
+    if let Some(foreign_key) = foreign_key {
+        let columns = std::slice::from_ref(&column.name);
+        if !foreign_key.columns.is_empty() && foreign_key.columns != columns {
+            return Err(invalid(format!(
+                "The foreign key of column {} can only use that column",
+                column.name
+            )));
+        }
+        if DB::INLINE_REFERENCES {
+            if let Some(name) = &foreign_key.name {
+                statement.push_str(&format!(" constraint {}", DB::quote_ident(name)));
+            }
+            statement.push(' ');
+            statement.push_str(&references::<DB>(foreign_key, schema)?);
+        } else {
+            statement.push_str(", add ");
+            statement.push_str(&foreign_key_constraint::<DB>(foreign_key, columns, schema)?);
+        }
+    }
+    Ok(statement)
+}
+
+fn rename_column<DB: Dialect>(table: &str, column: &str, name: &str) -> String {
+    format!(
+        "alter table {} rename column {} to {}",
+        DB::quote_ident(table),
+        DB::quote_ident(column),
+        DB::quote_ident(name)
+    )
+}
+
+pub(super) fn drop_column<DB: Dialect>(table: &str, column: &str) -> String {
+    format!(
+        "alter table {} drop column {}",
+        DB::quote_ident(table),
+        DB::quote_ident(column)
+    )
+}
+
+pub(super) fn drop_table<DB: Dialect>(table: &str) -> String {
+    format!("drop table {}", DB::quote_ident(table))
+}
@@ -1,199 +1,288 @@
-use serde::Serialize;
-use sqlx::{postgres::PgRow, sqlite::SqliteRow, Column, FromRow, Row, TypeInfo};
-use std::collections::HashMap;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{ser::SerializeMap, Deserialize, Serialize, Serializer};
+use serde_json::Value;
+use sqlx::{
+    mysql::{MySqlColumn, MySqlRow},
+    postgres::{PgColumn, PgRow},
+    sqlite::{SqliteColumn, SqliteRow},
+    Column, ColumnIndex, Decode, FromRow, Row, Type, TypeInfo, ValueRef,
+};
 
-#[derive(Serialize)]
-pub struct QueryResult(HashMap<String, serde_json::Value>);
+use crate::internal::{pg_value, temporal::TemporalFormat};
 
-impl FromRow<'_, PgRow> for QueryResult {
-    fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
-        let columns = row.columns();
-        let data = columns
+/// The key of a row's warnings when it is written as an object.
+pub const WARNINGS: &str = "$warnings";
+
+/// A row with its columns in the order they were selected. A repeated
+/// column name, as from a join, gets a `_2`, `_3`, ... suffix.
+///
+/// Values that could not be decoded are null, with a [`Warning`] saying
+/// why; only SQL `NULL`s are null without one.
+#[derive(Debug, Clone, Default)]
+pub struct QueryResult {
+    columns: Vec<(String, Value)>,
+    warnings: Vec<Warning>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Warning {
+    pub column: String,
+    pub message: String,
+}
+
+impl QueryResult {
+    pub fn get(&self, column: &str) -> Option<&Value> {
+        self.columns
             .iter()
-            .map(|column| {
-                let name = column.name();
-                let column_type = column.type_info();
-                map_pg_column(name, row, column_type.name())
+            .find(|(name, _)| name == column)
+            .map(|(_, value)| value)
+    }
+
+    /// Sets `column`, adding it last if the row does not have it.
+    pub fn insert(&mut self, column: String, value: Value) {
+        match self.columns.iter_mut().find(|(name, _)| *name == column) {
+            Some((_, old)) => *old = value,
+            None => self.columns.push((column, value)),
+        }
+    }
+
+    /// Adds a decoded column under a name no other column of the row has.
+    fn push(&mut self, name: &str, value: Result<Value, String>) {
+        let mut unique = name.to_string();
+        let mut n = 1;
+        while self.get(&unique).is_some() {
+            n += 1;
+            unique = format!("{}_{}", name, n);
+        }
+        let value = value.unwrap_or_else(|message| {
+            self.warnings.push(Warning {
+                column: unique.clone(),
+                message,
+            });
+            Value::Null
+        });
+        self.columns.push((unique, value));
+    }
+}
+
+/// An object in column order, with the warnings under [`WARNINGS`] when there
+/// are any.
+impl Serialize for QueryResult {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let warned = !self.warnings.is_empty();
+        let mut map = serializer.serialize_map(Some(self.columns.len() + warned as usize))?;
+        for (name, value) in &self.columns {
+            map.serialize_entry(name, value)?;
+        }
+        if warned {
+            map.serialize_entry(WARNINGS, &self.warnings)?;
+        }
+        map.end()
+    }
+}
+
+/// How rows are written out.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Shape {
+    /// An object per row.
+    #[default]
+    Objects,
+    /// A [`Table`].
+    Arrays,
+}
+
+/// Rows as arrays of values in the order of `columns`.
+#[derive(Debug, Serialize)]
+pub struct Table {
+    pub columns: Vec<String>,
+    pub rows: Vec<Vec<Value>>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub warnings: Vec<RowWarning>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct RowWarning {
+    /// The index of the row in `rows`.
+    pub row: usize,
+    #[serde(flatten)]
+    pub warning: Warning,
+}
+
+impl Table {
+    /// Rows missing a column, as added by expansion, have it null.
+    pub fn new(rows: Vec<QueryResult>) -> Self {
+        let mut columns: Vec<String> = Vec::new();
+        for row in &rows {
+            for (name, _) in &row.columns {
+                if !columns.contains(name) {
+                    columns.push(name.clone());
+                }
+            }
+        }
+        let mut warnings = Vec::new();
+        let rows = rows
+            .into_iter()
+            .enumerate()
+            .map(|(i, row)| {
+                let values = columns
+                    .iter()
+                    .map(|column| row.get(column).cloned().unwrap_or(Value::Null))
+                    .collect();
+                warnings.extend(
+                    row.warnings
+                        .into_iter()
+                        .map(|warning| RowWarning { row: i, warning }),
+                );
+                values
             })
             .collect();
-        Ok(QueryResult(data))
+        Table {
+            columns,
+            rows,
+            warnings,
+        }
+    }
+}
+
+impl FromRow<'_, PgRow> for QueryResult {
+    fn from_row(row: &PgRow) -> Result<Self, sqlx::Error> {
+        let mut result = QueryResult::default();
+        for column in row.columns() {
+            result.push(column.name(), map_pg_column(row, column));
+        }
+        Ok(result)
     }
 }
 
 impl FromRow<'_, SqliteRow> for QueryResult {
     fn from_row(row: &SqliteRow) -> Result<Self, sqlx::Error> {
-        let columns = row.columns();
-        let data = columns
-            .iter()
-            .map(|column| {
-                let name = column.name();
-                let column_type = column.type_info();
-                map_sqlite_column(name, row, column_type.name())
-            })
-            .collect();
-        Ok(QueryResult(data))
+        let mut result = QueryResult::default();
+        for column in row.columns() {
+            result.push(column.name(), map_sqlite_column(row, column));
+        }
+        Ok(result)
     }
 }
 
-fn map_pg_column(name: &str, row: &PgRow, pg_type: &str) -> (String, serde_json::Value) {
-    match pg_type {
-        "BOOL" => row
-            .try_get::<bool, _>(name)
-            .map_or((name.to_string(), serde_json::Value::Null), |value| {
-                (name.to_string(), serde_json::Value::Bool(value))
-            }),
-        "INT2" => row
-            .try_get::<i16, _>(name)
-            .map_or((name.to_string(), serde_json::Value::Null), |value| {
-                (name.to_string(), serde_json::Value::Number(value.into()))
-            }),
-        "INT4" => row
-            .try_get::<i32, _>(name)
-            .map_or((name.to_string(), serde_json::Value::Null), |value| {
-                (name.to_string(), serde_json::Value::Number(value.into()))
-            }),
-        "INT8" => row
-            .try_get::<i64, _>(name)
-            .map_or((name.to_string(), serde_json::Value::Null), |value| {
-                (name.to_string(), serde_json::Value::Number(value.into()))
-            }),
-        "FLOAT4" => row.try_get::<f32, _>(name).map_or(
-            (name.to_string(), serde_json::Value::Null),
-            |value| {
-                serde_json::Number::from_f64(value.into())
-                    .map_or((name.to_string(), serde_json::Value::Null), |number| {
-                        (name.to_string(), serde_json::Value::Number(number))
-                    })
-            },
-        ),
-        "FLOAT8" => row.try_get::<f64, _>(name).map_or(
-            (name.to_string(), serde_json::Value::Null),
-            |value| {
-                serde_json::Number::from_f64(value.into())
-                    .map_or((name.to_string(), serde_json::Value::Null), |number| {
-                        (name.to_string(), serde_json::Value::Number(number))
-                    })
-            },
-        ),
-        "TEXT" | "VARCHAR" => row
-            .try_get::<String, _>(name)
-            .map_or((name.to_string(), serde_json::Value::Null), |value| {
-                (name.to_string(), serde_json::Value::String(value.into()))
-            }),
-        "JSON" | "JSONB" => row
-            .try_get::<serde_json::Value, _>(name)
-            .map_or((name.to_string(), serde_json::Value::Null), |value| {
-                (name.to_string(), value)
-            }),
-        "TIMESTAMPTZ" => row
-            .try_get::<chrono::DateTime<chrono::Utc>, _>(name)
-            .map_or((name.to_string(), serde_json::Value::Null), |value| {
-                (
-                    name.to_string(),
-                    serde_json::Value::Number(value.timestamp().into()),
-                )
-            }),
-        "TIMESTAMP" => row.try_get::<chrono::NaiveDateTime, _>(name).map_or(
-            (name.to_string(), serde_json::Value::Null),
-            |value| {
-                (
-                    name.to_string(),
-                    serde_json::Value::Number(value.timestamp().into()),
-                )
-            },
-        ),
-        "DATE" => row.try_get::<chrono::NaiveDate, _>(name).map_or(
-            (name.to_string(), serde_json::Value::Null),
-            |value| {
-                (
-                    name.to_string(),
-                    serde_json::Value::String(value.to_string()),
-                )
-            },
-        ),
-        "TIME" => row.try_get::<chrono::NaiveTime, _>(name).map_or(
-            (name.to_string(), serde_json::Value::Null),
-            |value| {
-                (
-                    name.to_string(),
-                    serde_json::Value::String(value.to_string()),
-                )
-            },
-        ),
-        "NULL" => (name.to_string(), serde_json::Value::Null),
-        _ => (
-            name.to_string(),
-            serde_json::Value::String("not supported".into()),
-        ),
+impl FromRow<'_, MySqlRow> for QueryResult {
+    fn from_row(row: &MySqlRow) -> Result<Self, sqlx::Error> {
+        let mut result = QueryResult::default();
+        for column in row.columns() {
+            result.push(column.name(), map_mysql_column(row, column));
+        }
+        Ok(result)
     }
 }
 
-fn map_sqlite_column(
-    name: &str,
-    row: &SqliteRow,
-    sqlite_type: &str,
-) -> (String, serde_json::Value) {
-    match sqlite_type {
-        "BOOLEAN" => row
-            .try_get::<bool, _>(name)
-            .map_or((name.to_string(), serde_json::Value::Null), |value| {
-                (name.to_string(), serde_json::Value::Bool(value))
-            }),
-        "INTEGER" => row
-            .try_get::<i64, _>(name)
-            .map_or((name.to_string(), serde_json::Value::Null), |value| {
-                (name.to_string(), serde_json::Value::Number(value.into()))
-            }),
-        "REAL" => row.try_get::<f64, _>(name).map_or(
-            (name.to_string(), serde_json::Value::Null),
-            |value| {
-                serde_json::Number::from_f64(value.into())
-                    .map_or((name.to_string(), serde_json::Value::Null), |number| {
-                        (name.to_string(), serde_json::Value::Number(number))
-                    })
-            },
-        ),
-        "TEXT" => row
-            .try_get::<String, _>(name)
-            .map_or((name.to_string(), serde_json::Value::Null), |value| {
-                (name.to_string(), serde_json::Value::String(value.into()))
-            }),
-        "JSON" => row
-            .try_get::<serde_json::Value, _>(name)
-            .map_or((name.to_string(), serde_json::Value::Null), |value| {
-                (name.to_string(), value)
-            }),
-        "TIMESTAMP" => row.try_get::<chrono::NaiveDateTime, _>(name).map_or(
-            (name.to_string(), serde_json::Value::Null),
-            |value| {
-                (
-                    name.to_string(),
-                    serde_json::Value::Number(value.timestamp().into()),
-                )
-            },
-        ),
-        "DATE" => row.try_get::<chrono::NaiveDate, _>(name).map_or(
-            (name.to_string(), serde_json::Value::Null),
-            |value| {
-                (
-                    name.to_string(),
-                    serde_json::Value::String(value.to_string()),
-                )
-            },
-        ),
-        "TIME" => row.try_get::<chrono::NaiveTime, _>(name).map_or(
-            (name.to_string(), serde_json::Value::Null),
-            |value| {
-                (
-                    name.to_string(),
-                    serde_json::Value::String(value.to_string()),
-                )
-            },
-        ),
-        "NULL" => (name.to_string(), serde_json::Value::Null),
-        _ => (
-            name.to_string(),
-            serde_json::Value::String("not supported".into()),
-        ),
+/// Decodes the value at `index`; SQL `NULL`s are handled before.
+fn get<'r, R, T>(row: &'r R, index: usize) -> Result<T, String>
+where
+    R: Row,
+    usize: ColumnIndex<R>,
+    T: Decode<'r, R::Database> + Type<R::Database>,
+{
+    row.try_get(index).map_err(|e| e.to_string())
+}
+
+fn float(value: f64) -> Result<Value, String> {
+    serde_json::Number::from_f64(value)
+        .map(Value::Number)
+        .ok_or_else(|| format!("{} has no JSON representation", value))
+}
+
+fn unsupported(column_type: &str) -> Result<Value, String> {
+    Err(format!("Unsupported column type {}", column_type))
+}
+
+fn map_pg_column(row: &PgRow, column: &PgColumn) -> Result<Value, String> {
+    let i = column.ordinal();
+    let value = row.try_get_raw(i).map_err(|e| e.to_string())?;
+    if value.is_null() {
+        return Ok(Value::Null);
+    }
+    match column.type_info().name() {
+        "BOOL" => get(row, i).map(Value::Bool),
+        "INT2" => get::<_, i16>(row, i).map(Value::from),
+        "INT4" => get::<_, i32>(row, i).map(Value::from),
+        "INT8" => get::<_, i64>(row, i).map(Value::from),
+        "FLOAT4" => get::<_, f32>(row, i).and_then(|value| float(value.into())),
+        "FLOAT8" => get(row, i).and_then(float),
+        "TEXT" | "VARCHAR" => get(row, i).map(Value::String),
+        "JSON" | "JSONB" => get::<_, Value>(row, i),
+        "TIMESTAMPTZ" => get(row, i).map(|value| TemporalFormat::current().timestamp(value)),
+        "TIMESTAMP" => get(row, i).map(|value| TemporalFormat::current().naive_timestamp(value)),
+        "DATE" => get::<_, chrono::NaiveDate>(row, i).map(|value| value.to_string().into()),
+        "TIME" => get::<_, chrono::NaiveTime>(row, i).map(|value| value.to_string().into()),
+        _ => pg_value::decode(value),
+    }
+}
+
+/// Goes by the storage class of the value, which SQLite does not hold to
+/// the declared type, and by the declared type only to read booleans,
+/// dates and times.
+fn map_sqlite_column(row: &SqliteRow, column: &SqliteColumn) -> Result<Value, String> {
+    let i = column.ordinal();
+    let value = row.try_get_raw(i).map_err(|e| e.to_string())?;
+    if value.is_null() {
+        return Ok(Value::Null);
+    }
+    let storage = value.type_info().name().to_string();
+    match (column.type_info().name(), storage.as_str()) {
+        ("BOOLEAN", "INTEGER") => get(row, i).map(Value::Bool),
+        ("DATETIME", _) => {
+            get(row, i).map(|value| TemporalFormat::current().naive_timestamp(value))
+        }
+        ("DATE", "TEXT") => {
+            get::<_, chrono::NaiveDate>(row, i).map(|value| value.to_string().into())
+        }
+        ("TIME", "TEXT") => {
+            get::<_, chrono::NaiveTime>(row, i).map(|value| value.to_string().into())
+        }
+        (_, "INTEGER") => get::<_, i64>(row, i).map(Value::from),
+        (_, "REAL") => get(row, i).and_then(float),
+        (_, "TEXT") => get(row, i).map(Value::String),
+        (_, "BLOB") => get::<_, Vec<u8>>(row, i).map(|value| STANDARD.encode(value).into()),
+        (column_type, _) => unsupported(column_type),
+    }
+}
+
+fn map_mysql_column(row: &MySqlRow, column: &MySqlColumn) -> Result<Value, String> {
+    let i = column.ordinal();
+    let value = row.try_get_raw(i).map_err(|e| e.to_string())?;
+    if value.is_null() {
+        return Ok(Value::Null);
+    }
+    match column.type_info().name() {
+        "BOOLEAN" => get(row, i).map(Value::Bool),
+        "TINYINT" | "SMALLINT" | "MEDIUMINT" | "INT" | "BIGINT" => {
+            get::<_, i64>(row, i).map(Value::from)
+        }
+        "TINYINT UNSIGNED" | "SMALLINT UNSIGNED" | "MEDIUMINT UNSIGNED" | "INT UNSIGNED"
+        | "BIGINT UNSIGNED" => get::<_, u64>(row, i).map(Value::from),
+        "YEAR" => row
+            .try_get_unchecked::<i64, _>(i)
+            .map(Value::from)
+            .map_err(|e| e.to_string()),
+        "FLOAT" => get::<_, f32>(row, i).and_then(|value| float(value.into())),
+        "DOUBLE" => get(row, i).and_then(float),
+        // Decimals arrive as their exact textual representation.
+        "DECIMAL" => row
+            .try_get_unchecked::<String, _>(i)
+            .map(Value::String)
+            .map_err(|e| e.to_string()),
+        "CHAR" | "VARCHAR" | "TINYTEXT" | "TEXT" | "MEDIUMTEXT" | "LONGTEXT" | "ENUM" | "SET" => {
+            get(row, i).map(Value::String)
+        }
+        "BINARY" | "VARBINARY" | "TINYBLOB" | "BLOB" | "MEDIUMBLOB" | "LONGBLOB" => {
+            get::<_, Vec<u8>>(row, i).map(|value| STANDARD.encode(value).into())
+        }
+        "JSON" => get::<_, Value>(row, i),
+        "TIMESTAMP" => get(row, i).map(|value| TemporalFormat::current().timestamp(value)),
+        "DATETIME" => get(row, i).map(|value| TemporalFormat::current().naive_timestamp(value)),
+        "DATE" => get::<_, chrono::NaiveDate>(row, i).map(|value| value.to_string().into()),
+        "TIME" => get::<_, chrono::NaiveTime>(row, i).map(|value| value.to_string().into()),
+        column_type => unsupported(column_type),
     }
 }
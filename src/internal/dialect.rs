@@ -0,0 +1,640 @@
+use std::fmt;
+
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use sqlx::{Database, MySql, Postgres, QueryBuilder, Sqlite};
+
+use crate::internal::temporal::TemporalFormat;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct InvalidValue {
+    pub column: String,
+    pub message: String,
+}
+
+impl fmt::Display for InvalidValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Invalid value for column {}: {}",
+            self.column, self.message
+        )
+    }
+}
+
+impl std::error::Error for InvalidValue {}
+
+/// A query parameter that has already been coerced for its column.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Bind {
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Text(String),
+}
+
+/// The parts of query building that differ between database backends.
+///
+/// Column types are whatever `DBX::columns` reports for the backend: the
+/// `udt_name` on Postgres, the `data_type` on MySQL and the lowercased
+/// declared type on SQLite.
+pub trait Dialect: Database {
+    /// What follows `insert into <table>` to insert a row of defaults.
+    const DEFAULT_VALUES: &'static str;
+
+    /// Column constraint making an integer column generate its own values.
+    const AUTO_INCREMENT: &'static str;
+
+    /// Whether `references` in a column definition declares a foreign key;
+    /// MySQL parses it and ignores it.
+    const INLINE_REFERENCES: bool;
+
+    /// Whether `order by` takes `nulls first` and `nulls last`; MySQL
+    /// does not.
+    const NULLS_ORDER: bool;
+
+    /// Column types holding JSON that a field can reach into.
+    const JSON_TYPES: &'static [&'static str];
+
+    /// What follows a `like` pattern to escape its wildcards with a
+    /// backslash; MySQL string literals need the backslash doubled.
+    const LIKE_ESCAPE: &'static str = " escape '\\'";
+
+    fn quote_ident(ident: &str) -> String;
+
+    fn push_bind(query_builder: &mut QueryBuilder<'_, Self>, value: Bind);
+
+    /// Binds `value` for a column of `column_type`, coercing JSON values
+    /// into what the backend expects for that column.
+    fn push_value(
+        query_builder: &mut QueryBuilder<'_, Self>,
+        column: &str,
+        column_type: &str,
+        value: &Value,
+    ) -> Result<(), InvalidValue>;
+
+    /// The type to declare for a column of `data_type`, which is either a
+    /// portable name (`integer`, `text`, `timestamp`, ...) or taken as a
+    /// native type. `None` when it is not plainly a type name.
+    fn native_type(data_type: &str, maximum_length: Option<i32>) -> Option<String>;
+
+    /// Pushes the value at `path` inside the quoted JSON `column`.
+    fn push_json_path(query_builder: &mut QueryBuilder<'_, Self>, column: &str, path: &[String]) {
+        let mut json_path = "$".to_string();
+        for segment in path {
+            match segment.parse::<u32>() {
+                Ok(index) => json_path.push_str(&format!("[{}]", index)),
+                Err(_) => json_path.push_str(&format!(".\"{}\"", segment)),
+            }
+        }
+        query_builder.push(format!("json_extract({}, ", column));
+        Self::push_bind(query_builder, Bind::Text(json_path));
+        query_builder.push(")");
+    }
+
+    /// The average of the quoted `column`.
+    fn average(column: &str) -> String {
+        format!("avg({})", column)
+    }
+
+    /// Wraps the quoted `column` so it can be matched with `like`.
+    fn like_operand(column: &str, column_type: &str) -> String {
+        let _ = column_type;
+        column.to_string()
+    }
+
+    /// Pushes a condition matching the rows of `table` whose text `columns`
+    /// match the search `term`, by the backend's full-text search through
+    /// `index`.
+    fn push_text_match(
+        query_builder: &mut QueryBuilder<'_, Self>,
+        table: &str,
+        columns: &[String],
+        index: &str,
+        term: &str,
+    );
+
+    /// Pushes the relevance of the match of `push_text_match`, higher for
+    /// better matches.
+    fn push_text_rank(
+        query_builder: &mut QueryBuilder<'_, Self>,
+        table: &str,
+        columns: &[String],
+        index: &str,
+        term: &str,
+    );
+}
+
+impl Dialect for Postgres {
+    const DEFAULT_VALUES: &'static str = " default values";
+    const AUTO_INCREMENT: &'static str = " generated by default as identity";
+    const INLINE_REFERENCES: bool = true;
+    const NULLS_ORDER: bool = true;
+    const JSON_TYPES: &'static [&'static str] = &["json", "jsonb"];
+
+    fn quote_ident(ident: &str) -> String {
+        format!("\"{}\"", ident.replace('"', "\"\""))
+    }
+
+    fn push_json_path(query_builder: &mut QueryBuilder<'_, Self>, column: &str, path: &[String]) {
+        query_builder.push(column);
+        for segment in path {
+            query_builder.push(" -> ");
+            match segment.parse::<u32>() {
+                Ok(index) => query_builder.push(index.to_string()),
+                Err(_) => query_builder.push_bind(segment.clone()),
+            };
+        }
+    }
+
+    /// `avg` of integers and numerics is a `numeric`; a float is what is
+    /// wanted of an average.
+    fn average(column: &str) -> String {
+        format!("avg({})::float8", column)
+    }
+
+    fn native_type(data_type: &str, maximum_length: Option<i32>) -> Option<String> {
+        let data_type = declared_type(data_type)?;
+        Some(match data_type.as_str() {
+            "int" | "integer" | "int4" => "integer".to_string(),
+            "smallint" | "int2" => "smallint".to_string(),
+            "bigint" | "int8" => "bigint".to_string(),
+            "varchar" | "character varying" => sized("varchar", maximum_length),
+            "char" | "character" | "bpchar" => sized("char", maximum_length),
+            "bool" | "boolean" => "boolean".to_string(),
+            "real" | "float4" => "real".to_string(),
+            "double" | "double precision" | "float" | "float8" => "double precision".to_string(),
+            "decimal" | "numeric" => "numeric".to_string(),
+            "datetime" | "timestamp" | "timestamp without time zone" => "timestamp".to_string(),
+            "timestamptz" | "timestamp with time zone" => "timestamptz".to_string(),
+            "time without time zone" => "time".to_string(),
+            "blob" | "bytes" | "binary" => "bytea".to_string(),
+            _ => data_type,
+        })
+    }
+
+    fn push_bind(query_builder: &mut QueryBuilder<'_, Self>, value: Bind) {
+        match value {
+            Bind::Int(value) => query_builder.push_bind(value),
+            Bind::Float(value) => query_builder.push_bind(value),
+            Bind::Bool(value) => query_builder.push_bind(value),
+            Bind::Text(value) => query_builder.push_bind(value),
+        };
+    }
+
+    fn push_value(
+        query_builder: &mut QueryBuilder<'_, Self>,
+        column: &str,
+        column_type: &str,
+        value: &Value,
+    ) -> Result<(), InvalidValue> {
+        let bind = match column_type {
+            _ if value.is_null() => {
+                query_builder.push("null");
+                return Ok(());
+            }
+            "int2" | "int4" | "int8" => to_int(column, value)?,
+            "float4" | "float8" => to_float(column, value)?,
+            "bool" => to_bool(column, value)?,
+            "json" | "jsonb" => {
+                query_builder.push("cast(");
+                Self::push_bind(query_builder, Bind::Text(value.to_string()));
+                query_builder.push(format!(" as {})", column_type));
+                return Ok(());
+            }
+            _ if is_pg_text_type(column_type) => to_text(column, value)?,
+            "timestamp" | "timestamptz" => {
+                let value = to_timestamp(column, value)?.format(NAIVE_TIMESTAMP);
+                query_builder.push("cast(");
+                Self::push_bind(query_builder, Bind::Text(format!("{}+00", value)));
+                query_builder.push(format!(" as {})", column_type));
+                return Ok(());
+            }
+            _ => {
+                // Let Postgres parse the value into the column's own type
+                // (uuid, numeric, timestamptz, enums, arrays, ...).
+                let value = match value {
+                    Value::Array(items) if column_type.starts_with('_') => array_literal(items),
+                    _ => scalar_to_string(value),
+                }
+                .ok_or_else(|| invalid(column, "unsupported value for this column type"))?;
+                query_builder.push("cast(");
+                Self::push_bind(query_builder, Bind::Text(value));
+                query_builder.push(format!(" as {})", Self::quote_ident(column_type)));
+                return Ok(());
+            }
+        };
+        Self::push_bind(query_builder, bind);
+        Ok(())
+    }
+
+    fn like_operand(column: &str, column_type: &str) -> String {
+        if is_pg_text_type(column_type) {
+            column.to_string()
+        } else {
+            format!("cast({} as text)", column)
+        }
+    }
+
+    /// Matches on the indexed expression itself, so the index serves it,
+    /// and parses the term with the text search configuration it names.
+    fn push_text_match(
+        query_builder: &mut QueryBuilder<'_, Self>,
+        _table: &str,
+        _columns: &[String],
+        index: &str,
+        term: &str,
+    ) {
+        query_builder.push(format!("{} @@ ", index));
+        push_pg_query(query_builder, index, term);
+    }
+
+    fn push_text_rank(
+        query_builder: &mut QueryBuilder<'_, Self>,
+        _table: &str,
+        _columns: &[String],
+        index: &str,
+        term: &str,
+    ) {
+        query_builder.push(format!("ts_rank({}, ", index));
+        push_pg_query(query_builder, index, term);
+        query_builder.push(")");
+    }
+}
+
+impl Dialect for MySql {
+    const DEFAULT_VALUES: &'static str = " () values ()";
+    const AUTO_INCREMENT: &'static str = " auto_increment";
+    const INLINE_REFERENCES: bool = false;
+    const NULLS_ORDER: bool = false;
+    const JSON_TYPES: &'static [&'static str] = &["json"];
+    const LIKE_ESCAPE: &'static str = " escape '\\\\'";
+
+    fn quote_ident(ident: &str) -> String {
+        format!("`{}`", ident.replace('`', "``"))
+    }
+
+    fn native_type(data_type: &str, maximum_length: Option<i32>) -> Option<String> {
+        let data_type = declared_type(data_type)?;
+        Some(match data_type.as_str() {
+            "int" | "integer" | "int4" => "int".to_string(),
+            "smallint" | "int2" => "smallint".to_string(),
+            "bigint" | "int8" => "bigint".to_string(),
+            "varchar" | "character varying" => sized("varchar", maximum_length.or(Some(255))),
+            "char" | "character" | "bpchar" => sized("char", maximum_length),
+            "bool" | "boolean" => "boolean".to_string(),
+            "real" | "float4" => "float".to_string(),
+            "double" | "double precision" | "float8" => "double".to_string(),
+            "decimal" | "numeric" => "decimal(65, 30)".to_string(),
+            "timestamp" | "timestamp without time zone" => "datetime(6)".to_string(),
+            "timestamptz" | "timestamp with time zone" => "timestamp(6)".to_string(),
+            "time without time zone" => "time".to_string(),
+            "jsonb" => "json".to_string(),
+            "uuid" => "char(36)".to_string(),
+            "bytea" | "bytes" | "binary" => "longblob".to_string(),
+            _ => data_type,
+        })
+    }
+
+    fn push_bind(query_builder: &mut QueryBuilder<'_, Self>, value: Bind) {
+        match value {
+            Bind::Int(value) => query_builder.push_bind(value),
+            Bind::Float(value) => query_builder.push_bind(value),
+            Bind::Bool(value) => query_builder.push_bind(value),
+            Bind::Text(value) => query_builder.push_bind(value),
+        };
+    }
+
+    fn push_value(
+        query_builder: &mut QueryBuilder<'_, Self>,
+        column: &str,
+        column_type: &str,
+        value: &Value,
+    ) -> Result<(), InvalidValue> {
+        let bind = match column_type {
+            _ if value.is_null() => {
+                query_builder.push("null");
+                return Ok(());
+            }
+            "tinyint" | "smallint" | "mediumint" | "int" | "bigint" => to_int(column, value)?,
+            "float" | "double" | "real" => to_float(column, value)?,
+            "boolean" => to_bool(column, value)?,
+            "json" => {
+                query_builder.push("cast(");
+                Self::push_bind(query_builder, Bind::Text(value.to_string()));
+                query_builder.push(" as json)");
+                return Ok(());
+            }
+            "timestamp" | "datetime" => {
+                let value = to_timestamp(column, value)?;
+                Bind::Text(value.format(NAIVE_TIMESTAMP).to_string())
+            }
+            // MySQL converts strings into decimals, dates, enums, ... itself.
+            _ => to_text(column, value)?,
+        };
+        Self::push_bind(query_builder, bind);
+        Ok(())
+    }
+
+    /// Needs a `fulltext` index on exactly `columns`.
+    fn push_text_match(
+        query_builder: &mut QueryBuilder<'_, Self>,
+        table: &str,
+        columns: &[String],
+        index: &str,
+        term: &str,
+    ) {
+        Self::push_text_rank(query_builder, table, columns, index, term);
+    }
+
+    fn push_text_rank(
+        query_builder: &mut QueryBuilder<'_, Self>,
+        _table: &str,
+        columns: &[String],
+        _index: &str,
+        term: &str,
+    ) {
+        let columns: Vec<String> = columns.iter().map(|c| Self::quote_ident(c)).collect();
+        query_builder.push(format!("match({}) against (", columns.join(", ")));
+        query_builder.push_bind(term.to_string());
+        query_builder.push(" in natural language mode)");
+    }
+}
+
+impl Dialect for Sqlite {
+    const DEFAULT_VALUES: &'static str = " default values";
+    const AUTO_INCREMENT: &'static str = " autoincrement";
+    const INLINE_REFERENCES: bool = true;
+    const NULLS_ORDER: bool = true;
+    const JSON_TYPES: &'static [&'static str] = &["json", "text"];
+
+    fn quote_ident(ident: &str) -> String {
+        format!("\"{}\"", ident.replace('"', "\"\""))
+    }
+
+    // Only `integer primary key` columns can be `autoincrement`, so every
+    // integer type is declared as `integer`.
+    fn native_type(data_type: &str, maximum_length: Option<i32>) -> Option<String> {
+        let data_type = declared_type(data_type)?;
+        Some(match data_type.as_str() {
+            "int" | "integer" | "int4" | "smallint" | "int2" | "bigint" | "int8" => {
+                "integer".to_string()
+            }
+            "varchar" | "character varying" => sized("varchar", maximum_length),
+            "char" | "character" | "bpchar" => sized("char", maximum_length),
+            "bool" | "boolean" => "boolean".to_string(),
+            "float4" | "double" | "double precision" | "float" | "float8" => "real".to_string(),
+            "decimal" => "numeric".to_string(),
+            "timestamp"
+            | "timestamp without time zone"
+            | "timestamptz"
+            | "timestamp with time zone" => "datetime".to_string(),
+            "time without time zone" => "time".to_string(),
+            "jsonb" => "json".to_string(),
+            "uuid" => "text".to_string(),
+            "bytea" | "bytes" | "binary" => "blob".to_string(),
+            _ => data_type,
+        })
+    }
+
+    fn push_bind(query_builder: &mut QueryBuilder<'_, Self>, value: Bind) {
+        match value {
+            Bind::Int(value) => query_builder.push_bind(value),
+            Bind::Float(value) => query_builder.push_bind(value),
+            Bind::Bool(value) => query_builder.push_bind(value),
+            Bind::Text(value) => query_builder.push_bind(value),
+        };
+    }
+
+    fn push_value(
+        query_builder: &mut QueryBuilder<'_, Self>,
+        column: &str,
+        column_type: &str,
+        value: &Value,
+    ) -> Result<(), InvalidValue> {
+        // Follows SQLite's type affinity rules for declared column types.
+        let bind = match value {
+            Value::Null => {
+                query_builder.push("null");
+                return Ok(());
+            }
+            Value::Array(_) | Value::Object(_) => Bind::Text(value.to_string()),
+            _ if column_type.starts_with("timestamp") || column_type.starts_with("datetime") => {
+                let value = to_timestamp(column, value)?;
+                Bind::Text(value.format(NAIVE_TIMESTAMP).to_string())
+            }
+            _ if column_type.contains("int") => to_int(column, value)?,
+            _ if column_type.contains("bool") => to_bool(column, value)?,
+            _ if ["real", "floa", "doub"]
+                .iter()
+                .any(|affinity| column_type.contains(affinity)) =>
+            {
+                to_float(column, value)?
+            }
+            _ => to_text(column, value)?,
+        };
+        Self::push_bind(query_builder, bind);
+        Ok(())
+    }
+
+    /// Searches the FTS5 table `{table}_fts`, whose rowids are those of
+    /// `table`; it decides which columns are searched.
+    fn push_text_match(
+        query_builder: &mut QueryBuilder<'_, Self>,
+        table: &str,
+        _columns: &[String],
+        index: &str,
+        term: &str,
+    ) {
+        let index = Self::quote_ident(index);
+        query_builder.push(format!(
+            "{}.rowid in (select rowid from {} where {} match ",
+            Self::quote_ident(table),
+            index,
+            index
+        ));
+        query_builder.push_bind(fts5_query(term));
+        query_builder.push(")");
+    }
+
+    /// The negated bm25 `rank` of FTS5, which is lower for better matches.
+    fn push_text_rank(
+        query_builder: &mut QueryBuilder<'_, Self>,
+        table: &str,
+        _columns: &[String],
+        index: &str,
+        term: &str,
+    ) {
+        let index = Self::quote_ident(index);
+        query_builder.push(format!(
+            "(select -rank from {} where {} match ",
+            index, index
+        ));
+        query_builder.push_bind(fts5_query(term));
+        query_builder.push(format!(" and rowid = {}.rowid)", Self::quote_ident(table)));
+    }
+}
+
+fn invalid(column: &str, message: &str) -> InvalidValue {
+    InvalidValue {
+        column: column.to_string(),
+        message: message.to_string(),
+    }
+}
+
+fn to_int(column: &str, value: &Value) -> Result<Bind, InvalidValue> {
+    match value {
+        Value::Number(number) => number.as_i64(),
+        Value::String(value) => value.parse().ok(),
+        _ => None,
+    }
+    .map(Bind::Int)
+    .ok_or_else(|| invalid(column, "expected an integer"))
+}
+
+fn to_float(column: &str, value: &Value) -> Result<Bind, InvalidValue> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(value) => value.parse().ok(),
+        _ => None,
+    }
+    .map(Bind::Float)
+    .ok_or_else(|| invalid(column, "expected a number"))
+}
+
+fn to_bool(column: &str, value: &Value) -> Result<Bind, InvalidValue> {
+    match value {
+        Value::Bool(value) => Some(*value),
+        Value::String(value) => value.parse().ok(),
+        _ => None,
+    }
+    .map(Bind::Bool)
+    .ok_or_else(|| invalid(column, "expected a boolean"))
+}
+
+/// How timestamps are written, in UTC.
+const NAIVE_TIMESTAMP: &str = "%Y-%m-%d %H:%M:%S%.f";
+
+fn to_timestamp(column: &str, value: &Value) -> Result<DateTime<Utc>, InvalidValue> {
+    TemporalFormat::current()
+        .parse_timestamp(value)
+        .ok_or_else(|| invalid(column, "expected a timestamp"))
+}
+
+fn to_text(column: &str, value: &Value) -> Result<Bind, InvalidValue> {
+    scalar_to_string(value)
+        .map(Bind::Text)
+        .ok_or_else(|| invalid(column, "expected a string"))
+}
+
+/// Lowercases `data_type` and collapses its whitespace, refusing anything
+/// but letters, digits, spaces and `_(),[]` so it can go into DDL as is.
+fn declared_type(data_type: &str) -> Option<String> {
+    let data_type = data_type
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .to_lowercase();
+    let plain = data_type.starts_with(|c: char| c.is_ascii_alphabetic())
+        && data_type
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || " _(),[]".contains(c));
+    plain.then_some(data_type)
+}
+
+fn sized(data_type: &str, length: Option<i32>) -> String {
+    match length {
+        Some(length) => format!("{}({})", data_type, length),
+        None => data_type.to_string(),
+    }
+}
+
+fn is_pg_text_type(udt_name: &str) -> bool {
+    matches!(udt_name, "text" | "varchar" | "bpchar" | "name" | "citext")
+}
+
+pub fn scalar_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::Bool(value) => Some(value.to_string()),
+        Value::Number(value) => Some(value.to_string()),
+        Value::String(value) => Some(value.clone()),
+        _ => None,
+    }
+}
+
+/// Renders a JSON array as a Postgres array literal, e.g. `{"a","b"}`.
+fn array_literal(items: &[Value]) -> Option<String> {
+    let items = items
+        .iter()
+        .map(|item| match item {
+            Value::Null => Some("NULL".to_string()),
+            Value::Array(items) => array_literal(items),
+            _ => scalar_to_string(item)
+                .map(|item| format!("\"{}\"", item.replace('\\', "\\\\").replace('"', "\\\""))),
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(format!("{{{}}}", items.join(",")))
+}
+
+/// Pushes `term` as a query in the text search configuration of the
+/// `to_tsvector` expression `document`, bound rather than copied from it.
+fn push_pg_query(query_builder: &mut QueryBuilder<'_, Postgres>, document: &str, term: &str) {
+    query_builder.push("websearch_to_tsquery(");
+    if let Some(config) = pg_regconfig(document) {
+        query_builder.push_bind(config);
+        query_builder.push("::regconfig, ");
+    }
+    query_builder.push_bind(term.to_string());
+    query_builder.push(")");
+}
+
+/// The configuration named by `to_tsvector('english'::regconfig, ...)`, as
+/// `pg_get_indexdef` prints it: a string literal, with its quotes doubled,
+/// cast to `regconfig`.
+pub(super) fn pg_regconfig(document: &str) -> Option<String> {
+    let mut chars = document.strip_prefix("to_tsvector('")?.char_indices();
+    let mut config = String::new();
+    while let Some((i, c)) = chars.next() {
+        if c != '\'' {
+            config.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some((_, '\'')) => config.push('\''),
+            _ => {
+                let rest = &document["to_tsvector('".len() + i + 1..];
+                return rest.starts_with("::regconfig,").then_some(config);
+            }
+        }
+    }
+    None
+}
+
+/// Quotes each word of `term`, so FTS5 reads none of it as query syntax.
+fn fts5_query(term: &str) -> String {
+    let words: Vec<String> = term
+        .split_whitespace()
+        .map(|word| format!("\"{}\"", word.replace('"', "\"\"")))
+        .collect();
+    words.join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_the_regconfig_of_an_indexed_document() {
+        assert_eq!(
+            pg_regconfig("to_tsvector('english'::regconfig, COALESCE(title, ''::text))").as_deref(),
+            Some("english")
+        );
+        assert_eq!(
+            pg_regconfig("to_tsvector('public.\"it''s\"'::regconfig, body)").as_deref(),
+            Some("public.\"it's\"")
+        );
+        assert_eq!(pg_regconfig("to_tsvector('english'::text, body)"), None);
+        assert_eq!(pg_regconfig("to_tsvector('english"), None);
+        assert_eq!(pg_regconfig("lower(body)"), None);
+    }
+}
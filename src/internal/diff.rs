@@ -0,0 +1,495 @@
+use sqlx::{MySql, Postgres, Sqlite};
+
+use crate::{
+    internal::db::{DBXError, Pool, DBX},
+    internal::ddl::{self, for_dialect, invalid, Ddl},
+    models::schema::{
+        Check, ColumnDiff, ForeignKey, Index, Schema, SchemaDiff, Table, TableDiff, VColumn,
+    },
+};
+
+impl DBX {
+    /// Compares the current schema with the tables of `desired`, returning
+    /// the differences and the DDL that turns one into the other, in the
+    /// order it has to run. Tables, columns, constraints and indexes missing
+    /// from `desired` are only dropped with `drop`. With `apply`, that DDL is run in one
+    /// transaction; MySQL commits every DDL statement on its own, so it
+    /// cannot apply a migration.
+    pub async fn migrate(
+        &self,
+        desired: &Schema,
+        drop: bool,
+        apply: bool,
+    ) -> Result<(SchemaDiff, Vec<String>), DBXError> {
+        if apply && matches!(self.pool, Pool::MySql(_)) {
+            return Err(invalid(
+                "MySQL cannot apply a migration in one transaction; run the DDL of a dry run instead"
+                    .to_string(),
+            ));
+        }
+        self.introspect(&[]).await?;
+        let live = self.schema();
+        let (diff, statements) = for_dialect!(&self.pool, migration(&live, desired, drop))?;
+        if apply && !statements.is_empty() {
+            self.execute_ddl(&statements).await?;
+        }
+        Ok((diff, statements))
+    }
+}
+
+fn migration<B: Ddl>(
+    live: &Schema,
+    desired: &Schema,
+    drop: bool,
+) -> Result<(SchemaDiff, Vec<String>), DBXError> {
+    let desired: Vec<Table> = desired.tables.iter().map(normalized).collect();
+    for (i, table) in desired.iter().enumerate() {
+        if desired[..i].iter().any(|t| t.name == table.name) {
+            return Err(invalid(format!("Table {} is declared twice", table.name)));
+        }
+    }
+    let find = |tables: &'_ [Table], name: &str| tables.iter().position(|t| t.name == name);
+    let added: Vec<&Table> = desired
+        .iter()
+        .filter(|table| find(&live.tables, &table.name).is_none())
+        .collect();
+    let removed: Vec<&Table> = live
+        .tables
+        .iter()
+        .filter(|table| drop && find(&desired, &table.name).is_none())
+        .collect();
+    let mut changed: Vec<(&Table, TableDiff)> = Vec::new();
+    for table in &desired {
+        if let Some(i) = find(&live.tables, &table.name) {
+            let mut diff = table_diff::<B>(&live.tables[i], table)?;
+            if !drop {
+                diff.removed_columns.clear();
+                diff.removed_foreign_keys.clear();
+                diff.removed_checks.clear();
+                diff.removed_indexes.clear();
+                // Nor is a unique index or constraint left out of `desired`.
+                for column in &mut diff.changed_columns {
+                    if column.from.is_unique && !column.to.is_unique {
+                        column.changes.retain(|change| *change != "is_unique");
+                    }
+                }
+                diff.changed_columns
+                    .retain(|column| !column.changes.is_empty());
+            }
+            if !is_empty(&diff) {
+                changed.push((table, diff));
+            }
+        }
+    }
+
+    let mut statements = Vec::new();
+    // Constraints go first, so nothing they depend on is gone yet.
+    for (_, diff) in &changed {
+        for foreign_key in &diff.removed_foreign_keys {
+            let name = foreign_key.name.as_deref().ok_or_else(|| {
+                invalid(format!("A foreign key of table {} has no name", diff.name))
+            })?;
+            statements.push(B::drop_foreign_key(&diff.name, name)?);
+        }
+        for check in &diff.removed_checks {
+            let name = check
+                .name
+                .as_deref()
+                .ok_or_else(|| invalid(format!("A check of table {} has no name", diff.name)))?;
+            statements.push(B::drop_check(&diff.name, name)?);
+        }
+        for index in &diff.removed_indexes {
+            statements.push(B::drop_index(&diff.name, &index.name));
+        }
+    }
+    for table in dependency_order(&removed).into_iter().rev() {
+        statements.push(ddl::drop_table::<B>(&table.name));
+    }
+    for table in dependency_order(&added) {
+        statements.extend(ddl::create_table::<B>(table, &live.name)?);
+    }
+    for (table, diff) in &changed {
+        for name in &diff.added_columns {
+            let column = table.columns.iter().find(|c| &c.name == name);
+            if let Some(column) = column {
+                statements.push(ddl::add_column::<B>(&diff.name, column, None, &live.name)?);
+            }
+        }
+        for column in &diff.changed_columns {
+            let unsupported: Vec<&str> = column
+                .changes
+                .iter()
+                .copied()
+                .filter(|change| {
+                    matches!(
+                        *change,
+                        "is_primary_key" | "is_unique" | "is_auto_increment"
+                    )
+                })
+                .collect();
+            if !unsupported.is_empty() {
+                return Err(invalid(format!(
+                    "Cannot change {} of column {}.{}",
+                    unsupported.join(", "),
+                    diff.name,
+                    column.name
+                )));
+            }
+            statements.extend(B::alter_column(
+                &diff.name,
+                &column.from,
+                &column.to,
+                &column.changes,
+            )?);
+        }
+        for name in &diff.removed_columns {
+            statements.push(ddl::drop_column::<B>(&diff.name, name));
+        }
+    }
+    for (_, diff) in &changed {
+        for foreign_key in &diff.added_foreign_keys {
+            let constraint =
+                ddl::foreign_key_constraint::<B>(foreign_key, &foreign_key.columns, &live.name)?;
+            statements.push(B::add_constraint(&diff.name, &constraint)?);
+        }
+        for check in &diff.added_checks {
+            statements.push(B::add_constraint(
+                &diff.name,
+                &ddl::check_constraint::<B>(check),
+            )?);
+        }
+        for index in &diff.added_indexes {
+            statements.push(ddl::create_index::<B>(&diff.name, index)?);
+        }
+    }
+
+    let diff = SchemaDiff {
+        added_tables: added.iter().map(|table| table.name.clone()).collect(),
+        removed_tables: removed.iter().map(|table| table.name.clone()).collect(),
+        changed_tables: changed.into_iter().map(|(_, diff)| diff).collect(),
+    };
+    Ok((diff, statements))
+}
+
+/// Spells out what a hand-written definition leaves implicit, the way
+/// introspection reports it.
+fn normalized(table: &Table) -> Table {
+    let mut table = table.clone();
+    if table.primary_key.is_empty() {
+        table.primary_key = table
+            .columns
+            .iter()
+            .filter(|column| column.is_primary_key)
+            .map(|column| column.name.clone())
+            .collect();
+    }
+    let sole_key = match table.primary_key.as_slice() {
+        [key] => Some(key.clone()),
+        _ => None,
+    };
+    for column in &mut table.columns {
+        column.is_primary_key = table.primary_key.contains(&column.name);
+        column.is_unique = column.is_unique
+            || sole_key.as_ref() == Some(&column.name)
+            || table
+                .indexes
+                .iter()
+                .any(|index| index.is_unique && index.columns == [column.name.as_str()]);
+    }
+    for foreign_key in &mut table.foreign_keys {
+        foreign_key.on_delete = foreign_key.on_delete.to_uppercase();
+        foreign_key.on_update = foreign_key.on_update.to_uppercase();
+    }
+    table
+}
+
+fn table_diff<B: Ddl>(live: &Table, desired: &Table) -> Result<TableDiff, DBXError> {
+    let mut diff = TableDiff {
+        name: desired.name.clone(),
+        ..TableDiff::default()
+    };
+    for column in &desired.columns {
+        match live.columns.iter().find(|c| c.name == column.name) {
+            Some(live_column) => {
+                let changes = column_changes::<B>(live_column, column)?;
+                if !changes.is_empty() {
+                    diff.changed_columns.push(ColumnDiff {
+                        name: column.name.clone(),
+                        changes,
+                        from: live_column.clone(),
+                        to: column.clone(),
+                    });
+                }
+            }
+            None => diff.added_columns.push(column.name.clone()),
+        }
+    }
+    diff.removed_columns = live
+        .columns
+        .iter()
+        .filter(|column| !desired.columns.iter().any(|c| c.name == column.name))
+        .map(|column| column.name.clone())
+        .collect();
+
+    diff.added_foreign_keys = unmatched(&desired.foreign_keys, &live.foreign_keys, |d, l| {
+        same_foreign_key(l, d)
+    });
+    diff.removed_foreign_keys = unmatched(&live.foreign_keys, &desired.foreign_keys, |l, d| {
+        same_foreign_key(l, d)
+    });
+
+    let live_indexes: Vec<Index> = explicit_indexes(live);
+    let desired_indexes: Vec<Index> = explicit_indexes(desired);
+    let same_index = |a: &Index, b: &Index| {
+        a.name == b.name && a.columns == b.columns && a.is_unique == b.is_unique
+    };
+    diff.added_indexes = unmatched(&desired_indexes, &live_indexes, same_index);
+    diff.removed_indexes = unmatched(&live_indexes, &desired_indexes, same_index);
+
+    // The database rewrites check expressions, so named checks are matched
+    // by name alone.
+    let same_check = |a: &Check, b: &Check| match (&a.name, &b.name) {
+        (Some(a), Some(b)) => a == b,
+        _ => a.expression.trim() == b.expression.trim(),
+    };
+    diff.added_checks = unmatched(&desired.checks, &live.checks, same_check);
+    diff.removed_checks = unmatched(&live.checks, &desired.checks, same_check);
+    Ok(diff)
+}
+
+fn column_changes<B: Ddl>(
+    live: &VColumn,
+    desired: &VColumn,
+) -> Result<Vec<&'static str>, DBXError> {
+    let live_type = B::native_type(&live.data_type, live.maximum_length)
+        .unwrap_or_else(|| live.data_type.to_lowercase());
+    let mut changes = Vec::new();
+    if B::reported_type(&live_type) != B::reported_type(&ddl::native_type::<B>(desired)?) {
+        changes.push("data_type");
+    }
+    // Keys are never null, whatever the backend reports for them.
+    let key = live.is_primary_key && desired.is_primary_key;
+    if !key && !live.is_nullable.eq_ignore_ascii_case(&desired.is_nullable) {
+        changes.push("is_nullable");
+    }
+    // Generated columns report defaults of their own making.
+    if !live.is_auto_increment
+        && !desired.is_auto_increment
+        && live.default_value.as_deref().map(B::reported_default)
+            != desired.default_value.as_deref().map(B::reported_default)
+    {
+        changes.push("default_value");
+    }
+    if live.is_primary_key != desired.is_primary_key {
+        changes.push("is_primary_key");
+    }
+    if live.is_unique != desired.is_unique {
+        changes.push("is_unique");
+    }
+    if live.is_auto_increment != desired.is_auto_increment {
+        changes.push("is_auto_increment");
+    }
+    Ok(changes)
+}
+
+/// Whether the `live` foreign key is what `desired` asks for; names are
+/// not compared, nor referenced columns or schema left out of `desired`.
+fn same_foreign_key(live: &ForeignKey, desired: &ForeignKey) -> bool {
+    live.columns == desired.columns
+        && live.referenced_table == desired.referenced_table
+        && (desired.referenced_schema.is_empty()
+            || live.referenced_schema == desired.referenced_schema)
+        && (desired.referenced_columns.is_empty()
+            || live.referenced_columns == desired.referenced_columns)
+        && live.on_delete.eq_ignore_ascii_case(&desired.on_delete)
+        && live.on_update.eq_ignore_ascii_case(&desired.on_update)
+}
+
+fn explicit_indexes(table: &Table) -> Vec<Index> {
+    table
+        .indexes
+        .iter()
+        .filter(|index| !ddl::is_implied(table, index))
+        .cloned()
+        .collect()
+}
+
+/// The items of `items` with no match in `others`.
+fn unmatched<T: Clone>(items: &[T], others: &[T], same: impl Fn(&T, &T) -> bool) -> Vec<T> {
+    items
+        .iter()
+        .filter(|item| !others.iter().any(|other| same(item, other)))
+        .cloned()
+        .collect()
+}
+
+fn is_empty(diff: &TableDiff) -> bool {
+    diff.added_columns.is_empty()
+        && diff.removed_columns.is_empty()
+        && diff.changed_columns.is_empty()
+        && diff.added_foreign_keys.is_empty()
+        && diff.removed_foreign_keys.is_empty()
+        && diff.added_indexes.is_empty()
+        && diff.removed_indexes.is_empty()
+        && diff.added_checks.is_empty()
+        && diff.removed_checks.is_empty()
+}
+
+/// Orders `tables` so each comes after the tables among them it
+/// references; cycles are left in the order given.
+fn dependency_order<'a>(tables: &[&'a Table]) -> Vec<&'a Table> {
+    let mut ordered: Vec<&Table> = Vec::with_capacity(tables.len());
+    let mut pending: Vec<&Table> = tables.to_vec();
+    while !pending.is_empty() {
+        let ready = pending.iter().position(|table| {
+            table.foreign_keys.iter().all(|foreign_key| {
+                foreign_key.referenced_table == table.name
+                    || !pending
+                        .iter()
+                        .any(|other| other.name == foreign_key.referenced_table)
+            })
+        });
+        ordered.push(pending.remove(ready.unwrap_or(0)));
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::{json, Value};
+
+    use super::*;
+
+    fn schema(tables: Value) -> Schema {
+        serde_json::from_value(json!({ "name": "public", "tables": tables })).unwrap()
+    }
+
+    fn column(name: &str, data_type: &str) -> Value {
+        json!({
+            "name": name,
+            "data_type": data_type,
+            "is_nullable": "YES",
+            "maximum_length": null,
+            "default_value": null,
+        })
+    }
+
+    fn key(name: &str) -> Value {
+        let mut key = column(name, "integer");
+        key["is_nullable"] = json!("NO");
+        key["is_primary_key"] = json!(true);
+        key["is_unique"] = json!(true);
+        key
+    }
+
+    fn unique(name: &str, data_type: &str) -> Value {
+        let mut unique = column(name, data_type);
+        unique["is_unique"] = json!(true);
+        unique
+    }
+
+    /// Two tables as Postgres introspection reports them.
+    fn live() -> Schema {
+        schema(json!([
+            {
+                "name": "authors",
+                "columns": [key("id"), column("name", "text")],
+                "primary_key": ["id"],
+                "indexes": [
+                    { "name": "authors_pkey", "columns": ["id"], "is_unique": true, "is_primary": true },
+                ],
+            },
+            {
+                "name": "books",
+                "columns": [key("id"), column("author_id", "integer"), unique("isbn", "text")],
+                "primary_key": ["id"],
+                "foreign_keys": [{
+                    "name": "books_author_id_fkey",
+                    "columns": ["author_id"],
+                    "referenced_schema": "public",
+                    "referenced_table": "authors",
+                    "referenced_columns": ["id"],
+                }],
+                "indexes": [
+                    { "name": "books_pkey", "columns": ["id"], "is_unique": true, "is_primary": true },
+                    { "name": "books_isbn_key", "columns": ["isbn"], "is_unique": true },
+                    { "name": "books_author_idx", "columns": ["author_id"] },
+                ],
+                "checks": [{ "name": "books_isbn_check", "expression": "(length(isbn) > 0)" }],
+            },
+        ]))
+    }
+
+    /// `books` with its columns only, and no `authors`.
+    fn columns_only() -> Schema {
+        schema(json!([{
+            "name": "books",
+            "columns": [key("id"), column("author_id", "integer"), column("isbn", "text")],
+        }]))
+    }
+
+    #[test]
+    fn finds_nothing_to_do_for_the_live_schema() {
+        let (diff, statements) = migration::<Postgres>(&live(), &live(), true).unwrap();
+        assert!(statements.is_empty(), "{:?}", statements);
+        assert!(diff.changed_tables.is_empty());
+    }
+
+    #[test]
+    fn keeps_what_the_document_leaves_out_without_drop() {
+        let (diff, statements) = migration::<Postgres>(&live(), &columns_only(), false).unwrap();
+        assert!(statements.is_empty(), "{:?}", statements);
+        assert!(diff.removed_tables.is_empty());
+        assert!(diff.changed_tables.is_empty());
+    }
+
+    #[test]
+    fn drops_what_the_document_leaves_out_with_drop() {
+        let mut desired = columns_only();
+        desired.tables[0].columns.pop();
+        let (diff, statements) = migration::<Postgres>(&live(), &desired, true).unwrap();
+        assert_eq!(diff.removed_tables, ["authors"]);
+        assert_eq!(
+            statements,
+            [
+                r#"alter table "books" drop constraint "books_author_id_fkey""#,
+                r#"alter table "books" drop constraint "books_isbn_check""#,
+                r#"drop index "books_author_idx""#,
+                r#"drop table "authors""#,
+                r#"alter table "books" drop column "isbn""#,
+            ]
+        );
+    }
+
+    #[test]
+    fn adds_what_the_document_declares() {
+        let desired = schema(json!([{
+            "name": "books",
+            "columns": [
+                key("id"),
+                column("author_id", "integer"),
+                column("isbn", "text"),
+                column("title", "text"),
+            ],
+            "indexes": [{ "name": "books_title_idx", "columns": ["title"] }],
+        }]));
+        let (diff, statements) = migration::<Postgres>(&live(), &desired, false).unwrap();
+        assert_eq!(diff.changed_tables[0].added_columns, ["title"]);
+        assert_eq!(
+            statements,
+            [
+                r#"alter table "books" add column "title" text"#,
+                r#"create index "books_title_idx" on "books" ("title")"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_changes_it_cannot_make() {
+        let mut desired = live();
+        desired.tables[1].columns[1].is_unique = true;
+        assert!(migration::<Postgres>(&live(), &desired, true).is_err());
+        desired.tables.push(desired.tables[0].clone());
+        assert!(migration::<Postgres>(&live(), &desired, false).is_err());
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
+
+use serde_json::Value;
+use sqlx::QueryBuilder;
+
+use crate::{
+    internal::db::{with_pool, DBXError, Pool, DBX},
+    internal::de::QueryResult,
+    internal::dialect::Dialect,
+    internal::filter::Expr,
+    models::schema::ForeignKey,
+};
+
+/// How many levels `expand` may nest, as in `comments.user.team`.
+pub const MAX_EXPAND_DEPTH: usize = 3;
+
+/// A foreign key followed from the rows of one table to another, resolved
+/// from the schema snapshot.
+#[derive(Debug)]
+pub struct Relation {
+    /// The key the related rows are embedded under.
+    pub name: String,
+    /// The related table.
+    pub table: String,
+    /// Whether the related table holds the foreign key, making each row
+    /// relate to many.
+    pub to_many: bool,
+    /// The column of the parent rows the relation joins on.
+    column: String,
+    /// The column of the related table that matches `column`.
+    related_column: String,
+    pub children: Vec<Relation>,
+}
+
+impl Relation {
+    /// Every table the relations reach.
+    pub fn tables(relations: &[Relation]) -> Vec<String> {
+        let mut tables = Vec::new();
+        for relation in relations {
+            tables.push(relation.table.clone());
+            tables.extend(Relation::tables(&relation.children));
+        }
+        tables.sort();
+        tables.dedup();
+        tables
+    }
+}
+
+type Expansion<'a> = Pin<Box<dyn Future<Output = Result<(), DBXError>> + Send + 'a>>;
+
+impl DBX {
+    /// Resolves a comma-separated list of relation paths such as
+    /// `author,comments.user` against the foreign keys of `table`.
+    ///
+    /// A single-column foreign key of `table` relates to one row, named after
+    /// its column without the `_id` suffix, or else after the table it
+    /// references. A foreign key of another table referencing `table` relates
+    /// to many rows, named after that table.
+    pub fn relations(&self, table: &str, expand: &str) -> Result<Vec<Relation>, DBXError> {
+        let mut relations: Vec<Relation> = Vec::new();
+        for path in expand
+            .split(',')
+            .map(str::trim)
+            .filter(|path| !path.is_empty())
+        {
+            let names: Vec<&str> = path.split('.').map(str::trim).collect();
+            if names.len() > MAX_EXPAND_DEPTH {
+                return Err(DBXError::ExpandTooDeep(path.to_string()));
+            }
+            let mut level = &mut relations;
+            let mut parent = table.to_string();
+            for name in names {
+                let i = match level.iter().position(|relation| relation.name == name) {
+                    Some(i) => i,
+                    None => {
+                        level.push(self.relation(&parent, name)?);
+                        level.len() - 1
+                    }
+                };
+                parent = level[i].table.clone();
+                level = &mut level[i].children;
+            }
+        }
+        Ok(relations)
+    }
+
+    fn relation(&self, table: &str, name: &str) -> Result<Relation, DBXError> {
+        let schema = self.schema();
+        let local = |foreign_key: &ForeignKey| {
+            foreign_key.columns.len() == 1
+                && (foreign_key.referenced_schema.is_empty()
+                    || foreign_key.referenced_schema == schema.name)
+        };
+        let to_one = self
+            .table(table)?
+            .foreign_keys
+            .into_iter()
+            .find(|foreign_key| {
+                local(foreign_key)
+                    && match foreign_key.columns[0].strip_suffix("_id") {
+                        Some(stripped) => stripped == name,
+                        None => foreign_key.referenced_table == name,
+                    }
+            });
+        if let Some(foreign_key) = to_one {
+            let related_column = match foreign_key.referenced_columns.as_slice() {
+                [column] => column.clone(),
+                _ => {
+                    let columns = self.columns(&foreign_key.referenced_table)?;
+                    self.primary_key(&foreign_key.referenced_table, &columns)?
+                }
+            };
+            return Ok(Relation {
+                name: name.to_string(),
+                table: foreign_key.referenced_table,
+                to_many: false,
+                column: foreign_key.columns[0].clone(),
+                related_column,
+                children: Vec::new(),
+            });
+        }
+
+        let to_many = schema
+            .tables
+            .iter()
+            .filter(|related| related.name == name)
+            .flat_map(|related| &related.foreign_keys)
+            .find(|foreign_key| local(foreign_key) && foreign_key.referenced_table == table);
+        if let Some(foreign_key) = to_many {
+            let column = match foreign_key.referenced_columns.as_slice() {
+                [column] => column.clone(),
+                _ => self.primary_key(table, &self.columns(table)?)?,
+            };
+            return Ok(Relation {
+                name: name.to_string(),
+                table: name.to_string(),
+                to_many: true,
+                column,
+                related_column: foreign_key.columns[0].clone(),
+                children: Vec::new(),
+            });
+        }
+        Err(DBXError::UnknownRelation(format!("{}.{}", table, name)))
+    }
+
+    /// Embeds the rows related to `rows` under the name of each relation:
+    /// an object, or `null`, for a single row and an array for many. Each
+    /// relation takes one query per level, whatever the number of rows;
+    /// `filters` restrict the rows of the tables they are keyed by.
+    pub fn expand<'a>(
+        &'a self,
+        rows: &'a mut [QueryResult],
+        relations: &'a [Relation],
+        filters: &'a HashMap<String, Expr>,
+    ) -> Expansion<'a> {
+        Box::pin(async move {
+            for relation in relations {
+                let mut keys: Vec<Value> = Vec::new();
+                for row in rows.iter() {
+                    match row.get(&relation.column) {
+                        Some(Value::Null) | None => {}
+                        Some(key) if keys.contains(key) => {}
+                        Some(key) => keys.push(key.clone()),
+                    }
+                }
+                let mut related = if keys.is_empty() {
+                    Vec::new()
+                } else {
+                    self.related(relation, &keys, filters).await?
+                };
+                self.expand(&mut related, &relation.children, filters)
+                    .await?;
+
+                let mut grouped: HashMap<String, Vec<Value>> = HashMap::new();
+                for row in related {
+                    let key = row
+                        .get(&relation.related_column)
+                        .map(Value::to_string)
+                        .unwrap_or_default();
+                    let row = serde_json::to_value(row).unwrap_or(Value::Null);
+                    grouped.entry(key).or_default().push(row);
+                }
+                for row in rows.iter_mut() {
+                    let matches = row
+                        .get(&relation.column)
+                        .and_then(|key| grouped.get(&key.to_string()));
+                    let value = match (relation.to_many, matches) {
+                        (true, Some(matches)) => Value::Array(matches.clone()),
+                        (true, None) => Value::Array(Vec::new()),
+                        (false, Some(matches)) => matches[0].clone(),
+                        (false, None) => Value::Null,
+                    };
+                    row.insert(relation.name.clone(), value);
+                }
+            }
+            Ok(())
+        })
+    }
+
+    async fn related(
+        &self,
+        relation: &Relation,
+        keys: &[Value],
+        filters: &HashMap<String, Expr>,
+    ) -> Result<Vec<QueryResult>, DBXError> {
+        let columns = self.columns(&relation.table)?;
+        let rows = with_pool!(&self.pool, pool => {
+            let mut query_builder = QueryBuilder::new("");
+            Self::related_query(
+                relation,
+                &columns,
+                keys,
+                filters.get(&relation.table),
+                &mut query_builder,
+            )?;
+            query_builder
+                .build_query_as::<QueryResult>()
+                .fetch_all(pool)
+                .await?
+        });
+        Ok(rows)
+    }
+
+    fn related_query<DB: Dialect>(
+        relation: &Relation,
+        columns: &HashMap<String, String>,
+        keys: &[Value],
+        filter: Option<&Expr>,
+        query_builder: &mut QueryBuilder<'_, DB>,
+    ) -> Result<(), DBXError> {
+        query_builder.push("select * from ");
+        query_builder.push(DB::quote_ident(&relation.table));
+        Self::push_where_ids(
+            query_builder,
+            &relation.related_column,
+            columns,
+            keys,
+            filter,
+        )
+    }
+}
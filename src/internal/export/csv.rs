@@ -0,0 +1,152 @@
+use std::io;
+
+use serde_json::Value;
+
+use crate::internal::de::QueryResult;
+use crate::internal::export::{text, Encoder, ExportColumn};
+
+/// RFC 4180 CSV with a header row; nulls are empty fields. Text that a
+/// spreadsheet would run as a formula is prefixed with `'`.
+pub(super) struct Csv {
+    columns: Vec<String>,
+    header: Option<Vec<u8>>,
+}
+
+impl Csv {
+    pub(super) fn new(columns: Vec<ExportColumn>) -> Self {
+        let columns: Vec<String> = columns.into_iter().map(|column| column.name).collect();
+        let names: Vec<String> = columns.iter().cloned().map(defused).collect();
+        let mut header = Vec::new();
+        write_record(&mut header, names.iter().map(String::as_str));
+        Csv {
+            columns,
+            header: Some(header),
+        }
+    }
+}
+
+impl Encoder for Csv {
+    fn row(&mut self, row: &QueryResult) -> io::Result<Vec<u8>> {
+        let mut out = self.header.take().unwrap_or_default();
+        let fields: Vec<String> = self
+            .columns
+            .iter()
+            .map(|column| match row.get(column) {
+                None | Some(Value::Null) => String::new(),
+                Some(Value::String(value)) => defused(value.clone()),
+                Some(value) => text(value),
+            })
+            .collect();
+        write_record(&mut out, fields.iter().map(String::as_str));
+        Ok(out)
+    }
+
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        Ok(self.header.take().unwrap_or_default())
+    }
+}
+
+/// `field` with a `'` in front when it starts like a formula, unless it
+/// is a number such as `-1.5`.
+fn defused(field: String) -> String {
+    let formula = field.starts_with(['=', '+', '-', '@', '\t', '\r']);
+    if formula && field.parse::<f64>().is_err() {
+        format!("'{}", field)
+    } else {
+        field
+    }
+}
+
+fn write_record<'a>(out: &mut Vec<u8>, fields: impl Iterator<Item = &'a str>) {
+    for (i, field) in fields.enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        if field.contains([',', '"', '\r', '\n']) {
+            out.push(b'"');
+            out.extend_from_slice(field.replace('"', "\"\"").as_bytes());
+            out.push(b'"');
+        } else {
+            out.extend_from_slice(field.as_bytes());
+        }
+    }
+    out.extend_from_slice(b"\r\n");
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::internal::export::{tests::encode, ExportFormat};
+
+    /// Reads RFC 4180 `csv` back into records.
+    fn parse(csv: &[u8]) -> Vec<Vec<String>> {
+        let csv = std::str::from_utf8(csv).unwrap();
+        let mut records = Vec::new();
+        let mut record = Vec::new();
+        let mut field = String::new();
+        let mut quoted = false;
+        let mut chars = csv.chars().peekable();
+        while let Some(c) = chars.next() {
+            match (c, quoted) {
+                ('"', true) if chars.peek() == Some(&'"') => {
+                    field.push('"');
+                    chars.next();
+                }
+                ('"', _) => quoted = !quoted,
+                (',', false) => record.push(std::mem::take(&mut field)),
+                ('\r', false) if chars.peek() == Some(&'\n') => {
+                    chars.next();
+                    record.push(std::mem::take(&mut field));
+                    records.push(std::mem::take(&mut record));
+                }
+                (c, _) => field.push(c),
+            }
+        }
+        assert!(field.is_empty() && record.is_empty(), "unterminated record");
+        records
+    }
+
+    #[test]
+    fn round_trips_fields() {
+        let csv = encode(
+            ExportFormat::Csv,
+            &[("id", "int4"), ("name", "text"), ("tags", "jsonb")],
+            vec![
+                vec![
+                    ("id", json!(1)),
+                    ("name", json!("a, \"b\"\r\nc")),
+                    ("tags", json!(["x"])),
+                ],
+                vec![("id", json!(2)), ("name", json!(null))],
+            ],
+        )
+        .unwrap();
+        assert_eq!(
+            parse(&csv),
+            [
+                vec!["id", "name", "tags"],
+                vec!["1", "a, \"b\"\r\nc", "[\"x\"]"],
+                vec!["2", "", ""],
+            ]
+        );
+    }
+
+    #[test]
+    fn defuses_formulas() {
+        let values = ["=1+2", "+1", "-1+2", "@sum(a1)", "-1.5", "a=b"];
+        let rows = values
+            .iter()
+            .map(|value| vec![("v", json!(value))])
+            .collect();
+        let csv = encode(ExportFormat::Csv, &[("v", "text")], rows).unwrap();
+        let fields: Vec<String> = parse(&csv).into_iter().skip(1).flatten().collect();
+        assert_eq!(fields, ["'=1+2", "+1", "'-1+2", "'@sum(a1)", "-1.5", "a=b"]);
+    }
+
+    #[test]
+    fn writes_the_header_without_rows() {
+        let csv = encode(ExportFormat::Csv, &[("a", "text"), ("b", "text")], vec![]).unwrap();
+        assert_eq!(parse(&csv), [vec!["a", "b"]]);
+    }
+}
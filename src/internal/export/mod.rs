@@ -0,0 +1,151 @@
+mod csv;
+mod parquet;
+mod xlsx;
+
+use std::io;
+
+use serde::Deserialize;
+use serde_json::Value;
+
+use crate::internal::{
+    db::{DBQuery, DBXError, DBX},
+    de::QueryResult,
+    search::RELEVANCE,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    Csv,
+    Xlsx,
+    Parquet,
+}
+
+impl ExportFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "csv",
+            ExportFormat::Xlsx => "xlsx",
+            ExportFormat::Parquet => "parquet",
+        }
+    }
+
+    pub fn content_type(self) -> &'static str {
+        match self {
+            ExportFormat::Csv => "text/csv; charset=utf-8",
+            ExportFormat::Xlsx => {
+                "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet"
+            }
+            ExportFormat::Parquet => "application/vnd.apache.parquet",
+        }
+    }
+
+    /// An encoder writing `columns` of each row in this format.
+    pub fn encoder(self, columns: Vec<ExportColumn>) -> Box<dyn Encoder> {
+        match self {
+            ExportFormat::Csv => Box::new(csv::Csv::new(columns)),
+            ExportFormat::Xlsx => Box::new(xlsx::Xlsx::new(columns)),
+            ExportFormat::Parquet => Box::new(parquet::Parquet::new(columns)),
+        }
+    }
+}
+
+/// A column of an export, with the type of the values it holds: the type
+/// `DBX::columns` reports for a table column, `json` for a value inside one
+/// and `float8` for the search relevance.
+#[derive(Debug, Clone)]
+pub struct ExportColumn {
+    pub name: String,
+    pub column_type: String,
+}
+
+/// Writes rows into a file a piece at a time, keeping the types the rows
+/// were decoded with where the format has them.
+pub trait Encoder {
+    /// Encodes `row`, returning the bytes of the file that are ready.
+    fn row(&mut self, row: &QueryResult) -> io::Result<Vec<u8>>;
+
+    /// Returns the rest of the file.
+    fn finish(&mut self) -> io::Result<Vec<u8>>;
+}
+
+impl DBX {
+    /// The columns selected by `query`, in order.
+    pub fn export_columns(&self, query: &DBQuery) -> Result<Vec<ExportColumn>, DBXError> {
+        let table = self.table(&query.table)?;
+        let mut columns: Vec<ExportColumn> = if query.fields.is_empty() {
+            table
+                .columns
+                .into_iter()
+                .map(|column| ExportColumn {
+                    name: column.name,
+                    column_type: column.column_type,
+                })
+                .collect()
+        } else {
+            query
+                .fields
+                .iter()
+                .map(|field| ExportColumn {
+                    name: field.name(),
+                    column_type: match field.path.is_empty() {
+                        true => table
+                            .columns
+                            .iter()
+                            .find(|column| column.name == field.column)
+                            .map(|column| column.column_type.clone())
+                            .unwrap_or_default(),
+                        false => "json".to_string(),
+                    },
+                })
+                .collect()
+        };
+        if query.search.is_some() {
+            columns.push(ExportColumn {
+                name: RELEVANCE.to_string(),
+                column_type: "float8".to_string(),
+            });
+        }
+        Ok(columns)
+    }
+}
+
+/// Values that have no counterpart in a format are written as JSON text.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `rows` of `(column, value)` pairs through a `format` encoder
+    /// for `columns` of `(name, type)`, returning the whole file.
+    pub(super) fn encode(
+        format: ExportFormat,
+        columns: &[(&str, &str)],
+        rows: Vec<Vec<(&str, Value)>>,
+    ) -> io::Result<Vec<u8>> {
+        let columns = columns
+            .iter()
+            .map(|(name, column_type)| ExportColumn {
+                name: name.to_string(),
+                column_type: column_type.to_string(),
+            })
+            .collect();
+        let mut encoder = format.encoder(columns);
+        let mut out = Vec::new();
+        for values in rows {
+            let mut row = QueryResult::default();
+            for (column, value) in values {
+                row.insert(column.to_string(), value);
+            }
+            out.extend(encoder.row(&row)?);
+        }
+        out.extend(encoder.finish()?);
+        Ok(out)
+    }
+}
@@ -0,0 +1,602 @@
+use std::io;
+
+use serde_json::Value;
+
+use crate::internal::de::QueryResult;
+use crate::internal::export::{text, Encoder, ExportColumn};
+use crate::internal::temporal::TemporalFormat;
+
+/// How many rows are held before they are written out as a row group.
+const ROW_GROUP_ROWS: usize = 10_000;
+
+const MAGIC: &[u8] = b"PAR1";
+
+// Parquet enums, as the format defines them.
+const DATA_PAGE: i32 = 0;
+const PLAIN: i32 = 0;
+const RLE: i32 = 3;
+const OPTIONAL: i32 = 1;
+const UNCOMPRESSED: i32 = 0;
+const UINT_64: i32 = 14;
+
+/// Uncompressed, plain encoded Parquet with every column optional. Column
+/// types follow the SQL types of the columns, and a value that does not fit
+/// its column fails the export.
+pub(super) struct Parquet {
+    columns: Vec<String>,
+    kinds: Vec<Kind>,
+    /// The signs of the integers in each column so far.
+    signs: Vec<Signs>,
+    /// How the timestamps of the rows are written.
+    format: TemporalFormat,
+    rows: Vec<Vec<Value>>,
+    offset: u64,
+    row_groups: Vec<RowGroup>,
+}
+
+struct RowGroup {
+    rows: usize,
+    chunks: Vec<Chunk>,
+}
+
+struct Chunk {
+    offset: u64,
+    size: u64,
+}
+
+impl Parquet {
+    pub(super) fn new(columns: Vec<ExportColumn>) -> Self {
+        Parquet {
+            kinds: columns
+                .iter()
+                .map(|column| Kind::of(&column.column_type))
+                .collect(),
+            signs: vec![Signs::default(); columns.len()],
+            columns: columns.into_iter().map(|column| column.name).collect(),
+            format: TemporalFormat::current(),
+            rows: Vec::new(),
+            offset: 0,
+            row_groups: Vec::new(),
+        }
+    }
+
+    /// Writes the rows held as a row group, a column chunk of one page each.
+    fn flush(&mut self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        if self.offset == 0 {
+            out.extend(MAGIC);
+            self.offset = MAGIC.len() as u64;
+        }
+        if self.rows.is_empty() {
+            return Ok(out);
+        }
+        let rows = &self.rows;
+        let mut chunks = Vec::with_capacity(self.kinds.len());
+        for (i, kind) in self.kinds.iter().enumerate() {
+            let values = rows.iter().map(|row| &row[i]);
+            let page = kind.page(&self.columns[i], values, self.format, &mut self.signs[i])?;
+            let size = i32::try_from(page.len())
+                .map_err(|_| io::Error::other("A Parquet page is larger than 2 GiB"))?;
+            let header = page_header(rows.len(), size);
+            let chunk = Chunk {
+                offset: self.offset,
+                size: (header.len() + page.len()) as u64,
+            };
+            self.offset += chunk.size;
+            out.extend(header);
+            out.extend(page);
+            chunks.push(chunk);
+        }
+        self.row_groups.push(RowGroup {
+            rows: rows.len(),
+            chunks,
+        });
+        self.rows.clear();
+        Ok(out)
+    }
+
+    fn footer(&self) -> Vec<u8> {
+        let kinds = &self.kinds;
+        let mut t = Thrift::default();
+        t.i32(1, 1);
+        t.list(2, STRUCT, self.columns.len() + 1);
+        t.enter();
+        t.binary(4, b"schema");
+        t.i32(5, self.columns.len() as i32);
+        t.end();
+        for ((column, kind), signs) in self.columns.iter().zip(kinds).zip(&self.signs) {
+            t.enter();
+            t.i32(1, kind.physical());
+            t.i32(3, OPTIONAL);
+            t.binary(4, column.as_bytes());
+            if signs.unsigned {
+                t.i32(6, UINT_64);
+            } else if let Some(converted) = kind.converted() {
+                t.i32(6, converted);
+            }
+            t.end();
+        }
+        t.i64(
+            3,
+            self.row_groups.iter().map(|group| group.rows as i64).sum(),
+        );
+        t.list(4, STRUCT, self.row_groups.len());
+        for group in &self.row_groups {
+            t.enter();
+            t.list(1, STRUCT, group.chunks.len());
+            for (chunk, (column, kind)) in group.chunks.iter().zip(self.columns.iter().zip(kinds)) {
+                t.enter();
+                t.i64(2, chunk.offset as i64);
+                t.begin(3);
+                t.i32(1, kind.physical());
+                t.list(2, I32, 2);
+                t.element(PLAIN);
+                t.element(RLE);
+                t.list(3, BINARY, 1);
+                t.bytes(column.as_bytes());
+                t.i32(4, UNCOMPRESSED);
+                t.i64(5, group.rows as i64);
+                t.i64(6, chunk.size as i64);
+                t.i64(7, chunk.size as i64);
+                t.i64(9, chunk.offset as i64);
+                t.end();
+                t.end();
+            }
+            t.i64(2, group.chunks.iter().map(|chunk| chunk.size as i64).sum());
+            t.i64(3, group.rows as i64);
+            t.end();
+        }
+        t.binary(6, b"penkr");
+        t.end();
+        t.out
+    }
+}
+
+impl Encoder for Parquet {
+    fn row(&mut self, row: &QueryResult) -> io::Result<Vec<u8>> {
+        let values = self
+            .columns
+            .iter()
+            .map(|column| row.get(column).cloned().unwrap_or(Value::Null))
+            .collect();
+        self.rows.push(values);
+        if self.rows.len() == ROW_GROUP_ROWS {
+            self.flush()
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        let mut out = self.flush()?;
+        let footer = self.footer();
+        out.extend(&footer);
+        out.extend((footer.len() as u32).to_le_bytes());
+        out.extend(MAGIC);
+        Ok(out)
+    }
+}
+
+/// Whether a column of `Kind::Int64` has held negative integers, and
+/// integers past `i64::MAX`, as MySQL's `bigint unsigned` can. Both are
+/// written as 64 bits, so a column of the latter alone is declared
+/// unsigned.
+#[derive(Debug, Clone, Copy, Default)]
+struct Signs {
+    negative: bool,
+    unsigned: bool,
+}
+
+/// The type a column is written as.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Boolean,
+    Int64,
+    Double,
+    /// Microseconds since the Unix epoch, in UTC.
+    Timestamp,
+    Utf8,
+    Json,
+}
+
+impl Kind {
+    /// The type of a column of `column_type`, as `DBX::columns` reports it
+    /// on any backend. Types without a counterpart, such as decimals and
+    /// dates, are written as the text they are decoded to.
+    fn of(column_type: &str) -> Kind {
+        let column_type = column_type.to_lowercase();
+        let base = column_type.split('(').next().unwrap_or_default().trim();
+        match base {
+            "bool" | "boolean" => Kind::Boolean,
+            "int2" | "int4" | "int8" | "tinyint" | "smallint" | "mediumint" | "int" | "integer"
+            | "bigint" | "year" => Kind::Int64,
+            "float4" | "float8" | "float" | "real" | "double" | "double precision" => Kind::Double,
+            "timestamp" | "timestamptz" | "datetime" => Kind::Timestamp,
+            "json" | "jsonb" => Kind::Json,
+            _ => Kind::Utf8,
+        }
+    }
+
+    fn physical(self) -> i32 {
+        match self {
+            Kind::Boolean => 0,
+            Kind::Int64 | Kind::Timestamp => 2,
+            Kind::Double => 5,
+            Kind::Utf8 | Kind::Json => 6,
+        }
+    }
+
+    fn converted(self) -> Option<i32> {
+        match self {
+            Kind::Utf8 => Some(0),
+            Kind::Timestamp => Some(10),
+            Kind::Json => Some(19),
+            _ => None,
+        }
+    }
+
+    /// A data page of the `values` of `column`: definition levels, then the
+    /// values that are not null. Timestamps are read in `format`, and the
+    /// signs of integers are added to `signs`.
+    fn page<'a>(
+        self,
+        column: &str,
+        values: impl Iterator<Item = &'a Value>,
+        format: TemporalFormat,
+        signs: &mut Signs,
+    ) -> io::Result<Vec<u8>> {
+        let mut defined = Vec::new();
+        let mut booleans = Vec::new();
+        let mut data = Vec::new();
+        for value in values {
+            let fits = match (self, value) {
+                (_, Value::Null) => {
+                    defined.push(false);
+                    continue;
+                }
+                (Kind::Boolean, Value::Bool(value)) => {
+                    booleans.push(*value);
+                    true
+                }
+                (Kind::Int64, Value::Number(number)) => match (number.as_i64(), number.as_u64()) {
+                    (Some(number), _) => {
+                        signs.negative |= number < 0;
+                        data.extend(number.to_le_bytes());
+                        true
+                    }
+                    (None, Some(number)) => {
+                        signs.unsigned = true;
+                        data.extend(number.to_le_bytes());
+                        true
+                    }
+                    _ => false,
+                },
+                (Kind::Double, Value::Number(number)) => number
+                    .as_f64()
+                    .map(|number| data.extend(number.to_le_bytes()))
+                    .is_some(),
+                (Kind::Timestamp, value) => format
+                    .parse_timestamp(value)
+                    .map(|value| data.extend(value.timestamp_micros().to_le_bytes()))
+                    .is_some(),
+                (Kind::Utf8, value) => {
+                    push_binary(&mut data, &text(value));
+                    true
+                }
+                (Kind::Json, value) => {
+                    push_binary(&mut data, &value.to_string());
+                    true
+                }
+                _ => false,
+            };
+            if !fits {
+                return Err(io::Error::other(format!(
+                    "Column {} holds a value that is not {:?}",
+                    column, self
+                )));
+            }
+            defined.push(true);
+        }
+        if signs.negative && signs.unsigned {
+            return Err(io::Error::other(format!(
+                "Column {} holds integers both below 0 and above {}",
+                column,
+                i64::MAX
+            )));
+        }
+        if self == Kind::Boolean {
+            data = bit_pack(&booleans);
+        }
+        // A single bit-packed run of the levels, each group of 8 one byte.
+        let levels = bit_pack(&defined);
+        let mut run = Vec::new();
+        varint(&mut run, ((levels.len() as u64) << 1) | 1);
+        run.extend(levels);
+        let mut page = Vec::with_capacity(4 + run.len() + data.len());
+        page.extend((run.len() as u32).to_le_bytes());
+        page.extend(run);
+        page.extend(data);
+        Ok(page)
+    }
+}
+
+fn push_binary(data: &mut Vec<u8>, value: &str) {
+    data.extend((value.len() as u32).to_le_bytes());
+    data.extend(value.as_bytes());
+}
+
+fn page_header(values: usize, size: i32) -> Vec<u8> {
+    let mut t = Thrift::default();
+    t.i32(1, DATA_PAGE);
+    t.i32(2, size);
+    t.i32(3, size);
+    t.begin(5);
+    t.i32(1, values as i32);
+    t.i32(2, PLAIN);
+    t.i32(3, RLE);
+    t.i32(4, RLE);
+    t.end();
+    t.end();
+    t.out
+}
+
+/// Packs `bits` eight to a byte, least significant first.
+fn bit_pack(bits: &[bool]) -> Vec<u8> {
+    let mut bytes = vec![0; bits.len().div_ceil(8)];
+    for (i, _) in bits.iter().enumerate().filter(|(_, bit)| **bit) {
+        bytes[i / 8] |= 1 << (i % 8);
+    }
+    bytes
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+// Thrift compact protocol types.
+const I32: u8 = 5;
+const I64: u8 = 6;
+const BINARY: u8 = 8;
+const LIST: u8 = 9;
+const STRUCT: u8 = 12;
+
+/// Writes Thrift compact protocol structs, as much of it as Parquet
+/// metadata needs. Fields must come in increasing order, at most 15 apart.
+#[derive(Default)]
+struct Thrift {
+    out: Vec<u8>,
+    last: i16,
+    outer: Vec<i16>,
+}
+
+impl Thrift {
+    fn field(&mut self, id: i16, kind: u8) {
+        self.out.push((((id - self.last) as u8) << 4) | kind);
+        self.last = id;
+    }
+
+    fn i32(&mut self, id: i16, value: i32) {
+        self.field(id, I32);
+        self.element(value);
+    }
+
+    fn i64(&mut self, id: i16, value: i64) {
+        self.field(id, I64);
+        varint(&mut self.out, ((value << 1) ^ (value >> 63)) as u64);
+    }
+
+    fn binary(&mut self, id: i16, value: &[u8]) {
+        self.field(id, BINARY);
+        self.bytes(value);
+    }
+
+    fn list(&mut self, id: i16, kind: u8, len: usize) {
+        self.field(id, LIST);
+        if len < 15 {
+            self.out.push(((len as u8) << 4) | kind);
+        } else {
+            self.out.push(0xf0 | kind);
+            varint(&mut self.out, len as u64);
+        }
+    }
+
+    /// A struct field; its fields follow, then `end`.
+    fn begin(&mut self, id: i16) {
+        self.field(id, STRUCT);
+        self.enter();
+    }
+
+    /// A struct in a list.
+    fn enter(&mut self) {
+        self.outer.push(self.last);
+        self.last = 0;
+    }
+
+    fn end(&mut self) {
+        self.out.push(0);
+        self.last = self.outer.pop().unwrap_or(0);
+    }
+
+    /// An i32 in a list.
+    fn element(&mut self, value: i32) {
+        varint(&mut self.out, ((value << 1) ^ (value >> 31)) as u32 as u64);
+    }
+
+    /// A binary in a list.
+    fn bytes(&mut self, value: &[u8]) {
+        varint(&mut self.out, value.len() as u64);
+        self.out.extend(value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use parquet::basic::{ConvertedType, Type};
+    use parquet::file::reader::{FileReader, SerializedFileReader};
+    use parquet::record::Field;
+    use serde_json::{json, Value};
+
+    use crate::internal::export::{tests::encode, ExportFormat};
+
+    /// A column's name, physical type and converted type.
+    type Element = (String, Type, ConvertedType);
+
+    /// Reads `parquet` back with the `parquet` crate, into its schema, the
+    /// number of its row groups and its rows.
+    fn read(parquet: Vec<u8>) -> (Vec<Element>, usize, Vec<Vec<Value>>) {
+        let reader = SerializedFileReader::new(Bytes::from(parquet)).unwrap();
+        let metadata = reader.metadata();
+        let schema = metadata
+            .file_metadata()
+            .schema_descr()
+            .columns()
+            .iter()
+            .map(|column| {
+                let name = column.name().to_string();
+                (name, column.physical_type(), column.converted_type())
+            })
+            .collect();
+        let rows = reader
+            .get_row_iter(None)
+            .unwrap()
+            .map(|row| {
+                row.unwrap()
+                    .get_column_iter()
+                    .map(|(_, field)| match field {
+                        Field::Null => Value::Null,
+                        Field::Bool(value) => json!(value),
+                        Field::Long(value) | Field::TimestampMicros(value) => json!(value),
+                        Field::ULong(value) => json!(value),
+                        Field::Double(value) => json!(value),
+                        Field::Str(value) => json!(value),
+                        field => panic!("unexpected field {:?}", field),
+                    })
+                    .collect()
+            })
+            .collect();
+        (schema, metadata.num_row_groups(), rows)
+    }
+
+    #[test]
+    fn round_trips_typed_columns() {
+        let parquet = encode(
+            ExportFormat::Parquet,
+            &[
+                ("id", "int8"),
+                ("ok", "bool"),
+                ("score", "float8"),
+                ("at", "timestamptz"),
+                ("name", "varchar(20)"),
+                ("doc", "jsonb"),
+                ("price", "numeric"),
+            ],
+            vec![
+                vec![
+                    ("id", json!(1)),
+                    ("ok", json!(true)),
+                    ("score", json!(2)),
+                    ("at", json!(1_700_000_000)),
+                    ("name", json!("ann")),
+                    ("doc", json!("text")),
+                    ("price", json!("9.99")),
+                ],
+                vec![("id", json!(2)), ("ok", json!(false)), ("at", json!(null))],
+            ],
+        )
+        .unwrap();
+        let (schema, _, rows) = read(parquet);
+        let schema: Vec<(&str, Type, ConvertedType)> = schema
+            .iter()
+            .map(|(name, physical, converted)| (name.as_str(), *physical, *converted))
+            .collect();
+        assert_eq!(
+            schema,
+            [
+                ("id", Type::INT64, ConvertedType::NONE),
+                ("ok", Type::BOOLEAN, ConvertedType::NONE),
+                ("score", Type::DOUBLE, ConvertedType::NONE),
+                ("at", Type::INT64, ConvertedType::TIMESTAMP_MICROS),
+                ("name", Type::BYTE_ARRAY, ConvertedType::UTF8),
+                ("doc", Type::BYTE_ARRAY, ConvertedType::JSON),
+                ("price", Type::BYTE_ARRAY, ConvertedType::UTF8),
+            ]
+        );
+        assert_eq!(
+            rows,
+            [
+                vec![
+                    json!(1),
+                    json!(true),
+                    json!(2.0),
+                    json!(1_700_000_000_000_000i64),
+                    json!("ann"),
+                    json!("\"text\""),
+                    json!("9.99"),
+                ],
+                vec![
+                    json!(2),
+                    json!(false),
+                    json!(null),
+                    json!(null),
+                    json!(null),
+                    json!(null),
+                    json!(null),
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn splits_row_groups() {
+        let rows = (0..super::ROW_GROUP_ROWS as i64 + 1)
+            .map(|i| vec![("id", json!(i))])
+            .collect();
+        let parquet = encode(ExportFormat::Parquet, &[("id", "int4")], rows).unwrap();
+        let (_, groups, rows) = read(parquet);
+        assert_eq!(groups, 2);
+        let ids: Vec<i64> = rows.iter().map(|row| row[0].as_i64().unwrap()).collect();
+        assert_eq!(
+            ids,
+            (0..super::ROW_GROUP_ROWS as i64 + 1).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn writes_a_file_without_rows() {
+        let parquet = encode(ExportFormat::Parquet, &[("id", "int4")], vec![]).unwrap();
+        let (schema, _, rows) = read(parquet);
+        assert_eq!(
+            schema,
+            [("id".to_string(), Type::INT64, ConvertedType::NONE)]
+        );
+        assert!(rows.is_empty());
+    }
+
+    #[test]
+    fn writes_unsigned_integers_past_i64() {
+        let rows = vec![vec![("id", json!(1))], vec![("id", json!(u64::MAX))]];
+        let parquet = encode(ExportFormat::Parquet, &[("id", "bigint")], rows).unwrap();
+        let (schema, _, rows) = read(parquet);
+        assert_eq!(schema[0].2, ConvertedType::UINT_64);
+        assert_eq!(rows, [vec![json!(1)], vec![json!(u64::MAX)]]);
+
+        let rows = vec![vec![("id", json!(-1))], vec![("id", json!(u64::MAX))]];
+        let error = encode(ExportFormat::Parquet, &[("id", "bigint")], rows).unwrap_err();
+        assert!(error.to_string().contains("both below 0"));
+    }
+
+    #[test]
+    fn rejects_values_of_another_type() {
+        let rows = vec![vec![("id", json!("one"))]];
+        let error = encode(ExportFormat::Parquet, &[("id", "int4")], rows).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Column id holds a value that is not Int64"
+        );
+    }
+}
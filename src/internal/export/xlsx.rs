@@ -0,0 +1,371 @@
+use std::io::{self, Write};
+
+use flate2::write::DeflateEncoder;
+use flate2::Compression;
+use serde_json::Value;
+
+use crate::internal::de::QueryResult;
+use crate::internal::export::{text, Encoder, ExportColumn};
+
+/// The most rows a worksheet holds, header included.
+const MAX_ROWS: usize = 1_048_576;
+
+const CONTENT_TYPES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Types xmlns="http://schemas.openxmlformats.org/package/2006/content-types"><Default Extension="rels" ContentType="application/vnd.openxmlformats-package.relationships+xml"/><Default Extension="xml" ContentType="application/xml"/><Override PartName="/xl/workbook.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.sheet.main+xml"/><Override PartName="/xl/worksheets/sheet1.xml" ContentType="application/vnd.openxmlformats-officedocument.spreadsheetml.worksheet+xml"/></Types>"#;
+
+const RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument" Target="xl/workbook.xml"/></Relationships>"#;
+
+const WORKBOOK: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<workbook xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships"><sheets><sheet name="Sheet1" sheetId="1" r:id="rId1"/></sheets></workbook>"#;
+
+const WORKBOOK_RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/worksheet" Target="worksheets/sheet1.xml"/></Relationships>"#;
+
+const SHEET_START: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<worksheet xmlns="http://schemas.openxmlformats.org/spreadsheetml/2006/main"><sheetData>"#;
+
+const SHEET_END: &str = "</sheetData></worksheet>";
+
+/// A workbook of one sheet, with a header row and a row per record.
+/// Numbers and booleans are typed cells, everything else inline strings.
+pub(super) struct Xlsx {
+    columns: Vec<String>,
+    zip: Zip,
+    rows: usize,
+}
+
+impl Xlsx {
+    pub(super) fn new(columns: Vec<ExportColumn>) -> Self {
+        Xlsx {
+            columns: columns.into_iter().map(|column| column.name).collect(),
+            zip: Zip::default(),
+            rows: 0,
+        }
+    }
+
+    fn start(&mut self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        for (name, content) in [
+            ("[Content_Types].xml", CONTENT_TYPES),
+            ("_rels/.rels", RELS),
+            ("xl/workbook.xml", WORKBOOK),
+            ("xl/_rels/workbook.xml.rels", WORKBOOK_RELS),
+        ] {
+            out.extend(self.zip.start_file(name));
+            out.extend(self.zip.write(content.as_bytes())?);
+            out.extend(self.zip.finish_file()?);
+        }
+        out.extend(self.zip.start_file("xl/worksheets/sheet1.xml"));
+        let mut header = SHEET_START.to_string();
+        header.push_str("<row>");
+        for column in &self.columns {
+            push_string(&mut header, column);
+        }
+        header.push_str("</row>");
+        out.extend(self.zip.write(header.as_bytes())?);
+        self.rows = 1;
+        Ok(out)
+    }
+}
+
+impl Encoder for Xlsx {
+    fn row(&mut self, row: &QueryResult) -> io::Result<Vec<u8>> {
+        let mut out = if self.rows == 0 {
+            self.start()?
+        } else {
+            Vec::new()
+        };
+        if self.rows == MAX_ROWS {
+            return Err(io::Error::other(format!(
+                "A worksheet holds at most {} rows",
+                MAX_ROWS
+            )));
+        }
+        let mut xml = String::from("<row>");
+        for column in &self.columns {
+            match row.get(column) {
+                None | Some(Value::Null) => xml.push_str("<c/>"),
+                Some(Value::Number(number)) => {
+                    xml.push_str(&format!("<c><v>{}</v></c>", number));
+                }
+                Some(Value::Bool(value)) => {
+                    xml.push_str(&format!("<c t=\"b\"><v>{}</v></c>", *value as u8));
+                }
+                Some(value) => push_string(&mut xml, &text(value)),
+            }
+        }
+        xml.push_str("</row>");
+        out.extend(self.zip.write(xml.as_bytes())?);
+        self.rows += 1;
+        Ok(out)
+    }
+
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        let mut out = if self.rows == 0 {
+            self.start()?
+        } else {
+            Vec::new()
+        };
+        out.extend(self.zip.write(SHEET_END.as_bytes())?);
+        out.extend(self.zip.finish_file()?);
+        out.extend(self.zip.finish()?);
+        Ok(out)
+    }
+}
+
+fn push_string(xml: &mut String, value: &str) {
+    xml.push_str("<c t=\"inlineStr\"><is><t xml:space=\"preserve\">");
+    for c in value.chars() {
+        match c {
+            '&' => xml.push_str("&amp;"),
+            '<' => xml.push_str("&lt;"),
+            '>' => xml.push_str("&gt;"),
+            '"' => xml.push_str("&quot;"),
+            // Control characters other than tab and newlines are not XML.
+            '\t' | '\n' | '\r' => xml.push(c),
+            c if c < ' ' => {}
+            c => xml.push(c),
+        }
+    }
+    xml.push_str("</t></is></c>");
+}
+
+/// A zip archive written front to back, each entry deflated as it comes
+/// and followed by a data descriptor, as its size is not known up front.
+#[derive(Default)]
+struct Zip {
+    offset: u64,
+    entries: Vec<ZipEntry>,
+    current: Option<(ZipEntry, DeflateEncoder<Vec<u8>>, crc32fast::Hasher)>,
+}
+
+struct ZipEntry {
+    name: String,
+    offset: u64,
+    crc: u32,
+    compressed: u64,
+    size: u64,
+}
+
+const DATA_DESCRIPTOR: u16 = 0x0008;
+const DEFLATE: u16 = 8;
+const VERSION: u16 = 20;
+
+impl Zip {
+    fn start_file(&mut self, name: &str) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend(0x04034b50u32.to_le_bytes());
+        out.extend(VERSION.to_le_bytes());
+        out.extend(DATA_DESCRIPTOR.to_le_bytes());
+        out.extend(DEFLATE.to_le_bytes());
+        out.extend([0; 4]); // modification time and date
+        out.extend([0; 12]); // crc and sizes, in the data descriptor
+        out.extend((name.len() as u16).to_le_bytes());
+        out.extend(0u16.to_le_bytes()); // extra field length
+        out.extend(name.as_bytes());
+        let entry = ZipEntry {
+            name: name.to_string(),
+            offset: self.offset,
+            crc: 0,
+            compressed: 0,
+            size: 0,
+        };
+        self.current = Some((
+            entry,
+            DeflateEncoder::new(Vec::new(), Compression::default()),
+            crc32fast::Hasher::new(),
+        ));
+        self.offset += out.len() as u64;
+        out
+    }
+
+    fn write(&mut self, data: &[u8]) -> io::Result<Vec<u8>> {
+        let (entry, encoder, hasher) = self.current.as_mut().ok_or_else(no_file)?;
+        hasher.update(data);
+        entry.size += data.len() as u64;
+        encoder.write_all(data)?;
+        let out = std::mem::take(encoder.get_mut());
+        entry.compressed += out.len() as u64;
+        self.offset += out.len() as u64;
+        Ok(out)
+    }
+
+    fn finish_file(&mut self) -> io::Result<Vec<u8>> {
+        let (mut entry, encoder, hasher) = self.current.take().ok_or_else(no_file)?;
+        let mut out = encoder.finish()?;
+        entry.compressed += out.len() as u64;
+        entry.crc = hasher.finalize();
+        out.extend(0x08074b50u32.to_le_bytes());
+        out.extend(entry.crc.to_le_bytes());
+        out.extend(size32(entry.compressed)?.to_le_bytes());
+        out.extend(size32(entry.size)?.to_le_bytes());
+        self.offset += out.len() as u64;
+        self.entries.push(entry);
+        Ok(out)
+    }
+
+    /// The central directory, ending the archive.
+    fn finish(&mut self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        for entry in &self.entries {
+            out.extend(0x02014b50u32.to_le_bytes());
+            out.extend(VERSION.to_le_bytes()); // made by
+            out.extend(VERSION.to_le_bytes()); // needed to extract
+            out.extend(DATA_DESCRIPTOR.to_le_bytes());
+            out.extend(DEFLATE.to_le_bytes());
+            out.extend([0; 4]); // modification time and date
+            out.extend(entry.crc.to_le_bytes());
+            out.extend(size32(entry.compressed)?.to_le_bytes());
+            out.extend(size32(entry.size)?.to_le_bytes());
+            out.extend((entry.name.len() as u16).to_le_bytes());
+            out.extend([0; 12]); // extra, comment, disk, attributes
+            out.extend(size32(entry.offset)?.to_le_bytes());
+            out.extend(entry.name.as_bytes());
+        }
+        let count = self.entries.len() as u16;
+        out.extend(0x06054b50u32.to_le_bytes());
+        out.extend([0; 4]); // disk numbers
+        out.extend(count.to_le_bytes());
+        out.extend(count.to_le_bytes());
+        out.extend(size32(out.len() as u64 - 12)?.to_le_bytes());
+        out.extend(size32(self.offset)?.to_le_bytes());
+        out.extend(0u16.to_le_bytes()); // comment length
+        Ok(out)
+    }
+}
+
+fn no_file() -> io::Error {
+    io::Error::other("No zip entry is open")
+}
+
+/// Sizes and offsets past 4 GiB would need ZIP64.
+fn size32(size: u64) -> io::Result<u32> {
+    u32::try_from(size).map_err(|_| io::Error::other("The workbook is larger than 4 GiB"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use flate2::read::DeflateDecoder;
+    use serde_json::json;
+
+    use crate::internal::export::{tests::encode, ExportFormat};
+
+    fn u16_at(data: &[u8], at: usize) -> usize {
+        u16::from_le_bytes([data[at], data[at + 1]]) as usize
+    }
+
+    fn u32_at(data: &[u8], at: usize) -> u32 {
+        u32::from_le_bytes(data[at..at + 4].try_into().unwrap())
+    }
+
+    /// Reads the entries of `zip` through its central directory, checking
+    /// each against its local header, data descriptor and CRC.
+    fn unzip(zip: &[u8]) -> Vec<(String, String)> {
+        let end = zip.len() - 22;
+        assert_eq!(u32_at(zip, end), 0x06054b50);
+        let count = u16_at(zip, end + 10);
+        let mut at = u32_at(zip, end + 16) as usize;
+        assert_eq!(at + u32_at(zip, end + 12) as usize, end);
+        let mut entries = Vec::new();
+        for _ in 0..count {
+            assert_eq!(u32_at(zip, at), 0x02014b50);
+            let crc = u32_at(zip, at + 16);
+            let compressed = u32_at(zip, at + 20) as usize;
+            let size = u32_at(zip, at + 24) as usize;
+            let name_len = u16_at(zip, at + 28);
+            let name = std::str::from_utf8(&zip[at + 46..at + 46 + name_len]).unwrap();
+            let local = u32_at(zip, at + 42) as usize;
+            assert_eq!(u32_at(zip, local), 0x04034b50);
+            assert_eq!(&zip[local + 30..local + 30 + name_len], name.as_bytes());
+            let start = local + 30 + name_len + u16_at(zip, local + 28);
+            let descriptor = start + compressed;
+            assert_eq!(u32_at(zip, descriptor), 0x08074b50);
+            assert_eq!(u32_at(zip, descriptor + 4), crc);
+            let mut content = String::new();
+            DeflateDecoder::new(&zip[start..descriptor])
+                .read_to_string(&mut content)
+                .unwrap();
+            assert_eq!(content.len(), size);
+            assert_eq!(crc32fast::hash(content.as_bytes()), crc);
+            entries.push((name.to_string(), content));
+            at += 46 + name_len;
+        }
+        entries
+    }
+
+    fn sheet(xlsx: &[u8]) -> String {
+        let entries = unzip(xlsx);
+        let names: Vec<&str> = entries.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(
+            names,
+            [
+                "[Content_Types].xml",
+                "_rels/.rels",
+                "xl/workbook.xml",
+                "xl/_rels/workbook.xml.rels",
+                "xl/worksheets/sheet1.xml",
+            ]
+        );
+        let (_, sheet) = entries.into_iter().last().unwrap();
+        let data = sheet.split("<sheetData>").nth(1).unwrap();
+        data.strip_suffix("</sheetData></worksheet>")
+            .unwrap()
+            .to_string()
+    }
+
+    #[test]
+    fn round_trips_cells() {
+        let xlsx = encode(
+            ExportFormat::Xlsx,
+            &[("n", "int4"), ("ok", "bool"), ("name", "text")],
+            vec![
+                vec![
+                    ("n", json!(1.5)),
+                    ("ok", json!(true)),
+                    ("name", json!("a<b&\u{1}")),
+                ],
+                vec![
+                    ("n", json!(null)),
+                    ("ok", json!(false)),
+                    ("name", json!(["x"])),
+                ],
+            ],
+        )
+        .unwrap();
+        let string = |value: &str| {
+            format!(
+                "<c t=\"inlineStr\"><is><t xml:space=\"preserve\">{}</t></is></c>",
+                value
+            )
+        };
+        assert_eq!(
+            sheet(&xlsx),
+            [
+                format!(
+                    "<row>{}{}{}</row>",
+                    string("n"),
+                    string("ok"),
+                    string("name")
+                ),
+                format!(
+                    "<row><c><v>1.5</v></c><c t=\"b\"><v>1</v></c>{}</row>",
+                    string("a&lt;b&amp;")
+                ),
+                format!(
+                    "<row><c/><c t=\"b\"><v>0</v></c>{}</row>",
+                    string("[&quot;x&quot;]")
+                ),
+            ]
+            .concat()
+        );
+    }
+
+    #[test]
+    fn writes_the_header_without_rows() {
+        let xlsx = encode(ExportFormat::Xlsx, &[("a", "text")], vec![]).unwrap();
+        assert!(sheet(&xlsx).starts_with("<row><c t=\"inlineStr\">"));
+    }
+}
@@ -0,0 +1,81 @@
+use std::collections::HashMap;
+
+use sqlx::QueryBuilder;
+
+use crate::internal::{db::DBXError, dialect::Dialect};
+
+/// One field of a projection such as `id,meta.address.city`: a column, or
+/// a path into a JSON column, returned under the path as written.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Field {
+    pub column: String,
+    /// Object keys, or array indexes when all digits.
+    pub path: Vec<String>,
+}
+
+impl Field {
+    pub fn column(column: &str) -> Self {
+        Field {
+            column: column.to_string(),
+            path: Vec::new(),
+        }
+    }
+
+    pub fn parse(fields: &str) -> Result<Vec<Field>, DBXError> {
+        fields
+            .split(',')
+            .map(str::trim)
+            .filter(|field| !field.is_empty())
+            .map(|field| {
+                let mut segments = field.split('.');
+                let column = segments.next().unwrap_or_default().to_string();
+                let path: Vec<String> = segments.map(str::to_string).collect();
+                let invalid =
+                    |segment: &String| segment.is_empty() || segment.contains(['"', '\\', '\'']);
+                if column.is_empty() || path.iter().any(invalid) {
+                    return Err(DBXError::InvalidField(field.to_string()));
+                }
+                Ok(Field { column, path })
+            })
+            .collect()
+    }
+
+    /// The key the field is returned under.
+    pub fn name(&self) -> String {
+        if self.path.is_empty() {
+            self.column.clone()
+        } else {
+            format!("{}.{}", self.column, self.path.join("."))
+        }
+    }
+
+    /// Pushes the select list for `fields`, all columns when empty.
+    /// Rejects paths into columns that hold no JSON.
+    pub(super) fn push_list<DB: Dialect>(
+        fields: &[Field],
+        columns: &HashMap<String, String>,
+        query_builder: &mut QueryBuilder<'_, DB>,
+    ) -> Result<(), DBXError> {
+        if fields.is_empty() {
+            query_builder.push("*");
+        }
+        for (i, field) in fields.iter().enumerate() {
+            if i > 0 {
+                query_builder.push(", ");
+            }
+            let column = DB::quote_ident(&field.column);
+            if field.path.is_empty() {
+                query_builder.push(column);
+                continue;
+            }
+            let column_type = columns.get(&field.column).map(String::as_str);
+            if !column_type.is_some_and(|column_type| DB::JSON_TYPES.contains(&column_type)) {
+                return Err(DBXError::InvalidField(field.name()));
+            }
+            DB::push_json_path(query_builder, &column, &field.path);
+            query_builder.push(" as ");
+            query_builder.push(DB::quote_ident(&field.name()));
+        }
+        Ok(())
+    }
+}
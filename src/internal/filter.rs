@@ -0,0 +1,648 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use sqlx::QueryBuilder;
+
+use crate::internal::dialect::{scalar_to_string, Bind, Dialect};
+
+/// A parsed filter expression, e.g. `age>=18 && (status="active" || role~"adm%")`.
+///
+/// Values may also be variables such as `@request.auth.id`, which have to be
+/// substituted with [`Expr::resolve`] before the expression is pushed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Compare {
+        field: String,
+        op: Op,
+        value: Literal,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Like,
+    NotLike,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Literal {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    /// `@request.auth.id` is stored as `request.auth.id`.
+    Var(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum FilterError {
+    Syntax { position: usize, message: String },
+    UnknownColumn(String),
+    UnknownVariable(String),
+    InvalidValue { column: String, message: String },
+}
+
+impl fmt::Display for FilterError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FilterError::Syntax { position, message } => {
+                write!(f, "Invalid filter at position {}: {}", position, message)
+            }
+            FilterError::UnknownColumn(column) => write!(f, "Unknown column: {}", column),
+            FilterError::UnknownVariable(name) => write!(f, "Unknown variable: @{}", name),
+            FilterError::InvalidValue { column, message } => {
+                write!(f, "Invalid value for column {}: {}", column, message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for FilterError {}
+
+impl fmt::Display for Op {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sql = match self {
+            Op::Eq => "=",
+            Op::Ne => "<>",
+            Op::Gt => ">",
+            Op::Ge => ">=",
+            Op::Lt => "<",
+            Op::Le => "<=",
+            Op::Like => "like",
+            Op::NotLike => "not like",
+        };
+        f.write_str(sql)
+    }
+}
+
+impl Expr {
+    pub fn parse(input: &str) -> Result<Self, FilterError> {
+        let tokens = tokenize(input)?;
+        let mut parser = Parser {
+            tokens,
+            pos: 0,
+            len: input.len(),
+            depth: 0,
+        };
+        let expr = parser.parse_or()?;
+        if let Some((position, token)) = parser.tokens.get(parser.pos) {
+            return Err(FilterError::Syntax {
+                position: *position,
+                message: format!("unexpected {}", token),
+            });
+        }
+        Ok(expr)
+    }
+
+    pub fn eq(field: &str, value: Literal) -> Self {
+        Expr::Compare {
+            field: field.to_string(),
+            op: Op::Eq,
+            value,
+        }
+    }
+
+    /// Replaces every variable with its value from `vars`.
+    pub fn resolve(self, vars: &HashMap<String, Literal>) -> Result<Self, FilterError> {
+        Ok(match self {
+            Expr::And(left, right) => Expr::And(
+                Box::new(left.resolve(vars)?),
+                Box::new(right.resolve(vars)?),
+            ),
+            Expr::Or(left, right) => Expr::Or(
+                Box::new(left.resolve(vars)?),
+                Box::new(right.resolve(vars)?),
+            ),
+            Expr::Not(expr) => Expr::Not(Box::new(expr.resolve(vars)?)),
+            Expr::Compare {
+                field,
+                op,
+                value: Literal::Var(name),
+            } => {
+                let value = vars
+                    .get(&name)
+                    .cloned()
+                    .ok_or(FilterError::UnknownVariable(name))?;
+                Expr::Compare { field, op, value }
+            }
+            compare => compare,
+        })
+    }
+
+    /// Pushes the expression into `query_builder`, binding every literal.
+    ///
+    /// `columns` maps the table's column names to their types, which are
+    /// used both to reject unknown fields and to coerce literals.
+    pub fn push<DB: Dialect>(
+        &self,
+        query_builder: &mut QueryBuilder<'_, DB>,
+        columns: &HashMap<String, String>,
+    ) -> Result<(), FilterError> {
+        match self {
+            Expr::And(left, right) | Expr::Or(left, right) => {
+                let joiner = if matches!(self, Expr::And(..)) {
+                    " and "
+                } else {
+                    " or "
+                };
+                query_builder.push("(");
+                left.push(query_builder, columns)?;
+                query_builder.push(joiner);
+                right.push(query_builder, columns)?;
+                query_builder.push(")");
+            }
+            Expr::Not(expr) => {
+                query_builder.push("not (");
+                expr.push(query_builder, columns)?;
+                query_builder.push(")");
+            }
+            Expr::Compare { field, op, value } => {
+                let column_type = columns
+                    .get(field)
+                    .ok_or_else(|| FilterError::UnknownColumn(field.clone()))?;
+                push_compare(query_builder, field, column_type, *op, value)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn push_compare<DB: Dialect>(
+    query_builder: &mut QueryBuilder<'_, DB>,
+    field: &str,
+    column_type: &str,
+    op: Op,
+    value: &Literal,
+) -> Result<(), FilterError> {
+    let column = DB::quote_ident(field);
+
+    if let Literal::Var(name) = value {
+        return Err(FilterError::UnknownVariable(name.clone()));
+    }
+
+    if let Literal::Null = value {
+        let sql = match op {
+            Op::Eq => "is null",
+            Op::Ne => "is not null",
+            _ => {
+                return Err(FilterError::InvalidValue {
+                    column: field.to_string(),
+                    message: format!("null cannot be compared with {}", op),
+                })
+            }
+        };
+        query_builder.push(format!("{} {}", column, sql));
+        return Ok(());
+    }
+
+    if let Op::Like | Op::NotLike = op {
+        let operand = DB::like_operand(&column, column_type);
+        query_builder.push(format!("{} {} ", operand, op));
+        let pattern = scalar_to_string(&value.into()).unwrap_or_default();
+        DB::push_bind(query_builder, Bind::Text(pattern));
+        return Ok(());
+    }
+
+    query_builder.push(format!("{} {} ", column, op));
+    DB::push_value(query_builder, field, column_type, &value.into()).map_err(|e| {
+        FilterError::InvalidValue {
+            column: e.column,
+            message: e.message,
+        }
+    })
+}
+
+impl From<&Literal> for serde_json::Value {
+    fn from(literal: &Literal) -> Self {
+        match literal {
+            Literal::Null => serde_json::Value::Null,
+            Literal::Bool(value) => serde_json::Value::Bool(*value),
+            Literal::Int(value) => serde_json::Value::Number((*value).into()),
+            Literal::Float(value) => serde_json::Number::from_f64(*value)
+                .map_or_else(|| value.to_string().into(), serde_json::Value::Number),
+            Literal::String(value) | Literal::Var(value) => {
+                serde_json::Value::String(value.clone())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Ident(String),
+    String(String),
+    Number(String),
+    Var(String),
+    Op(Op),
+    And,
+    Or,
+    Not,
+    LParen,
+    RParen,
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Token::Ident(ident) => write!(f, "identifier {}", ident),
+            Token::String(value) => write!(f, "string {:?}", value),
+            Token::Number(value) => write!(f, "number {}", value),
+            Token::Var(name) => write!(f, "variable @{}", name),
+            Token::Op(op) => write!(f, "operator {}", op),
+            Token::And => f.write_str("&&"),
+            Token::Or => f.write_str("||"),
+            Token::Not => f.write_str("!"),
+            Token::LParen => f.write_str("("),
+            Token::RParen => f.write_str(")"),
+        }
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<(usize, Token)>, FilterError> {
+    let mut tokens = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let token = match c {
+            c if c.is_whitespace() => continue,
+            '(' => Token::LParen,
+            ')' => Token::RParen,
+            '&' | '|' => match chars.next() {
+                Some((_, next)) if next == c => {
+                    if c == '&' {
+                        Token::And
+                    } else {
+                        Token::Or
+                    }
+                }
+                _ => {
+                    return Err(FilterError::Syntax {
+                        position: start,
+                        message: format!("expected {}{}", c, c),
+                    })
+                }
+            },
+            '!' => match chars.peek() {
+                Some((_, '=')) => {
+                    chars.next();
+                    Token::Op(Op::Ne)
+                }
+                Some((_, '~')) => {
+                    chars.next();
+                    Token::Op(Op::NotLike)
+                }
+                _ => Token::Not,
+            },
+            '=' => Token::Op(Op::Eq),
+            '~' => Token::Op(Op::Like),
+            '>' | '<' => {
+                let or_equal = matches!(chars.peek(), Some((_, '=')));
+                if or_equal {
+                    chars.next();
+                }
+                Token::Op(match (c, or_equal) {
+                    ('>', false) => Op::Gt,
+                    ('>', true) => Op::Ge,
+                    ('<', false) => Op::Lt,
+                    _ => Op::Le,
+                })
+            }
+            '"' | '\'' => {
+                let mut value = String::new();
+                loop {
+                    let next = match chars.next() {
+                        Some((_, '\\')) => chars.next().map(|(_, escaped)| (escaped, true)),
+                        Some((_, other)) => Some((other, false)),
+                        None => None,
+                    };
+                    match next {
+                        Some((quote, false)) if quote == c => break,
+                        Some((other, _)) => value.push(other),
+                        None => {
+                            return Err(FilterError::Syntax {
+                                position: start,
+                                message: "unterminated string".to_string(),
+                            })
+                        }
+                    }
+                }
+                Token::String(value)
+            }
+            c if c.is_ascii_digit() || c == '-' => {
+                let mut value = c.to_string();
+                while let Some((_, next)) = chars.peek() {
+                    if next.is_ascii_digit() || *next == '.' {
+                        value.push(*next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                if value == "-" {
+                    return Err(FilterError::Syntax {
+                        position: start,
+                        message: "expected a number after -".to_string(),
+                    });
+                }
+                Token::Number(value)
+            }
+            '@' => {
+                let mut value = String::new();
+                while let Some((_, next)) = chars.peek() {
+                    if next.is_alphanumeric() || *next == '_' || *next == '.' {
+                        value.push(*next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                if value.is_empty() {
+                    return Err(FilterError::Syntax {
+                        position: start,
+                        message: "expected a variable name after @".to_string(),
+                    });
+                }
+                Token::Var(value)
+            }
+            c if c.is_alphabetic() || c == '_' => {
+                let mut value = c.to_string();
+                while let Some((_, next)) = chars.peek() {
+                    if next.is_alphanumeric() || *next == '_' {
+                        value.push(*next);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                Token::Ident(value)
+            }
+            other => {
+                return Err(FilterError::Syntax {
+                    position: start,
+                    message: format!("unexpected character {:?}", other),
+                })
+            }
+        };
+        tokens.push((start, token));
+    }
+
+    Ok(tokens)
+}
+
+/// How deeply `!`, parentheses and chained `&&`/`||` may nest, so that
+/// parsing, resolving or dropping a hostile filter cannot exhaust the stack.
+const MAX_DEPTH: usize = 64;
+
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    pos: usize,
+    len: usize,
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&(usize, Token)> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<(usize, Token), FilterError> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token.ok_or_else(|| FilterError::Syntax {
+            position: self.len,
+            message: "unexpected end of filter".to_string(),
+        })
+    }
+
+    /// Runs `parse` one level deeper, failing at `position` past `MAX_DEPTH`.
+    fn nested<T>(
+        &mut self,
+        position: usize,
+        parse: impl FnOnce(&mut Self) -> Result<T, FilterError>,
+    ) -> Result<T, FilterError> {
+        self.deeper(position)?;
+        let result = parse(self);
+        self.depth -= 1;
+        result
+    }
+
+    /// Counts the operator at `position` against `MAX_DEPTH`: each one in a
+    /// chain wraps everything before it one level deeper.
+    fn deeper(&mut self, position: usize) -> Result<(), FilterError> {
+        if self.depth == MAX_DEPTH {
+            return Err(FilterError::Syntax {
+                position,
+                message: format!("nested deeper than {} levels", MAX_DEPTH),
+            });
+        }
+        self.depth += 1;
+        Ok(())
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, FilterError> {
+        let depth = self.depth;
+        let mut expr = self.parse_and()?;
+        while let Some((position, Token::Or)) = self.peek() {
+            self.deeper(*position)?;
+            self.pos += 1;
+            expr = Expr::Or(Box::new(expr), Box::new(self.parse_and()?));
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, FilterError> {
+        let depth = self.depth;
+        let mut expr = self.parse_unary()?;
+        while let Some((position, Token::And)) = self.peek() {
+            self.deeper(*position)?;
+            self.pos += 1;
+            expr = Expr::And(Box::new(expr), Box::new(self.parse_unary()?));
+        }
+        self.depth = depth;
+        Ok(expr)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, FilterError> {
+        match self.next()? {
+            (position, Token::Not) => self.nested(position, |parser| {
+                Ok(Expr::Not(Box::new(parser.parse_unary()?)))
+            }),
+            (position, Token::LParen) => self.nested(position, |parser| {
+                let expr = parser.parse_or()?;
+                match parser.next()? {
+                    (_, Token::RParen) => Ok(expr),
+                    (position, token) => Err(FilterError::Syntax {
+                        position,
+                        message: format!("expected ) but found {}", token),
+                    }),
+                }
+            }),
+            (_, Token::Ident(field)) => {
+                let op = match self.next()? {
+                    (_, Token::Op(op)) => op,
+                    (position, token) => {
+                        return Err(FilterError::Syntax {
+                            position,
+                            message: format!("expected an operator but found {}", token),
+                        })
+                    }
+                };
+                let value = self.parse_literal()?;
+                Ok(Expr::Compare { field, op, value })
+            }
+            (position, token) => Err(FilterError::Syntax {
+                position,
+                message: format!("expected a field but found {}", token),
+            }),
+        }
+    }
+
+    fn parse_literal(&mut self) -> Result<Literal, FilterError> {
+        match self.next()? {
+            (_, Token::String(value)) => Ok(Literal::String(value)),
+            (_, Token::Var(name)) => Ok(Literal::Var(name)),
+            (position, Token::Number(value)) => {
+                if let Ok(value) = value.parse::<i64>() {
+                    return Ok(Literal::Int(value));
+                }
+                value
+                    .parse::<f64>()
+                    .map(Literal::Float)
+                    .map_err(|_| FilterError::Syntax {
+                        position,
+                        message: format!("invalid number {}", value),
+                    })
+            }
+            (position, Token::Ident(ident)) => match ident.as_str() {
+                "null" => Ok(Literal::Null),
+                "true" => Ok(Literal::Bool(true)),
+                "false" => Ok(Literal::Bool(false)),
+                _ => Err(FilterError::Syntax {
+                    position,
+                    message: format!("expected a value but found identifier {}", ident),
+                }),
+            },
+            (position, token) => Err(FilterError::Syntax {
+                position,
+                message: format!("expected a value but found {}", token),
+            }),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compare(field: &str, op: Op, value: Literal) -> Expr {
+        Expr::Compare {
+            field: field.to_string(),
+            op,
+            value,
+        }
+    }
+
+    fn syntax_error(input: &str) -> (usize, String) {
+        match Expr::parse(input) {
+            Err(FilterError::Syntax { position, message }) => (position, message),
+            other => panic!("expected a syntax error for {:?}, got {:?}", input, other),
+        }
+    }
+
+    #[test]
+    fn parses_comparisons() {
+        assert_eq!(
+            Expr::parse("age>=18").unwrap(),
+            compare("age", Op::Ge, Literal::Int(18))
+        );
+        assert_eq!(
+            Expr::parse("name !~ \"adm%\"").unwrap(),
+            compare("name", Op::NotLike, Literal::String("adm%".to_string()))
+        );
+        assert_eq!(
+            Expr::parse("score<1.5").unwrap(),
+            compare("score", Op::Lt, Literal::Float(1.5))
+        );
+        assert_eq!(
+            Expr::parse("deleted_at=null").unwrap(),
+            compare("deleted_at", Op::Eq, Literal::Null)
+        );
+        assert_eq!(
+            Expr::parse("owner=@request.auth.id").unwrap(),
+            compare("owner", Op::Eq, Literal::Var("request.auth.id".to_string()))
+        );
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let a = compare("a", Op::Eq, Literal::Int(1));
+        let b = compare("b", Op::Eq, Literal::Int(2));
+        let c = compare("c", Op::Eq, Literal::Int(3));
+        assert_eq!(
+            Expr::parse("a=1 || b=2 && c=3").unwrap(),
+            Expr::Or(
+                Box::new(a.clone()),
+                Box::new(Expr::And(Box::new(b.clone()), Box::new(c.clone())))
+            )
+        );
+        assert_eq!(
+            Expr::parse("!(a=1 || b=2) && c=3").unwrap(),
+            Expr::And(
+                Box::new(Expr::Not(Box::new(Expr::Or(Box::new(a), Box::new(b))))),
+                Box::new(c)
+            )
+        );
+    }
+
+    #[test]
+    fn reports_syntax_errors_with_positions() {
+        assert_eq!(syntax_error("a=1 &&").0, 6);
+        assert_eq!(syntax_error("(a=1").0, 4);
+        assert_eq!(syntax_error("a=1 b=2").0, 4);
+        assert_eq!(syntax_error("a 1").0, 2);
+        assert_eq!(syntax_error("a=nope").0, 2);
+    }
+
+    #[test]
+    fn limits_nesting() {
+        let nested = format!("{}a=1{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert!(Expr::parse(&nested).is_ok());
+        let negated = format!("{}a=1", "!".repeat(MAX_DEPTH));
+        assert!(Expr::parse(&negated).is_ok());
+
+        let (position, message) = syntax_error(&format!("{}a=1", "(".repeat(MAX_DEPTH + 1)));
+        assert_eq!(position, MAX_DEPTH);
+        assert!(message.contains("nested deeper"));
+        assert_eq!(
+            syntax_error(&format!("{}a=1", "!".repeat(MAX_DEPTH + 1))).0,
+            MAX_DEPTH
+        );
+
+        let chain = vec!["a=1"; MAX_DEPTH + 1];
+        assert!(Expr::parse(&chain.join(" && ")).is_ok());
+        assert!(Expr::parse(&chain.join(" || ")).is_ok());
+        let longest = chain.join(" && ");
+        let (position, message) = syntax_error(&format!("{} && a=1", longest));
+        assert_eq!(position, longest.len() + 1);
+        assert!(message.contains("nested deeper"));
+    }
+
+    #[test]
+    fn rejects_deep_input_without_overflowing() {
+        syntax_error(&"!".repeat(100_000));
+        syntax_error(&"(".repeat(100_000));
+        syntax_error(&vec!["a=1"; 100_000].join(" && "));
+        syntax_error(&vec!["a=1"; 100_000].join(" || "));
+    }
+}
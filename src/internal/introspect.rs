@@ -0,0 +1,580 @@
+use std::collections::HashMap;
+
+use crate::{
+    internal::db::{with_pool, DBXError, Pool, DBX},
+    internal::dialect::Dialect,
+    models::schema::{Check, Database, ForeignKey, Index, Schema, Table, VColumn},
+};
+
+/// Table, column name, data type, type as the backend's `Dialect` expects
+/// it, nullability, maximum length, default, whether the backend generates
+/// its values and the position in the primary key (SQLite only, as its rowid
+/// keys have no index).
+type ColumnRow = (
+    String,
+    String,
+    String,
+    String,
+    String,
+    Option<i64>,
+    Option<String>,
+    i64,
+    i64,
+);
+
+/// Table, index name, column, uniqueness and whether it backs the primary
+/// key, one row per indexed column.
+type IndexRow = (String, String, String, i64, i64);
+
+/// Table, key grouping the rows of one constraint, its name, column,
+/// referenced schema, table and column, and the delete and update actions.
+type ForeignKeyRow = (
+    String,
+    String,
+    Option<String>,
+    String,
+    String,
+    String,
+    Option<String>,
+    String,
+    String,
+);
+
+/// Catalog queries of one backend. All but `schemas` and `current_schema`
+/// take the schema as parameter and cover all of its relations at once, the
+/// relation being the first column of their rows.
+struct Queries {
+    schemas: &'static str,
+    current_schema: &'static str,
+    relations: &'static str,
+    columns: &'static str,
+    indexes: &'static str,
+    foreign_keys: &'static str,
+    checks: &'static str,
+}
+
+impl Queries {
+    fn of(pool: &Pool) -> Self {
+        match pool {
+            Pool::Postgres(_) => Queries {
+                schemas: r#"select n.nspname::text from pg_namespace n
+                where n.nspname not like 'pg\_%'
+                and n.nspname <> 'information_schema'
+                order by 1"#,
+                current_schema: "select current_schema()::text",
+                relations: r#"select c.relname::text,
+                case c.relkind when 'v' then 'view' when 'm' then 'materialized_view' else 'table' end
+                from pg_class c
+                join pg_namespace n on n.oid = c.relnamespace
+                where n.nspname = $1
+                and c.relkind in ('r', 'p', 'v', 'm')
+                and not c.relispartition
+                order by 1"#,
+                // Materialized views are missing from information_schema.
+                columns: r#"select c.relname::text, a.attname::text, format_type(a.atttypid, null), ty.typname::text,
+                case when a.attnotnull then 'NO' else 'YES' end,
+                case when a.atttypid in ('bpchar'::regtype, 'varchar'::regtype) and a.atttypmod > 0
+                then a.atttypmod - 4 end::int8,
+                pg_get_expr(d.adbin, d.adrelid),
+                (case when a.attidentity <> '' or pg_get_expr(d.adbin, d.adrelid) like 'nextval(%'
+                then 1 else 0 end)::int8,
+                0::int8
+                from pg_attribute a
+                join pg_class c on c.oid = a.attrelid
+                join pg_namespace n on n.oid = c.relnamespace
+                join pg_type ty on ty.oid = a.atttypid
+                left join pg_attrdef d on d.adrelid = a.attrelid and d.adnum = a.attnum
+                where n.nspname = $1
+                and c.relkind in ('r', 'p', 'v', 'm')
+                and a.attnum > 0
+                and not a.attisdropped
+                order by c.relname, a.attnum"#,
+                indexes: r#"select t.relname::text, i.relname::text, a.attname::text,
+                (case when ix.indisunique then 1 else 0 end)::int8,
+                (case when ix.indisprimary then 1 else 0 end)::int8
+                from pg_index ix
+                join pg_class i on i.oid = ix.indexrelid
+                join pg_class t on t.oid = ix.indrelid
+                join pg_namespace n on n.oid = t.relnamespace
+                cross join lateral unnest(ix.indkey::int2[]) with ordinality as k(attnum, ord)
+                join pg_attribute a on a.attrelid = t.oid and a.attnum = k.attnum
+                where n.nspname = $1
+                and k.ord <= ix.indnkeyatts
+                order by t.relname, i.relname, k.ord"#,
+                foreign_keys: r#"select t.relname::text, con.conname::text, con.conname::text, a.attname::text,
+                rn.nspname::text, rt.relname::text, ra.attname::text,
+                case con.confdeltype when 'r' then 'RESTRICT' when 'c' then 'CASCADE'
+                when 'n' then 'SET NULL' when 'd' then 'SET DEFAULT' else 'NO ACTION' end,
+                case con.confupdtype when 'r' then 'RESTRICT' when 'c' then 'CASCADE'
+                when 'n' then 'SET NULL' when 'd' then 'SET DEFAULT' else 'NO ACTION' end
+                from pg_constraint con
+                join pg_class t on t.oid = con.conrelid
+                join pg_namespace n on n.oid = t.relnamespace
+                join pg_class rt on rt.oid = con.confrelid
+                join pg_namespace rn on rn.oid = rt.relnamespace
+                cross join lateral unnest(con.conkey, con.confkey) with ordinality as k(attnum, ref_attnum, ord)
+                join pg_attribute a on a.attrelid = con.conrelid and a.attnum = k.attnum
+                join pg_attribute ra on ra.attrelid = con.confrelid and ra.attnum = k.ref_attnum
+                where con.contype = 'f'
+                and n.nspname = $1
+                order by t.relname, con.conname, k.ord"#,
+                checks: r#"select t.relname::text, con.conname::text, pg_get_expr(con.conbin, con.conrelid)
+                from pg_constraint con
+                join pg_class t on t.oid = con.conrelid
+                join pg_namespace n on n.oid = t.relnamespace
+                where con.contype = 'c'
+                and n.nspname = $1
+                order by t.relname, con.conname"#,
+            },
+            // MySQL calls its databases schemas.
+            Pool::MySql(_) => Queries {
+                schemas: r#"select cast(s.schema_name as char) from information_schema.schemata s
+                where s.schema_name not in ('information_schema', 'mysql', 'performance_schema', 'sys')
+                order by 1"#,
+                current_schema: "select cast(database() as char)",
+                relations: r#"select cast(t.table_name as char),
+                cast(case when t.table_type = 'VIEW' then 'view' else 'table' end as char)
+                from information_schema.tables t
+                where t.table_schema = ?
+                order by 1"#,
+                columns: r#"select cast(c.table_name as char), cast(c.column_name as char), cast(c.data_type as char),
+                cast(case when c.column_type = 'tinyint(1)' then 'boolean' else c.data_type end as char),
+                cast(c.is_nullable as char),
+                cast(c.character_maximum_length as signed), cast(c.column_default as char),
+                cast(c.extra like '%auto_increment%' as signed), 0
+                from information_schema.columns c
+                where c.table_schema = ?
+                order by c.table_name, c.ordinal_position"#,
+                indexes: r#"select cast(s.table_name as char), cast(s.index_name as char), cast(s.column_name as char),
+                cast(s.non_unique = 0 as signed), cast(s.index_name = 'PRIMARY' as signed)
+                from information_schema.statistics s
+                where s.table_schema = ?
+                and s.column_name is not null
+                order by s.table_name, s.index_name, s.seq_in_index"#,
+                foreign_keys: r#"select cast(k.table_name as char), cast(k.constraint_name as char), cast(k.constraint_name as char),
+                cast(k.column_name as char), cast(k.referenced_table_schema as char),
+                cast(k.referenced_table_name as char), cast(k.referenced_column_name as char),
+                cast(r.delete_rule as char), cast(r.update_rule as char)
+                from information_schema.key_column_usage k
+                join information_schema.referential_constraints r
+                on r.constraint_schema = k.constraint_schema
+                and r.table_name = k.table_name
+                and r.constraint_name = k.constraint_name
+                where k.table_schema = ?
+                order by k.table_name, k.constraint_name, k.ordinal_position"#,
+                checks: r#"select cast(tc.table_name as char), cast(cc.constraint_name as char), cast(cc.check_clause as char)
+                from information_schema.check_constraints cc
+                join information_schema.table_constraints tc
+                on tc.constraint_schema = cc.constraint_schema
+                and tc.constraint_name = cc.constraint_name
+                where tc.constraint_type = 'CHECK'
+                and tc.table_schema = ?
+                order by 1, 2"#,
+            },
+            // SQLite's schemas are the main database and those attached to
+            // it.
+            Pool::Sqlite(_) => Queries {
+                schemas: "select d.name from pragma_database_list d order by d.seq",
+                current_schema: "select 'main'",
+                relations: r#"select t.name, t.type from pragma_table_list t
+                where t.schema = ?1
+                and t.type in ('table', 'view')
+                and t.name not like 'sqlite_%'
+                order by 1"#,
+                columns: r#"select t.name, c.name, c.type, lower(c.type), case when c."notnull" then 'NO' else 'YES' end,
+                null, c.dflt_value, 0, c.pk
+                from pragma_table_list t
+                join pragma_table_info(t.name, ?1) c
+                where t.schema = ?1
+                and t.type in ('table', 'view')
+                and t.name not like 'sqlite_%'
+                order by t.name, c.cid"#,
+                indexes: r#"select t.name, l.name, i.name, l."unique", l.origin = 'pk'
+                from pragma_table_list t
+                join pragma_index_list(t.name, ?1) l
+                join pragma_index_info(l.name, ?1) i
+                where t.schema = ?1
+                and t.type = 'table'
+                and t.name not like 'sqlite_%'
+                and i.name is not null
+                order by t.name, l.name, i.seqno"#,
+                // A missing `to` column refers to the primary key.
+                foreign_keys: r#"select t.name, cast(f.id as text), null, f."from", ?1, f."table",
+                coalesce(f."to", (select p.name from pragma_table_info(f."table", ?1) p where p.pk = f.seq + 1)),
+                f.on_delete, f.on_update
+                from pragma_table_list t
+                join pragma_foreign_key_list(t.name, ?1) f
+                where t.schema = ?1
+                and t.type = 'table'
+                and t.name not like 'sqlite_%'
+                order by t.name, f.id, f.seq"#,
+                // Schema names cannot be bound, so this is completed with
+                // the quoted schema.
+                checks: "select t.name, t.sql from {}.sqlite_master t where t.type = 'table'",
+            },
+        }
+    }
+}
+
+impl DBX {
+    /// Describes the tables, views and materialized views of `schemas`:
+    /// the current schema when empty, every schema for `*`.
+    ///
+    /// Whenever the current schema is among them, it also replaces the
+    /// snapshot collections are checked against.
+    pub async fn introspect(&self, schemas: &[&str]) -> Result<Database, DBXError> {
+        let queries = Queries::of(&self.pool);
+        let (available, current) = with_pool!(&self.pool, pool => {
+            let available = sqlx::query_scalar::<_, String>(queries.schemas)
+                .fetch_all(pool)
+                .await?;
+            let current = sqlx::query_scalar::<_, Option<String>>(queries.current_schema)
+                .fetch_one(pool)
+                .await?;
+            (available, current)
+        });
+        let names = match schemas {
+            [] => current.iter().cloned().collect(),
+            ["*"] => available,
+            schemas => {
+                if let Some(unknown) = schemas
+                    .iter()
+                    .find(|schema| !available.iter().any(|name| name == *schema))
+                {
+                    return Err(DBXError::UnknownSchema(unknown.to_string()));
+                }
+                schemas.iter().map(|schema| schema.to_string()).collect()
+            }
+        };
+
+        let mut database = Database {
+            schemas: Vec::with_capacity(names.len()),
+        };
+        for name in names {
+            database
+                .schemas
+                .push(self.introspect_schema(&queries, name).await?);
+        }
+        if let Some(schema) = database
+            .schemas
+            .iter()
+            .find(|schema| Some(&schema.name) == current.as_ref())
+        {
+            self.set_schema(schema.clone());
+        }
+        Ok(database)
+    }
+
+    async fn introspect_schema(&self, queries: &Queries, name: String) -> Result<Schema, DBXError> {
+        let (relations, columns, indexes, foreign_keys) = with_pool!(&self.pool, pool => {
+            let relations = sqlx::query_as::<_, (String, String)>(queries.relations)
+                .bind(&name)
+                .fetch_all(pool)
+                .await?;
+            let columns = sqlx::query_as::<_, ColumnRow>(queries.columns)
+                .bind(&name)
+                .fetch_all(pool)
+                .await?;
+            let indexes = sqlx::query_as::<_, IndexRow>(queries.indexes)
+                .bind(&name)
+                .fetch_all(pool)
+                .await?;
+            let foreign_keys = sqlx::query_as::<_, ForeignKeyRow>(queries.foreign_keys)
+                .bind(&name)
+                .fetch_all(pool)
+                .await?;
+            (relations, columns, indexes, foreign_keys)
+        });
+        let mut columns = by_table(columns, |row| &row.0);
+        let mut indexes = by_table(indexes, |row| &row.0);
+        let mut foreign_keys = by_table(foreign_keys, |row| &row.0);
+        let mut checks = self.checks(queries, &name).await?;
+
+        let mut schema = Schema {
+            name,
+            tables: Vec::new(),
+            views: Vec::new(),
+            materialized_views: Vec::new(),
+        };
+        for (relation, kind) in relations {
+            let table = self.build_table(
+                columns.remove(&relation).unwrap_or_default(),
+                indexes.remove(&relation).unwrap_or_default(),
+                foreign_keys.remove(&relation).unwrap_or_default(),
+                checks.remove(&relation).unwrap_or_default(),
+                relation,
+            );
+            match kind.as_str() {
+                "view" => schema.views.push(table),
+                "materialized_view" => schema.materialized_views.push(table),
+                _ => schema.tables.push(table),
+            }
+        }
+        Ok(schema)
+    }
+
+    fn build_table(
+        &self,
+        column_rows: Vec<ColumnRow>,
+        index_rows: Vec<IndexRow>,
+        foreign_key_rows: Vec<ForeignKeyRow>,
+        checks: Vec<Check>,
+        name: String,
+    ) -> Table {
+        let mut indexes: Vec<Index> = Vec::new();
+        for (_, index, column, is_unique, is_primary) in index_rows {
+            match indexes.last_mut() {
+                Some(last) if last.name == index => last.columns.push(column),
+                _ => indexes.push(Index {
+                    name: index,
+                    columns: vec![column],
+                    is_unique: is_unique != 0,
+                    is_primary: is_primary != 0,
+                }),
+            }
+        }
+
+        let mut key_columns: Vec<(i64, &str)> = column_rows
+            .iter()
+            .filter(|row| row.8 > 0)
+            .map(|row| (row.8, row.1.as_str()))
+            .collect();
+        key_columns.sort();
+        let primary_key: Vec<String> = match indexes.iter().find(|index| index.is_primary) {
+            Some(index) => index.columns.clone(),
+            None => key_columns
+                .into_iter()
+                .map(|(_, column)| column.to_string())
+                .collect(),
+        };
+
+        let mut foreign_keys: Vec<ForeignKey> = Vec::new();
+        let mut last_key = None;
+        for (
+            _,
+            key,
+            constraint,
+            column,
+            referenced_schema,
+            referenced_table,
+            referenced_column,
+            on_delete,
+            on_update,
+        ) in foreign_key_rows
+        {
+            let referenced_column = referenced_column.unwrap_or_default();
+            match foreign_keys.last_mut() {
+                Some(last) if last_key.as_ref() == Some(&key) => {
+                    last.columns.push(column);
+                    last.referenced_columns.push(referenced_column);
+                }
+                _ => foreign_keys.push(ForeignKey {
+                    name: constraint,
+                    columns: vec![column],
+                    referenced_schema,
+                    referenced_table,
+                    referenced_columns: vec![referenced_column],
+                    on_delete,
+                    on_update,
+                }),
+            }
+            last_key = Some(key);
+        }
+
+        let columns = column_rows
+            .into_iter()
+            .map(
+                |(
+                    _,
+                    column,
+                    data_type,
+                    column_type,
+                    is_nullable,
+                    maximum_length,
+                    default_value,
+                    generated,
+                    _,
+                )| {
+                    let is_primary_key = primary_key.contains(&column);
+                    let sole_key = primary_key.len() == 1 && is_primary_key;
+                    // An `integer primary key` is an alias of SQLite's rowid.
+                    let rowid = matches!(self.pool, Pool::Sqlite(_))
+                        && sole_key
+                        && data_type.eq_ignore_ascii_case("integer");
+                    VColumn {
+                        is_primary_key,
+                        is_unique: sole_key
+                            || indexes
+                                .iter()
+                                .any(|index| index.is_unique && index.columns == [column.as_str()]),
+                        is_auto_increment: generated != 0 || rowid,
+                        maximum_length: maximum_length.and_then(|length| length.try_into().ok()),
+                        name: column,
+                        data_type,
+                        column_type,
+                        is_nullable,
+                        default_value,
+                    }
+                },
+            )
+            .collect();
+
+        Table {
+            name,
+            columns,
+            primary_key,
+            foreign_keys,
+            indexes,
+            checks,
+        }
+    }
+
+    /// The check constraints of every table of `schema`.
+    async fn checks(
+        &self,
+        queries: &Queries,
+        schema: &str,
+    ) -> Result<HashMap<String, Vec<Check>>, sqlx::Error> {
+        let checks = match &self.pool {
+            Pool::Postgres(pool) => {
+                sqlx::query_as::<_, (String, Option<String>, String)>(queries.checks)
+                    .bind(schema)
+                    .fetch_all(pool)
+                    .await?
+            }
+            // Check constraints are only listed from MySQL 8.0.16 on; older
+            // servers have none to report.
+            Pool::MySql(pool) => {
+                sqlx::query_as::<_, (String, Option<String>, String)>(queries.checks)
+                    .bind(schema)
+                    .fetch_all(pool)
+                    .await
+                    .unwrap_or_default()
+            }
+            // SQLite keeps no catalog of check constraints, only the
+            // statements that created the tables.
+            Pool::Sqlite(pool) => {
+                let query = queries
+                    .checks
+                    .replace("{}", &sqlx::Sqlite::quote_ident(schema));
+                let tables = sqlx::query_as::<_, (String, Option<String>)>(&query)
+                    .fetch_all(pool)
+                    .await?;
+                return Ok(tables
+                    .into_iter()
+                    .map(|(table, sql)| (table, sqlite_checks(&sql.unwrap_or_default())))
+                    .collect());
+            }
+        };
+        let mut grouped: HashMap<String, Vec<Check>> = HashMap::new();
+        for (table, name, expression) in checks {
+            grouped
+                .entry(table)
+                .or_default()
+                .push(Check { name, expression });
+        }
+        Ok(grouped)
+    }
+}
+
+/// Groups `rows` by their table, keeping their order.
+fn by_table<R>(rows: Vec<R>, table: fn(&R) -> &String) -> HashMap<String, Vec<R>> {
+    let mut grouped: HashMap<String, Vec<R>> = HashMap::new();
+    for row in rows {
+        grouped.entry(table(&row).clone()).or_default().push(row);
+    }
+    grouped
+}
+
+/// Extracts the `check (...)` clauses of a SQLite `create table` statement,
+/// with the name of any `constraint` introducing them.
+fn sqlite_checks(sql: &str) -> Vec<Check> {
+    let bytes = sql.as_bytes();
+    let mut checks = Vec::new();
+    let mut words: Vec<&str> = Vec::new();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' | b'"' | b'`' | b'[' => {
+                let end = skip_quoted(bytes, i);
+                words.push(&sql[i..end]);
+                i = end;
+            }
+            c if c.is_ascii_alphanumeric() || c == b'_' => {
+                let start = i;
+                while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                    i += 1;
+                }
+                let word = &sql[start..i];
+                let open = i + sql[i..].len() - sql[i..].trim_start().len();
+                if word.eq_ignore_ascii_case("check") && bytes.get(open) == Some(&b'(') {
+                    let close = closing_paren(bytes, open);
+                    let name = match words.as_slice() {
+                        [.., constraint, name] if constraint.eq_ignore_ascii_case("constraint") => {
+                            Some(unquote(name).to_string())
+                        }
+                        _ => None,
+                    };
+                    checks.push(Check {
+                        name,
+                        expression: sql[open + 1..close].trim().to_string(),
+                    });
+                    words.clear();
+                    i = (close + 1).min(bytes.len());
+                } else {
+                    words.push(word);
+                }
+            }
+            b',' | b'(' | b')' => {
+                words.clear();
+                i += 1;
+            }
+            _ => i += 1,
+        }
+    }
+    checks
+}
+
+/// Returns the index just past the quoted text starting at `start`.
+fn skip_quoted(bytes: &[u8], start: usize) -> usize {
+    let quote = match bytes[start] {
+        b'[' => b']',
+        quote => quote,
+    };
+    bytes[start + 1..]
+        .iter()
+        .position(|&b| b == quote)
+        .map_or(bytes.len(), |end| start + end + 2)
+}
+
+/// Returns the index of the parenthesis closing the one at `open`, or the
+/// end of `bytes` when it is unbalanced.
+fn closing_paren(bytes: &[u8], open: usize) -> usize {
+    let mut depth = 0;
+    let mut i = open;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\'' | b'"' | b'`' | b'[' => {
+                i = skip_quoted(bytes, i);
+                continue;
+            }
+            b'(' => depth += 1,
+            b')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return i;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    bytes.len()
+}
+
+fn unquote(name: &str) -> &str {
+    match name.as_bytes().first() {
+        Some(b'\'' | b'"' | b'`' | b'[') if name.len() >= 2 => &name[1..name.len() - 1],
+        _ => name,
+    }
+}
@@ -0,0 +1,43 @@
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+/// DDL penkr ran against a connection, kept to audit its schema history.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct Migration {
+    pub id: i64,
+    pub sql: String,
+    /// Unix timestamp.
+    pub applied_at: i64,
+}
+
+/// Records `statements` as applied to `connection` and returns the id of
+/// the migration.
+pub async fn record(
+    pool: &SqlitePool,
+    connection: &str,
+    statements: &[String],
+) -> Result<i64, sqlx::Error> {
+    let sql: String = statements
+        .iter()
+        .map(|statement| format!("{};\n", statement))
+        .collect();
+    let result = sqlx::query(
+        "insert into schema_migrations (connection, sql, applied_at) values ($1, $2, $3)",
+    )
+    .bind(connection)
+    .bind(sql)
+    .bind(chrono::Utc::now().timestamp())
+    .execute(pool)
+    .await?;
+    Ok(result.last_insert_rowid())
+}
+
+/// Migrations applied to `connection`, oldest first.
+pub async fn list(pool: &SqlitePool, connection: &str) -> Result<Vec<Migration>, sqlx::Error> {
+    sqlx::query_as::<_, Migration>(
+        "select id, sql, applied_at from schema_migrations where connection = $1 order by id",
+    )
+    .bind(connection)
+    .fetch_all(pool)
+    .await
+}
@@ -1,2 +1,24 @@
+pub mod aggregate;
+pub mod auth;
+pub mod connections;
 pub mod db;
-mod de;
+mod ddl;
+pub mod de;
+pub mod dialect;
+mod diff;
+pub mod expand;
+pub mod export;
+pub mod fields;
+pub mod filter;
+mod introspect;
+pub mod migrations;
+pub mod page;
+mod pg_value;
+pub mod profiles;
+pub mod rules;
+pub mod search;
+pub mod secret;
+pub mod settings;
+pub mod sort;
+pub mod stream;
+pub mod temporal;
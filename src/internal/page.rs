@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Serialize;
+use serde_json::Value;
+use sqlx::{FromRow, QueryBuilder};
+
+use crate::{
+    internal::db::{with_pool, DBQuery, DBXError, Pool, DBX},
+    internal::de::QueryResult,
+    internal::dialect::{Bind, Dialect},
+    internal::fields::Field,
+    internal::search::RELEVANCE,
+    internal::sort::Sort,
+    internal::temporal::TemporalFormat,
+};
+
+pub const DEFAULT_PER_PAGE: i64 = 50;
+pub const MAX_PER_PAGE: i64 = 1000;
+
+/// Which page of a listing to return: by number, or after a cursor from a
+/// previous page.
+#[derive(Debug, Default)]
+pub struct Pagination {
+    /// 1-based.
+    pub page: Option<i64>,
+    pub per_page: Option<i64>,
+    /// The `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+    /// Leaves out the count, which takes a scan of the matching rows.
+    pub skip_total: bool,
+}
+
+#[derive(Serialize)]
+pub struct Page<T = Vec<QueryResult>> {
+    pub items: T,
+    /// Unset when paging by cursor.
+    pub page: Option<i64>,
+    pub per_page: i64,
+    pub total_items: Option<i64>,
+    pub total_pages: Option<i64>,
+    /// Set when paging by cursor and more rows follow.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_cursor: Option<String>,
+}
+
+impl<T> Page<T> {
+    pub fn map_items<U>(self, f: impl FnOnce(T) -> U) -> Page<U> {
+        Page {
+            items: f(self.items),
+            page: self.page,
+            per_page: self.per_page,
+            total_items: self.total_items,
+            total_pages: self.total_pages,
+            next_cursor: self.next_cursor,
+        }
+    }
+}
+
+impl DBX {
+    /// Selects one page of the rows matching `query`, whose own `limit` and
+    /// `offset` give the page when `pagination` does not.
+    ///
+    /// With a cursor, or `cursor=""` for the first page, rows are paged by
+    /// keyset on the sort columns and the primary key, which stays fast at
+    /// any depth; the sort columns should then hold no nulls.
+    pub async fn select_page(
+        &self,
+        query: &DBQuery,
+        pagination: &Pagination,
+    ) -> Result<Page, DBXError> {
+        let per_page = pagination
+            .per_page
+            .or_else(|| query.limit.map(i64::from))
+            .unwrap_or(DEFAULT_PER_PAGE);
+        if !(1..=MAX_PER_PAGE).contains(&per_page) {
+            return Err(DBXError::InvalidPagination(format!(
+                "per_page must be between 1 and {}",
+                MAX_PER_PAGE
+            )));
+        }
+        let total_items = if pagination.skip_total {
+            None
+        } else {
+            Some(self.count(query).await?)
+        };
+        let total_pages = total_items.map(|total| (total + per_page - 1) / per_page);
+
+        if let Some(cursor) = &pagination.cursor {
+            let (items, next_cursor) = self.select_after(query, cursor, per_page).await?;
+            return Ok(Page {
+                items,
+                page: None,
+                per_page,
+                total_items,
+                total_pages,
+                next_cursor,
+            });
+        }
+
+        let offset = match pagination.page {
+            Some(page) if page < 1 => {
+                return Err(DBXError::InvalidPagination(
+                    "page must be 1 or more".to_string(),
+                ))
+            }
+            Some(page) => (page - 1).checked_mul(per_page).ok_or_else(page_too_far)?,
+            None => query.offset.map(i64::from).unwrap_or(0),
+        };
+        let paged = DBQuery {
+            limit: Some(per_page as i32),
+            offset: Some(i32::try_from(offset).map_err(|_| page_too_far())?),
+            ..query.clone()
+        };
+        Ok(Page {
+            items: self.select(&paged).await?,
+            page: Some(offset / per_page + 1),
+            per_page,
+            total_items,
+            total_pages,
+            next_cursor: None,
+        })
+    }
+
+    /// Counts the rows matching the filter of `query`.
+    pub async fn count(&self, query: &DBQuery) -> Result<i64, DBXError> {
+        let columns = self.columns(&query.table)?;
+        let (count,) = with_pool!(&self.pool, pool => {
+            let mut query_builder = QueryBuilder::new("");
+            Self::count_all_query(query, &columns, &mut query_builder)?;
+            query_builder
+                .build_query_as::<(i64,)>()
+                .fetch_one(pool)
+                .await?
+        });
+        Ok(count)
+    }
+
+    /// Selects up to `per_page` rows past `cursor`, and the cursor past the
+    /// last of them when more follow.
+    async fn select_after(
+        &self,
+        query: &DBQuery,
+        cursor: &str,
+        per_page: i64,
+    ) -> Result<(Vec<QueryResult>, Option<String>), DBXError> {
+        let columns = self.columns(&query.table)?;
+        Self::check_columns(query, &columns)?;
+        let keys = self.sort_keys(query, &columns)?;
+        if keys.iter().any(|key| key.column == RELEVANCE) {
+            return Err(DBXError::InvalidPagination(
+                "Cursors cannot page by relevance".to_string(),
+            ));
+        }
+        let after = match cursor {
+            "" => None,
+            cursor => Some(decode_cursor(cursor, &keys)?),
+        };
+        let mut query = query.clone();
+        if !query.fields.is_empty() {
+            for key in &keys {
+                let field = Field::column(&key.column);
+                if !query.fields.contains(&field) {
+                    query.fields.push(field);
+                }
+            }
+        }
+
+        let (mut rows, last) = with_pool!(&self.pool, pool => {
+            let mut query_builder = QueryBuilder::new("");
+            Self::keyset_query(
+                &query,
+                &columns,
+                &keys,
+                after.as_deref(),
+                per_page + 1,
+                &mut query_builder,
+            )?;
+            let rows = query_builder.build().fetch_all(pool).await?;
+            // The cursor holds the keys of the last row in RFC 3339, which
+            // keeps timestamps whole whatever format the page is in.
+            let last = match rows.len() as i64 > per_page {
+                true => Some(TemporalFormat::Rfc3339.sync_scope(|| {
+                    QueryResult::from_row(&rows[per_page as usize - 1])
+                })?),
+                false => None,
+            };
+            let rows = rows
+                .iter()
+                .map(QueryResult::from_row)
+                .collect::<Result<Vec<_>, _>>()?;
+            (rows, last)
+        });
+        rows.truncate(per_page as usize);
+        let next_cursor = last.map(|row| encode_cursor(&row, &keys));
+        Ok((rows, next_cursor))
+    }
+
+    /// The sort of `query`, then the primary key to break ties.
+    fn sort_keys(
+        &self,
+        query: &DBQuery,
+        columns: &HashMap<String, String>,
+    ) -> Result<Vec<Sort>, DBXError> {
+        let primary_key = self.primary_key(&query.table, columns)?;
+        let mut keys = query.sort.clone();
+        if !keys.iter().any(|key| key.column == primary_key) {
+            keys.push(Sort {
+                column: primary_key,
+                descending: keys.last().is_some_and(|key| key.descending),
+                nulls: None,
+            });
+        }
+        Ok(keys)
+    }
+
+    fn count_all_query<DB: Dialect>(
+        query: &DBQuery,
+        columns: &HashMap<String, String>,
+        query_builder: &mut QueryBuilder<'_, DB>,
+    ) -> Result<(), DBXError> {
+        query_builder.push("select count(*) from ");
+        query_builder.push(DB::quote_ident(&query.table));
+        Self::push_where(query, columns, query_builder)?;
+        Ok(())
+    }
+
+    /// Rows of `query` ordered by `keys`, starting after the row with the
+    /// key values `after`:
+    /// `(a > ?) or (a = ? and b > ?)`, with `<` for descending keys.
+    fn keyset_query<DB: Dialect>(
+        query: &DBQuery,
+        columns: &HashMap<String, String>,
+        keys: &[Sort],
+        after: Option<&[Value]>,
+        limit: i64,
+        query_builder: &mut QueryBuilder<'_, DB>,
+    ) -> Result<(), DBXError> {
+        query_builder.push("select ");
+        Self::push_select_list(query, columns, query_builder)?;
+        query_builder.push(" from ");
+        query_builder.push(DB::quote_ident(&query.table));
+
+        let filtered = Self::push_where(query, columns, query_builder)?;
+        if let Some(after) = after {
+            query_builder.push(if filtered { " and (" } else { " where (" });
+            for (i, key) in keys.iter().enumerate() {
+                if i > 0 {
+                    query_builder.push(" or ");
+                }
+                query_builder.push("(");
+                for (j, previous) in keys[..i].iter().enumerate() {
+                    let column = &previous.column;
+                    query_builder.push(DB::quote_ident(column));
+                    query_builder.push(" = ");
+                    DB::push_value(query_builder, column, &columns[column], &after[j])?;
+                    query_builder.push(" and ");
+                }
+                let column = &key.column;
+                query_builder.push(DB::quote_ident(column));
+                query_builder.push(if key.descending { " < " } else { " > " });
+                DB::push_value(query_builder, column, &columns[column], &after[i])?;
+                query_builder.push(")");
+            }
+            query_builder.push(")");
+        }
+
+        Sort::push(keys, query_builder);
+        query_builder.push(" limit ");
+        DB::push_bind(query_builder, Bind::Int(limit));
+        Ok(())
+    }
+}
+
+/// Encodes the key values of `row` with the keys they belong to, so a
+/// cursor cannot be replayed against another sort.
+fn encode_cursor(row: &QueryResult, keys: &[Sort]) -> String {
+    let keyset: Vec<Value> = keys
+        .iter()
+        .map(|key| {
+            let value = row.get(&key.column).cloned().unwrap_or(Value::Null);
+            serde_json::json!([key.column, key.descending, value])
+        })
+        .collect();
+    URL_SAFE_NO_PAD.encode(Value::Array(keyset).to_string())
+}
+
+fn page_too_far() -> DBXError {
+    DBXError::InvalidPagination("page is out of range".to_string())
+}
+
+fn decode_cursor(cursor: &str, keys: &[Sort]) -> Result<Vec<Value>, DBXError> {
+    let invalid = || DBXError::InvalidPagination("Invalid cursor".to_string());
+    let json = URL_SAFE_NO_PAD.decode(cursor).map_err(|_| invalid())?;
+    let keyset: Vec<(String, bool, Value)> =
+        serde_json::from_slice(&json).map_err(|_| invalid())?;
+    let matches = keyset.len() == keys.len()
+        && keyset
+            .iter()
+            .zip(keys)
+            .all(|((column, descending, _), key)| {
+                *column == key.column && *descending == key.descending
+            });
+    if !matches {
+        return Err(DBXError::InvalidPagination(
+            "Cursor belongs to another sort order".to_string(),
+        ));
+    }
+    Ok(keyset.into_iter().map(|(_, _, value)| value).collect())
+}
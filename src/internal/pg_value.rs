@@ -0,0 +1,323 @@
+//! Decodes Postgres values from their binary wire format, for the types
+//! sqlx is not built to decode here and for the elements of arrays and
+//! ranges.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use chrono::{TimeZone, Utc};
+use serde_json::{json, Value};
+use sqlx::postgres::{PgTypeInfo, PgTypeKind, PgValueFormat, PgValueRef};
+use sqlx::{TypeInfo, ValueRef};
+
+use crate::internal::temporal::TemporalFormat;
+
+/// Decodes a value that is not null, or says why it cannot.
+pub(super) fn decode(value: PgValueRef<'_>) -> Result<Value, String> {
+    let type_info = value.type_info().into_owned();
+    let bytes = value.as_bytes().map_err(|e| e.to_string())?;
+    if value.format() == PgValueFormat::Text {
+        return Ok(text(bytes));
+    }
+    decode_binary(bytes, &type_info)
+        .ok_or_else(|| format!("Unsupported column type {}", type_info.name()))
+}
+
+fn decode_binary(bytes: &[u8], type_info: &PgTypeInfo) -> Option<Value> {
+    match type_info.kind() {
+        PgTypeKind::Enum(_) => return Some(text(bytes)),
+        PgTypeKind::Array(element) => return array(bytes, element),
+        PgTypeKind::Range(element) => return range(bytes, element),
+        PgTypeKind::Domain(base) => return decode_binary(bytes, base),
+        _ => {}
+    }
+    let value = match type_info.name() {
+        "BOOL" => Value::Bool(*bytes.first()? != 0),
+        "INT2" => json!(i16::from_be_bytes(bytes.try_into().ok()?)),
+        "INT4" => json!(i32::from_be_bytes(bytes.try_into().ok()?)),
+        "INT8" => json!(i64::from_be_bytes(bytes.try_into().ok()?)),
+        "OID" => json!(u32::from_be_bytes(bytes.try_into().ok()?)),
+        "FLOAT4" => json!(f32::from_be_bytes(bytes.try_into().ok()?)),
+        "FLOAT8" => json!(f64::from_be_bytes(bytes.try_into().ok()?)),
+        "TEXT" | "VARCHAR" | "CHAR" | "\"CHAR\"" | "NAME" | "CITEXT" => text(bytes),
+        "JSON" => serde_json::from_slice(bytes).ok()?,
+        // Prefixed by a format version.
+        "JSONB" => serde_json::from_slice(bytes.get(1..)?).ok()?,
+        "NUMERIC" => Value::String(numeric(bytes)?),
+        "MONEY" => Value::String(money(i64::from_be_bytes(bytes.try_into().ok()?))),
+        "UUID" => Value::String(uuid(bytes)?),
+        "BYTEA" => Value::String(STANDARD.encode(bytes)),
+        "INET" | "CIDR" => Value::String(inet(bytes)?),
+        "INTERVAL" => Value::String(interval(bytes)?),
+        "DATE" => Value::String(date(i32::from_be_bytes(bytes.try_into().ok()?))?.to_string()),
+        "TIME" => Value::String(time(i64::from_be_bytes(bytes.try_into().ok()?))?.to_string()),
+        "TIMETZ" => Value::String(timetz(bytes)?),
+        "TIMESTAMP" => {
+            let micros = i64::from_be_bytes(bytes.try_into().ok()?);
+            TemporalFormat::current().naive_timestamp(timestamp(micros)?)
+        }
+        "TIMESTAMPTZ" => {
+            let micros = i64::from_be_bytes(bytes.try_into().ok()?);
+            let value = Utc.from_utc_datetime(&timestamp(micros)?);
+            TemporalFormat::current().timestamp(value)
+        }
+        _ => return None,
+    };
+    Some(value)
+}
+
+fn text(bytes: &[u8]) -> Value {
+    Value::String(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// Reads a big-endian integer of `N` bytes off the front of `bytes`.
+fn take<const N: usize>(bytes: &mut &[u8]) -> Option<[u8; N]> {
+    let (head, rest) = (bytes.get(..N)?, bytes.get(N..)?);
+    *bytes = rest;
+    head.try_into().ok()
+}
+
+/// Reads a length-prefixed element, `None` inside when it is null.
+fn take_element<'a>(bytes: &mut &'a [u8]) -> Option<Option<&'a [u8]>> {
+    let len = i32::from_be_bytes(take(bytes)?);
+    if len < 0 {
+        return Some(None);
+    }
+    let (element, rest) = bytes.split_at_checked(len as usize)?;
+    *bytes = rest;
+    Some(Some(element))
+}
+
+/// Nested JSON arrays, one level per dimension.
+fn array(mut bytes: &[u8], element: &PgTypeInfo) -> Option<Value> {
+    let dimensions = i32::from_be_bytes(take(&mut bytes)?);
+    take::<4>(&mut bytes)?; // whether it has nulls
+    take::<4>(&mut bytes)?; // element oid
+    let mut lengths = Vec::new();
+    for _ in 0..dimensions {
+        lengths.push(i32::from_be_bytes(take(&mut bytes)?).max(0) as usize);
+        take::<4>(&mut bytes)?; // lower bound
+    }
+    if lengths.is_empty() {
+        return Some(Value::Array(Vec::new()));
+    }
+    array_dimension(&mut bytes, &lengths, element)
+}
+
+fn array_dimension(bytes: &mut &[u8], lengths: &[usize], element: &PgTypeInfo) -> Option<Value> {
+    let mut values = Vec::with_capacity(lengths[0]);
+    for _ in 0..lengths[0] {
+        let value = if lengths.len() > 1 {
+            array_dimension(bytes, &lengths[1..], element)?
+        } else {
+            match take_element(bytes)? {
+                Some(value) => decode_binary(value, element)?,
+                None => Value::Null,
+            }
+        };
+        values.push(value);
+    }
+    Some(Value::Array(values))
+}
+
+/// `{lower, upper, bounds}` where unbounded ends are null and `bounds` is
+/// e.g. `[)`; empty ranges have `bounds` `empty`.
+fn range(mut bytes: &[u8], element: &PgTypeInfo) -> Option<Value> {
+    const EMPTY: u8 = 0x01;
+    const LOWER_INCLUSIVE: u8 = 0x02;
+    const UPPER_INCLUSIVE: u8 = 0x04;
+    const LOWER_INFINITE: u8 = 0x08;
+    const UPPER_INFINITE: u8 = 0x10;
+
+    let [flags] = take::<1>(&mut bytes)?;
+    if flags & EMPTY != 0 {
+        return Some(json!({ "lower": null, "upper": null, "bounds": "empty" }));
+    }
+    let mut bound = |infinite: u8| -> Option<Value> {
+        if flags & infinite != 0 {
+            return Some(Value::Null);
+        }
+        let value = take_element(&mut bytes)??;
+        decode_binary(value, element)
+    };
+    let lower = bound(LOWER_INFINITE)?;
+    let upper = bound(UPPER_INFINITE)?;
+    let bounds = format!(
+        "{}{}",
+        if flags & LOWER_INCLUSIVE != 0 {
+            '['
+        } else {
+            '('
+        },
+        if flags & UPPER_INCLUSIVE != 0 {
+            ']'
+        } else {
+            ')'
+        },
+    );
+    Some(json!({ "lower": lower, "upper": upper, "bounds": bounds }))
+}
+
+/// The exact decimal text, from base-10000 digits.
+fn numeric(mut bytes: &[u8]) -> Option<String> {
+    let count = i16::from_be_bytes(take(&mut bytes)?).max(0) as usize;
+    let weight = i16::from_be_bytes(take(&mut bytes)?) as i32;
+    let sign = u16::from_be_bytes(take(&mut bytes)?);
+    let scale = u16::from_be_bytes(take(&mut bytes)?) as usize;
+    match sign {
+        0xC000 => return Some("NaN".into()),
+        0xD000 => return Some("Infinity".into()),
+        0xF000 => return Some("-Infinity".into()),
+        _ => {}
+    }
+    let mut digits = Vec::with_capacity(count);
+    for _ in 0..count {
+        digits.push(i16::from_be_bytes(take(&mut bytes)?));
+    }
+    let digit = |i: i32| -> i16 {
+        usize::try_from(i)
+            .ok()
+            .and_then(|i| digits.get(i).copied())
+            .unwrap_or(0)
+    };
+
+    let mut number = String::new();
+    if sign == 0x4000 {
+        number.push('-');
+    }
+    if weight < 0 {
+        number.push('0');
+    } else {
+        number.push_str(&digit(0).to_string());
+        for i in 1..=weight {
+            number.push_str(&format!("{:04}", digit(i)));
+        }
+    }
+    if scale > 0 {
+        let mut fraction = String::new();
+        let mut i = weight + 1;
+        while fraction.len() < scale {
+            fraction.push_str(&format!("{:04}", digit(i)));
+            i += 1;
+        }
+        fraction.truncate(scale);
+        number.push('.');
+        number.push_str(&fraction);
+    }
+    Some(number)
+}
+
+/// Cents, assuming the usual two fractional digits.
+fn money(cents: i64) -> String {
+    let sign = if cents < 0 { "-" } else { "" };
+    let cents = cents.unsigned_abs();
+    format!("{}{}.{:02}", sign, cents / 100, cents % 100)
+}
+
+fn uuid(bytes: &[u8]) -> Option<String> {
+    if bytes.len() != 16 {
+        return None;
+    }
+    let hex: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    Some(format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    ))
+}
+
+/// The address, with its prefix length for networks.
+fn inet(mut bytes: &[u8]) -> Option<String> {
+    let [family, bits, is_cidr, _] = take::<4>(&mut bytes)?;
+    let (address, max_bits) = match family {
+        2 => (
+            Ipv4Addr::from(<[u8; 4]>::try_from(bytes).ok()?).to_string(),
+            32,
+        ),
+        3 => (
+            Ipv6Addr::from(<[u8; 16]>::try_from(bytes).ok()?).to_string(),
+            128,
+        ),
+        _ => return None,
+    };
+    if is_cidr == 0 && bits == max_bits {
+        Some(address)
+    } else {
+        Some(format!("{}/{}", address, bits))
+    }
+}
+
+/// An ISO 8601 duration such as `P1Y2M3DT4H5M6.5S`.
+fn interval(mut bytes: &[u8]) -> Option<String> {
+    let micros = i64::from_be_bytes(take(&mut bytes)?);
+    let days = i32::from_be_bytes(take(&mut bytes)?);
+    let months = i32::from_be_bytes(take(&mut bytes)?);
+
+    let mut duration = String::from("P");
+    for (amount, unit) in [(months / 12, 'Y'), (months % 12, 'M'), (days, 'D')] {
+        if amount != 0 {
+            duration.push_str(&format!("{}{}", amount, unit));
+        }
+    }
+    if micros != 0 {
+        duration.push('T');
+        let hours = micros / 3_600_000_000;
+        let minutes = micros / 60_000_000 % 60;
+        let seconds = micros % 60_000_000;
+        if hours != 0 {
+            duration.push_str(&format!("{}H", hours));
+        }
+        if minutes != 0 {
+            duration.push_str(&format!("{}M", minutes));
+        }
+        if seconds != 0 {
+            let sign = if seconds < 0 { "-" } else { "" };
+            let seconds = seconds.unsigned_abs();
+            let fraction = format!("{:06}", seconds % 1_000_000);
+            let fraction = fraction.trim_end_matches('0');
+            duration.push_str(&format!("{}{}", sign, seconds / 1_000_000));
+            if !fraction.is_empty() {
+                duration.push('.');
+                duration.push_str(fraction);
+            }
+            duration.push('S');
+        }
+    }
+    if duration == "P" {
+        duration.push_str("T0S");
+    }
+    Some(duration)
+}
+
+/// Dates and timestamps count from 2000-01-01.
+fn epoch() -> Option<chrono::NaiveDateTime> {
+    chrono::NaiveDate::from_ymd_opt(2000, 1, 1)?.and_hms_opt(0, 0, 0)
+}
+
+fn date(days: i32) -> Option<chrono::NaiveDate> {
+    let epoch = epoch()?.date();
+    epoch.checked_add_signed(chrono::Duration::days(days.into()))
+}
+
+fn time(micros: i64) -> Option<chrono::NaiveTime> {
+    chrono::NaiveTime::from_num_seconds_from_midnight_opt(
+        u32::try_from(micros / 1_000_000).ok()?,
+        u32::try_from(micros % 1_000_000 * 1_000).ok()?,
+    )
+}
+
+/// The time followed by its UTC offset, e.g. `10:30:00+02:00`.
+fn timetz(mut bytes: &[u8]) -> Option<String> {
+    let micros = i64::from_be_bytes(take(&mut bytes)?);
+    // Stored in seconds west of UTC.
+    let offset = chrono::FixedOffset::west_opt(i32::from_be_bytes(take(&mut bytes)?))?;
+    Some(format!("{}{}", time(micros)?, offset))
+}
+
+fn timestamp(micros: i64) -> Option<chrono::NaiveDateTime> {
+    epoch()?.checked_add_signed(chrono::Duration::microseconds(micros))
+}
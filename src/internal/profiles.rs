@@ -0,0 +1,174 @@
+use std::fmt;
+use std::time::Duration;
+
+use serde::Serialize;
+use sqlx::SqlitePool;
+
+use crate::internal::db::driver_for_url;
+use crate::internal::secret::{SecretError, SecretKey};
+use crate::utils::db::PoolConfig;
+
+/// A saved target database connection. The URL carries the credentials and
+/// is only ever stored encrypted with the server key.
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub label: Option<String>,
+    pub db_url: String,
+    pub max_connections: u32,
+    /// Seconds to wait for a pooled connection.
+    pub acquire_timeout: Option<u32>,
+    /// Seconds an idle connection is kept open.
+    pub idle_timeout: Option<u32>,
+    pub is_default: bool,
+}
+
+/// What the API exposes about a profile; never includes the URL.
+#[derive(Serialize, sqlx::FromRow)]
+pub struct ProfileInfo {
+    pub name: String,
+    pub label: Option<String>,
+    pub driver: String,
+    pub max_connections: u32,
+    pub acquire_timeout: Option<u32>,
+    pub idle_timeout: Option<u32>,
+    pub is_default: bool,
+}
+
+#[derive(sqlx::FromRow)]
+struct ProfileRow {
+    name: String,
+    label: Option<String>,
+    db_url: String,
+    max_connections: u32,
+    acquire_timeout: Option<u32>,
+    idle_timeout: Option<u32>,
+    is_default: bool,
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Sqlx(sqlx::Error),
+    Secret(SecretError),
+    UnsupportedUrl,
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::Sqlx(e) => write!(f, "{}", e),
+            ProfileError::Secret(e) => write!(f, "{}", e),
+            ProfileError::UnsupportedUrl => write!(f, "Unsupported database url"),
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {}
+
+impl From<sqlx::Error> for ProfileError {
+    fn from(e: sqlx::Error) -> Self {
+        ProfileError::Sqlx(e)
+    }
+}
+
+impl From<SecretError> for ProfileError {
+    fn from(e: SecretError) -> Self {
+        ProfileError::Secret(e)
+    }
+}
+
+impl Profile {
+    pub fn pool_config(&self) -> PoolConfig {
+        PoolConfig {
+            max_connections: self.max_connections,
+            acquire_timeout: self.acquire_timeout.map(|s| Duration::from_secs(s.into())),
+            idle_timeout: self.idle_timeout.map(|s| Duration::from_secs(s.into())),
+        }
+    }
+
+    fn from_row(row: ProfileRow, key: &SecretKey) -> Result<Self, ProfileError> {
+        Ok(Profile {
+            name: row.name,
+            label: row.label,
+            db_url: key.decrypt(&row.db_url)?,
+            max_connections: row.max_connections,
+            acquire_timeout: row.acquire_timeout,
+            idle_timeout: row.idle_timeout,
+            is_default: row.is_default,
+        })
+    }
+}
+
+pub async fn list(pool: &SqlitePool) -> Result<Vec<ProfileInfo>, sqlx::Error> {
+    sqlx::query_as::<_, ProfileInfo>(
+        "select name, label, driver, max_connections, acquire_timeout, idle_timeout, is_default
+        from connection_profiles order by name",
+    )
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn find(
+    pool: &SqlitePool,
+    key: &SecretKey,
+    name: &str,
+) -> Result<Option<Profile>, ProfileError> {
+    let row = sqlx::query_as::<_, ProfileRow>("select * from connection_profiles where name = $1")
+        .bind(name)
+        .fetch_optional(pool)
+        .await?;
+    row.map(|row| Profile::from_row(row, key)).transpose()
+}
+
+/// The profile penkr reconnects to on startup, if one is marked.
+pub async fn find_default(
+    pool: &SqlitePool,
+    key: &SecretKey,
+) -> Result<Option<Profile>, ProfileError> {
+    let row = sqlx::query_as::<_, ProfileRow>("select * from connection_profiles where is_default")
+        .fetch_optional(pool)
+        .await?;
+    row.map(|row| Profile::from_row(row, key)).transpose()
+}
+
+/// Creates or replaces the profile named `profile.name`. Marking it as the
+/// default clears the mark from every other profile.
+pub async fn save(
+    pool: &SqlitePool,
+    key: &SecretKey,
+    profile: &Profile,
+) -> Result<(), ProfileError> {
+    let driver = driver_for_url(&profile.db_url).ok_or(ProfileError::UnsupportedUrl)?;
+    let mut tx = pool.begin().await?;
+    if profile.is_default {
+        sqlx::query("update connection_profiles set is_default = false")
+            .execute(&mut tx)
+            .await?;
+    }
+    sqlx::query(
+        "insert or replace into connection_profiles
+        (name, label, driver, db_url, max_connections, acquire_timeout, idle_timeout, is_default)
+        values ($1, $2, $3, $4, $5, $6, $7, $8)",
+    )
+    .bind(&profile.name)
+    .bind(&profile.label)
+    .bind(driver)
+    .bind(key.encrypt(&profile.db_url))
+    .bind(profile.max_connections)
+    .bind(profile.acquire_timeout)
+    .bind(profile.idle_timeout)
+    .bind(profile.is_default)
+    .execute(&mut tx)
+    .await?;
+    tx.commit().await?;
+    Ok(())
+}
+
+/// Returns whether a profile named `name` existed.
+pub async fn remove(pool: &SqlitePool, name: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("delete from connection_profiles where name = $1")
+        .bind(name)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,225 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+use crate::internal::auth::User;
+use crate::internal::filter::{Expr, FilterError, Literal};
+
+/// Access rules of one collection, written in the filter syntax.
+///
+/// Collections without rules are restricted to admins, as is every operation
+/// whose rule is `null`. An empty rule allows the operation for every
+/// signed-in user. Admins always bypass rules.
+#[derive(Debug, Default, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Rules {
+    pub list_rule: Option<String>,
+    pub view_rule: Option<String>,
+    pub create_rule: Option<String>,
+    pub update_rule: Option<String>,
+    pub delete_rule: Option<String>,
+}
+
+#[derive(Serialize, sqlx::FromRow)]
+pub struct CollectionRules {
+    pub collection: String,
+    #[serde(flatten)]
+    #[sqlx(flatten)]
+    pub rules: Rules,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum Operation {
+    List,
+    View,
+    Create,
+    Update,
+    Delete,
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Operation::List => "list",
+            Operation::View => "view",
+            Operation::Create => "create",
+            Operation::Update => "update",
+            Operation::Delete => "delete",
+        })
+    }
+}
+
+pub enum Access {
+    All,
+    Denied,
+    Filter(Expr),
+}
+
+#[derive(Debug)]
+pub enum RuleError {
+    Sqlx(sqlx::Error),
+    Filter(FilterError),
+}
+
+impl fmt::Display for RuleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuleError::Sqlx(e) => e.fmt(f),
+            RuleError::Filter(e) => write!(f, "Invalid rule: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RuleError {}
+
+impl From<sqlx::Error> for RuleError {
+    fn from(e: sqlx::Error) -> Self {
+        RuleError::Sqlx(e)
+    }
+}
+
+impl From<FilterError> for RuleError {
+    fn from(e: FilterError) -> Self {
+        RuleError::Filter(e)
+    }
+}
+
+impl Rules {
+    fn rule(&self, operation: Operation) -> Option<&str> {
+        match operation {
+            Operation::List => self.list_rule.as_deref(),
+            Operation::View => self.view_rule.as_deref(),
+            Operation::Create => self.create_rule.as_deref(),
+            Operation::Update => self.update_rule.as_deref(),
+            Operation::Delete => self.delete_rule.as_deref(),
+        }
+    }
+
+    /// Checks that every rule parses and only uses known variables.
+    pub fn validate(&self) -> Result<(), FilterError> {
+        let vars = auth_vars(&User {
+            id: 0,
+            username: String::new(),
+            is_admin: false,
+        });
+        for operation in [
+            Operation::List,
+            Operation::View,
+            Operation::Create,
+            Operation::Update,
+            Operation::Delete,
+        ] {
+            if let Some(rule) = self.rule(operation).filter(|rule| !rule.trim().is_empty()) {
+                Expr::parse(rule)?.resolve(&vars)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Variables a rule or filter can reference for the signed-in `user`.
+pub fn auth_vars(user: &User) -> HashMap<String, Literal> {
+    HashMap::from([
+        ("request.auth.id".to_string(), Literal::Int(user.id)),
+        (
+            "request.auth.username".to_string(),
+            Literal::String(user.username.clone()),
+        ),
+        (
+            "request.auth.is_admin".to_string(),
+            Literal::Bool(user.is_admin),
+        ),
+    ])
+}
+
+pub async fn list(
+    pool: &SqlitePool,
+    connection: &str,
+) -> Result<Vec<CollectionRules>, sqlx::Error> {
+    sqlx::query_as::<_, CollectionRules>(
+        "select collection, list_rule, view_rule, create_rule, update_rule, delete_rule
+        from collection_rules where connection = $1 order by collection",
+    )
+    .bind(connection)
+    .fetch_all(pool)
+    .await
+}
+
+pub async fn find(
+    pool: &SqlitePool,
+    connection: &str,
+    collection: &str,
+) -> Result<Option<Rules>, sqlx::Error> {
+    sqlx::query_as::<_, Rules>(
+        "select list_rule, view_rule, create_rule, update_rule, delete_rule
+        from collection_rules where connection = $1 and collection = $2",
+    )
+    .bind(connection)
+    .bind(collection)
+    .fetch_optional(pool)
+    .await
+}
+
+pub async fn save(
+    pool: &SqlitePool,
+    connection: &str,
+    collection: &str,
+    rules: &Rules,
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "insert or replace into collection_rules
+        (connection, collection, list_rule, view_rule, create_rule, update_rule, delete_rule)
+        values ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(connection)
+    .bind(collection)
+    .bind(&rules.list_rule)
+    .bind(&rules.view_rule)
+    .bind(&rules.create_rule)
+    .bind(&rules.update_rule)
+    .bind(&rules.delete_rule)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns whether `collection` had rules.
+pub async fn remove(
+    pool: &SqlitePool,
+    connection: &str,
+    collection: &str,
+) -> Result<bool, sqlx::Error> {
+    let result =
+        sqlx::query("delete from collection_rules where connection = $1 and collection = $2")
+            .bind(connection)
+            .bind(collection)
+            .execute(pool)
+            .await?;
+    Ok(result.rows_affected() > 0)
+}
+
+/// Decides what `user` may do to `collection`: everything, nothing, or only
+/// the rows matching the returned filter.
+pub async fn access(
+    pool: &SqlitePool,
+    connection: &str,
+    collection: &str,
+    operation: Operation,
+    user: &User,
+) -> Result<Access, RuleError> {
+    if user.is_admin {
+        return Ok(Access::All);
+    }
+    let rules = match find(pool, connection, collection).await? {
+        Some(rules) => rules,
+        None => return Ok(Access::Denied),
+    };
+    match rules.rule(operation) {
+        None => Ok(Access::Denied),
+        Some(rule) if rule.trim().is_empty() => Ok(Access::All),
+        Some(rule) => Ok(Access::Filter(
+            Expr::parse(rule)?.resolve(&auth_vars(user))?,
+        )),
+    }
+}
@@ -0,0 +1,262 @@
+use sqlx::{QueryBuilder, SqlitePool};
+
+use crate::internal::{
+    db::{DBXError, Pool, DBX},
+    dialect::{pg_regconfig, Bind, Dialect},
+};
+
+/// The key the relevance of each searched row is returned under, and the
+/// column to sort by it.
+pub const RELEVANCE: &str = "relevance";
+
+/// A search term, resolved to the columns it looks in and how.
+#[derive(Debug, Clone)]
+pub struct Search {
+    pub term: String,
+    pub columns: Vec<String>,
+    /// The index through which the backend's full-text search serves the
+    /// columns: the indexed `to_tsvector` expression on Postgres, the
+    /// `fulltext` index on MySQL and the FTS5 table on SQLite. Without one,
+    /// the columns are matched with `like`.
+    pub full_text: Option<String>,
+}
+
+impl Search {
+    pub(super) fn push_condition<DB: Dialect>(
+        &self,
+        table: &str,
+        query_builder: &mut QueryBuilder<'_, DB>,
+    ) {
+        if let Some(index) = &self.full_text {
+            DB::push_text_match(query_builder, table, &self.columns, index, &self.term);
+            return;
+        }
+        query_builder.push("(");
+        for (i, column) in self.columns.iter().enumerate() {
+            if i > 0 {
+                query_builder.push(" or ");
+            }
+            self.push_like::<DB>(column, query_builder);
+        }
+        query_builder.push(")");
+    }
+
+    /// Pushes the relevance of each row: the full-text rank, or else the
+    /// number of columns that match.
+    pub(super) fn push_relevance<DB: Dialect>(
+        &self,
+        table: &str,
+        query_builder: &mut QueryBuilder<'_, DB>,
+    ) {
+        if let Some(index) = &self.full_text {
+            DB::push_text_rank(query_builder, table, &self.columns, index, &self.term);
+        } else {
+            query_builder.push("(");
+            for (i, column) in self.columns.iter().enumerate() {
+                if i > 0 {
+                    query_builder.push(" + ");
+                }
+                query_builder.push("case when ");
+                self.push_like::<DB>(column, query_builder);
+                query_builder.push(" then 1 else 0 end");
+            }
+            query_builder.push(")");
+        }
+        query_builder.push(" as ");
+        query_builder.push(DB::quote_ident(RELEVANCE));
+    }
+
+    fn push_like<DB: Dialect>(&self, column: &str, query_builder: &mut QueryBuilder<'_, DB>) {
+        query_builder.push(format!("lower({}) like ", DB::quote_ident(column)));
+        let pattern = format!("%{}%", escape_like(&self.term.to_lowercase()));
+        DB::push_bind(query_builder, Bind::Text(pattern));
+        query_builder.push(DB::LIKE_ESCAPE);
+    }
+}
+
+/// Escapes the wildcards of `term`, so `like` matches it literally.
+fn escape_like(term: &str) -> String {
+    let mut escaped = String::with_capacity(term.len());
+    for c in term.chars() {
+        if matches!(c, '\\' | '%' | '_') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Whether a column of `column_type` holds text worth searching; Postgres
+/// names arrays like `_text`.
+fn is_text(column_type: &str) -> bool {
+    let column_type = column_type.to_lowercase();
+    if column_type.starts_with('_') || column_type.ends_with("[]") {
+        return false;
+    }
+    ["char", "text", "clob"]
+        .iter()
+        .any(|text| column_type.contains(text))
+}
+
+impl DBX {
+    /// Resolves a search of `table` for `term` in `columns`, or in every
+    /// text column when none are configured.
+    ///
+    /// Postgres needs a GIN index on a `to_tsvector` expression of exactly
+    /// the searched columns, MySQL a `fulltext` index on them and SQLite an
+    /// FTS5 table named `{table}_fts`; without them, columns are matched
+    /// with `like`.
+    pub async fn search(
+        &self,
+        table: &str,
+        term: &str,
+        columns: &[String],
+    ) -> Result<Search, DBXError> {
+        let types = self.columns(table)?;
+        let columns: Vec<String> = if columns.is_empty() {
+            let mut text: Vec<String> = types
+                .iter()
+                .filter(|(_, column_type)| is_text(column_type))
+                .map(|(column, _)| column.clone())
+                .collect();
+            text.sort();
+            text
+        } else {
+            for column in columns {
+                if !types.contains_key(column) {
+                    return Err(DBXError::UnknownColumn(column.clone()));
+                }
+            }
+            columns.to_vec()
+        };
+        if columns.is_empty() {
+            return Err(DBXError::NotSearchable(table.to_string()));
+        }
+        let full_text = match &self.pool {
+            Pool::Postgres(pool) => {
+                // Columns referenced by an index expression are recorded as
+                // dependencies of the index.
+                let indexes = sqlx::query_as::<_, (String, Vec<String>)>(
+                    "select pg_get_indexdef(i.indexrelid, 1, true),
+                        array(select a.attname::text from pg_depend d
+                            join pg_attribute a on a.attrelid = d.refobjid and a.attnum = d.refobjsubid
+                            where d.classid = 'pg_class'::regclass and d.objid = i.indexrelid
+                            order by 1)
+                    from pg_index i
+                    join pg_class c on c.oid = i.indexrelid
+                    join pg_am am on am.oid = c.relam
+                    join pg_class t on t.oid = i.indrelid
+                    join pg_namespace n on n.oid = t.relnamespace
+                    where n.nspname = $1 and t.relname = $2
+                        and am.amname = 'gin' and i.indnatts = 1
+                        and i.indexprs is not null and i.indpred is null",
+                )
+                .bind(&self.schema().name)
+                .bind(table)
+                .fetch_all(pool)
+                .await?;
+                indexes
+                    .into_iter()
+                    .find(|(expression, indexed)| {
+                        pg_regconfig(expression).is_some()
+                            && indexed.len() == columns.len()
+                            && columns.iter().all(|column| indexed.contains(column))
+                    })
+                    .map(|(expression, _)| expression)
+            }
+            Pool::MySql(pool) => {
+                let indexes = sqlx::query_as::<_, (String, String)>(
+                    "select cast(index_name as char), cast(column_name as char)
+                    from information_schema.statistics
+                    where table_schema = database() and table_name = ?
+                        and index_type = 'FULLTEXT'
+                    order by index_name, seq_in_index",
+                )
+                .bind(table)
+                .fetch_all(pool)
+                .await?;
+                let mut indexed: Vec<(&str, Vec<&str>)> = Vec::new();
+                for (index, column) in &indexes {
+                    match indexed.last_mut() {
+                        Some((last, columns)) if last == index => columns.push(column),
+                        _ => indexed.push((index, vec![column])),
+                    }
+                }
+                indexed
+                    .iter()
+                    .find(|(_, indexed)| {
+                        indexed.len() == columns.len()
+                            && columns
+                                .iter()
+                                .all(|column| indexed.contains(&column.as_str()))
+                    })
+                    .map(|(index, _)| index.to_string())
+            }
+            Pool::Sqlite(pool) => {
+                let index = format!("{}_fts", table);
+                let (found,) = sqlx::query_as::<_, (i64,)>(
+                    "select count(*) from sqlite_master
+                    where type = 'table' and name = ? and sql like '%using fts5%'",
+                )
+                .bind(&index)
+                .fetch_one(pool)
+                .await?;
+                (found > 0).then_some(index)
+            }
+        };
+        Ok(Search {
+            term: term.to_string(),
+            columns,
+            full_text,
+        })
+    }
+}
+
+/// The columns searched in `collection`, when configured.
+pub async fn find(
+    pool: &SqlitePool,
+    connection: &str,
+    collection: &str,
+) -> Result<Option<Vec<String>>, sqlx::Error> {
+    let columns = sqlx::query_as::<_, (String,)>(
+        "select columns from search_columns where connection = $1 and collection = $2",
+    )
+    .bind(connection)
+    .bind(collection)
+    .fetch_optional(pool)
+    .await?;
+    Ok(columns.map(|(columns,)| columns.split(',').map(str::to_string).collect()))
+}
+
+pub async fn save(
+    pool: &SqlitePool,
+    connection: &str,
+    collection: &str,
+    columns: &[String],
+) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "insert or replace into search_columns (connection, collection, columns)
+        values ($1, $2, $3)",
+    )
+    .bind(connection)
+    .bind(collection)
+    .bind(columns.join(","))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Returns whether `collection` had search columns.
+pub async fn remove(
+    pool: &SqlitePool,
+    connection: &str,
+    collection: &str,
+) -> Result<bool, sqlx::Error> {
+    let result =
+        sqlx::query("delete from search_columns where connection = $1 and collection = $2")
+            .bind(connection)
+            .bind(collection)
+            .execute(pool)
+            .await?;
+    Ok(result.rows_affected() > 0)
+}
@@ -0,0 +1,110 @@
+use std::fmt;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::Path;
+
+use aes_gcm::aead::{Aead, KeyInit, OsRng};
+use aes_gcm::{AeadCore, Aes256Gcm, Key, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+const NONCE_LEN: usize = 12;
+
+/// Server key used to encrypt secrets stored in the application database.
+///
+/// Taken from `PENKR_SECRET_KEY` (base64 encoded, 32 bytes) when set,
+/// otherwise read from `key_file`, which is generated on first boot.
+#[derive(Clone)]
+pub struct SecretKey(Aes256Gcm);
+
+#[derive(Debug)]
+pub enum SecretError {
+    InvalidKey(String),
+    Io(std::io::Error),
+    Decrypt,
+}
+
+impl fmt::Display for SecretError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SecretError::InvalidKey(message) => write!(f, "Invalid secret key: {}", message),
+            SecretError::Io(e) => write!(f, "Failed to read secret key: {}", e),
+            SecretError::Decrypt => write!(f, "Failed to decrypt secret, was the key changed?"),
+        }
+    }
+}
+
+impl std::error::Error for SecretError {}
+
+impl From<std::io::Error> for SecretError {
+    fn from(e: std::io::Error) -> Self {
+        SecretError::Io(e)
+    }
+}
+
+impl fmt::Debug for SecretKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("SecretKey(..)")
+    }
+}
+
+impl SecretKey {
+    pub fn load(key_file: &str) -> Result<Self, SecretError> {
+        if let Ok(encoded) = std::env::var("PENKR_SECRET_KEY") {
+            return Self::decode(&encoded);
+        }
+        if Path::new(key_file).exists() {
+            return Self::decode(&std::fs::read_to_string(key_file)?);
+        }
+        let key = Aes256Gcm::generate_key(OsRng);
+        // Created readable by the owner only, so the key is never exposed,
+        // and never over a file that appeared since the check above.
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        options
+            .open(key_file)?
+            .write_all(STANDARD.encode(key).as_bytes())?;
+        Ok(Self(Aes256Gcm::new(&key)))
+    }
+
+    fn decode(encoded: &str) -> Result<Self, SecretError> {
+        let bytes = STANDARD
+            .decode(encoded.trim())
+            .map_err(|e| SecretError::InvalidKey(e.to_string()))?;
+        if bytes.len() != 32 {
+            return Err(SecretError::InvalidKey("expected 32 bytes".to_string()));
+        }
+        Ok(Self(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&bytes))))
+    }
+
+    /// Encrypts `plaintext` into base64 of the random nonce followed by the
+    /// ciphertext.
+    pub fn encrypt(&self, plaintext: &str) -> String {
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let ciphertext = self
+            .0
+            .encrypt(&nonce, plaintext.as_bytes())
+            .expect("AES-GCM encryption cannot fail for in-memory buffers");
+        let mut sealed = nonce.to_vec();
+        sealed.extend(ciphertext);
+        STANDARD.encode(sealed)
+    }
+
+    pub fn decrypt(&self, sealed: &str) -> Result<String, SecretError> {
+        let sealed = STANDARD.decode(sealed).map_err(|_| SecretError::Decrypt)?;
+        if sealed.len() < NONCE_LEN {
+            return Err(SecretError::Decrypt);
+        }
+        let (nonce, ciphertext) = sealed.split_at(NONCE_LEN);
+        let plaintext = self
+            .0
+            .decrypt(Nonce::from_slice(nonce), ciphertext)
+            .map_err(|_| SecretError::Decrypt)?;
+        String::from_utf8(plaintext).map_err(|_| SecretError::Decrypt)
+    }
+}
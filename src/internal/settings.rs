@@ -0,0 +1,31 @@
+use sqlx::SqlitePool;
+
+/// The server-wide temporal format, taking precedence over
+/// `PENKR_TEMPORAL_FORMAT`.
+pub const TEMPORAL_FORMAT: &str = "temporal_format";
+
+pub async fn find(pool: &SqlitePool, key: &str) -> Result<Option<String>, sqlx::Error> {
+    let value = sqlx::query_as::<_, (String,)>("select value from settings where key = $1")
+        .bind(key)
+        .fetch_optional(pool)
+        .await?;
+    Ok(value.map(|(value,)| value))
+}
+
+pub async fn save(pool: &SqlitePool, key: &str, value: &str) -> Result<(), sqlx::Error> {
+    sqlx::query("insert or replace into settings (key, value) values ($1, $2)")
+        .bind(key)
+        .bind(value)
+        .execute(pool)
+        .await?;
+    Ok(())
+}
+
+/// Returns whether `key` was set.
+pub async fn remove(pool: &SqlitePool, key: &str) -> Result<bool, sqlx::Error> {
+    let result = sqlx::query("delete from settings where key = $1")
+        .bind(key)
+        .execute(pool)
+        .await?;
+    Ok(result.rows_affected() > 0)
+}
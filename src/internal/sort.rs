@@ -0,0 +1,104 @@
+use sqlx::QueryBuilder;
+
+use crate::internal::{db::DBXError, dialect::Dialect};
+
+/// One column of a sort such as `-created_at,name:nulls_last`, where `-`
+/// makes a column descending.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Sort {
+    pub column: String,
+    pub descending: bool,
+    /// Where nulls go; the backend's default when unset.
+    pub nulls: Option<Nulls>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Nulls {
+    First,
+    Last,
+}
+
+impl Sort {
+    pub fn parse(sort: &str) -> Result<Vec<Sort>, DBXError> {
+        sort.split(',')
+            .map(str::trim)
+            .filter(|term| !term.is_empty())
+            .map(|term| {
+                let (column, nulls) = match term.rsplit_once(':') {
+                    Some((column, "nulls_first")) => (column, Some(Nulls::First)),
+                    Some((column, "nulls_last")) => (column, Some(Nulls::Last)),
+                    Some((_, nulls)) => {
+                        return Err(DBXError::InvalidSort(format!(
+                            "Expected nulls_first or nulls_last, got {}",
+                            nulls
+                        )))
+                    }
+                    None => (term, None),
+                };
+                let (column, descending) = match column.strip_prefix('-') {
+                    Some(column) => (column, true),
+                    None => (column.strip_prefix('+').unwrap_or(column), false),
+                };
+                if column.is_empty() {
+                    return Err(DBXError::InvalidSort(format!("Missing column in {}", term)));
+                }
+                Ok(Sort {
+                    column: column.to_string(),
+                    descending,
+                    nulls,
+                })
+            })
+            .collect()
+    }
+
+    /// The sort given as `order_by=name&order=desc`.
+    pub fn from_order(order_by: &str, order: Option<&str>) -> Result<Sort, DBXError> {
+        let descending = match order.map(str::to_lowercase).as_deref() {
+            None | Some("asc") => false,
+            Some("desc") => true,
+            Some(order) => {
+                return Err(DBXError::InvalidSort(format!(
+                    "Expected asc or desc, got {}",
+                    order
+                )))
+            }
+        };
+        Ok(Sort {
+            column: order_by.to_string(),
+            descending,
+            nulls: None,
+        })
+    }
+
+    /// Pushes ` order by` and `sorts`, if any.
+    pub(super) fn push<DB: Dialect>(sorts: &[Sort], query_builder: &mut QueryBuilder<'_, DB>) {
+        if sorts.is_empty() {
+            return;
+        }
+        let terms: Vec<String> = sorts.iter().map(Sort::term::<DB>).collect();
+        query_builder.push(" order by ");
+        query_builder.push(terms.join(", "));
+    }
+
+    fn term<DB: Dialect>(&self) -> String {
+        let column = DB::quote_ident(&self.column);
+        let direction = if self.descending { "desc" } else { "asc" };
+        match self.nulls {
+            None => format!("{} {}", column, direction),
+            Some(nulls) if DB::NULLS_ORDER => {
+                let nulls = match nulls {
+                    Nulls::First => "first",
+                    Nulls::Last => "last",
+                };
+                format!("{} {} nulls {}", column, direction, nulls)
+            }
+            Some(nulls) => {
+                let nulls = match nulls {
+                    Nulls::First => "desc",
+                    Nulls::Last => "asc",
+                };
+                format!("{} is null {}, {} {}", column, nulls, column, direction)
+            }
+        }
+    }
+}
@@ -0,0 +1,82 @@
+use std::pin::Pin;
+
+use futures_util::{stream, Stream, StreamExt};
+use sqlx::QueryBuilder;
+use tokio::sync::mpsc;
+
+use crate::internal::{
+    db::{with_pool, DBQuery, DBXError, Pool, DBX},
+    de::QueryResult,
+    temporal::TemporalFormat,
+};
+
+/// How many rows are read ahead of the consumer before the query waits.
+const BUFFERED_ROWS: usize = 256;
+
+/// Rows as the database returns them. The query stops when the stream is
+/// dropped, and after the first error it yields.
+pub type RowStream = Pin<Box<dyn Stream<Item = Result<QueryResult, DBXError>>>>;
+
+impl DBX {
+    /// Streams the rows matching `query` without holding them all.
+    pub fn select_stream(&self, query: DBQuery) -> Result<RowStream, DBXError> {
+        let columns = self.columns(&query.table)?;
+        Self::check_columns(&query, &columns)?;
+        let pool = self.pool.clone();
+        Ok(spawn_rows(|sender| async move {
+            with_pool!(&pool, pool => {
+                let mut query_builder = QueryBuilder::new("select ");
+                Self::query_filter(&query, &columns, &mut query_builder)?;
+                let rows = query_builder.build_query_as::<QueryResult>().fetch(pool);
+                forward(rows, &sender).await;
+            });
+            Ok(())
+        }))
+    }
+
+    /// Streams the rows of a raw `query`.
+    pub fn raw_stream(&self, query: String) -> RowStream {
+        let pool = self.pool.clone();
+        spawn_rows(|sender| async move {
+            with_pool!(&pool, pool => {
+                let rows = sqlx::query_as::<_, QueryResult>(&query).fetch(pool);
+                forward(rows, &sender).await;
+            });
+            Ok(())
+        })
+    }
+}
+
+type Sender = mpsc::Sender<Result<QueryResult, DBXError>>;
+
+/// Runs `produce` on its own task, in the temporal format of the caller,
+/// and streams what it sends, or the error it fails with.
+fn spawn_rows<F, Fut>(produce: F) -> RowStream
+where
+    F: FnOnce(Sender) -> Fut,
+    Fut: std::future::Future<Output = Result<(), DBXError>> + 'static,
+{
+    let (sender, receiver) = mpsc::channel(BUFFERED_ROWS);
+    let task = produce(sender.clone());
+    actix_web::rt::spawn(TemporalFormat::current().scope(async move {
+        if let Err(e) = task.await {
+            sender.send(Err(e)).await.ok();
+        }
+    }));
+    Box::pin(stream::unfold(receiver, |mut receiver| async move {
+        receiver.recv().await.map(|row| (row, receiver))
+    }))
+}
+
+/// Sends `rows` on until the receiver goes away or a row fails.
+async fn forward<S>(mut rows: S, sender: &Sender)
+where
+    S: Stream<Item = Result<QueryResult, sqlx::Error>> + Unpin,
+{
+    while let Some(row) = rows.next().await {
+        let failed = row.is_err();
+        if sender.send(row.map_err(DBXError::from)).await.is_err() || failed {
+            break;
+        }
+    }
+}
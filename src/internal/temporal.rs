@@ -0,0 +1,129 @@
+use std::future::Future;
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use serde_json::Value;
+
+tokio::task_local! {
+    static FORMAT: TemporalFormat;
+}
+
+/// How timestamps are written into and read from JSON. Dates and times of
+/// day are always `YYYY-MM-DD` and `HH:MM:SS[.ffffff]` strings.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TemporalFormat {
+    /// Whole seconds since the Unix epoch.
+    #[default]
+    EpochSeconds,
+    EpochMillis,
+    EpochMicros,
+    /// RFC 3339 with microseconds; timestamps without a time zone have no
+    /// offset.
+    Rfc3339,
+}
+
+impl TemporalFormat {
+    pub fn parse(format: &str) -> Option<Self> {
+        match format {
+            "epoch_seconds" => Some(Self::EpochSeconds),
+            "epoch_millis" => Some(Self::EpochMillis),
+            "epoch_micros" => Some(Self::EpochMicros),
+            "rfc3339" => Some(Self::Rfc3339),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::EpochSeconds => "epoch_seconds",
+            Self::EpochMillis => "epoch_millis",
+            Self::EpochMicros => "epoch_micros",
+            Self::Rfc3339 => "rfc3339",
+        }
+    }
+
+    /// The server-wide format unless one is saved in the settings, from
+    /// `PENKR_TEMPORAL_FORMAT` when set.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("PENKR_TEMPORAL_FORMAT") {
+            Ok(format) => Self::parse(&format).ok_or_else(|| {
+                format!(
+                    "PENKR_TEMPORAL_FORMAT must be epoch_seconds, epoch_millis, epoch_micros or rfc3339, got {}",
+                    format
+                )
+            }),
+            Err(_) => Ok(Self::default()),
+        }
+    }
+
+    /// Runs `future` with rows decoded and values parsed in this format.
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        FORMAT.scope(self, future).await
+    }
+
+    /// Runs `f` with rows decoded and values parsed in this format.
+    pub fn sync_scope<R>(self, f: impl FnOnce() -> R) -> R {
+        FORMAT.sync_scope(self, f)
+    }
+
+    /// The format of the current scope, or the default outside of any.
+    pub fn current() -> Self {
+        FORMAT.try_with(|format| *format).unwrap_or_default()
+    }
+
+    /// Ticks per second of the epoch formats; RFC 3339 reads bare numbers
+    /// as seconds.
+    fn per_second(self) -> i64 {
+        match self {
+            Self::EpochSeconds | Self::Rfc3339 => 1,
+            Self::EpochMillis => 1_000,
+            Self::EpochMicros => 1_000_000,
+        }
+    }
+
+    pub fn timestamp(self, value: DateTime<Utc>) -> Value {
+        match self {
+            Self::EpochSeconds => value.timestamp().into(),
+            Self::EpochMillis => value.timestamp_millis().into(),
+            Self::EpochMicros => value.timestamp_micros().into(),
+            Self::Rfc3339 => value
+                .to_rfc3339_opts(chrono::SecondsFormat::AutoSi, true)
+                .into(),
+        }
+    }
+
+    /// Epoch formats take timestamps without a time zone to be in UTC.
+    pub fn naive_timestamp(self, value: NaiveDateTime) -> Value {
+        match self {
+            Self::Rfc3339 => value.format("%Y-%m-%dT%H:%M:%S%.f").to_string().into(),
+            _ => self.timestamp(Utc.from_utc_datetime(&value)),
+        }
+    }
+
+    /// Reads a timestamp written in this format, or as an RFC 3339 or
+    /// `YYYY-MM-DD HH:MM:SS` string in any of them. Strings without an
+    /// offset are taken to be in UTC.
+    pub fn parse_timestamp(self, value: &Value) -> Option<DateTime<Utc>> {
+        let ticks = match value {
+            Value::Number(number) => number.as_i64()?,
+            Value::String(value) => match value.parse::<i64>() {
+                Ok(ticks) => ticks,
+                Err(_) => return parse_timestamp(value),
+            },
+            _ => return None,
+        };
+        let per_second = self.per_second();
+        let nanos = ticks.rem_euclid(per_second) * (1_000_000_000 / per_second);
+        Utc.timestamp_opt(ticks.div_euclid(per_second), nanos as u32)
+            .single()
+    }
+}
+
+fn parse_timestamp(value: &str) -> Option<DateTime<Utc>> {
+    if let Ok(value) = DateTime::parse_from_rfc3339(value) {
+        return Some(value.with_timezone(&Utc));
+    }
+    ["%Y-%m-%dT%H:%M:%S%.f", "%Y-%m-%d %H:%M:%S%.f"]
+        .iter()
+        .find_map(|format| NaiveDateTime::parse_from_str(value, format).ok())
+        .map(|value| Utc.from_utc_datetime(&value))
+}
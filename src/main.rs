@@ -1,21 +1,66 @@
+mod api;
 mod internal;
 mod models;
 mod utils;
-mod api;
 
-use std::sync::Mutex;
+use std::sync::RwLock;
 
 use actix_cors::Cors;
-use actix_web::{get, middleware::Logger, web, App, HttpServer, Responder};
 use actix_files as fs;
+use actix_web::{get, middleware::Logger, web, App, HttpServer, Responder};
 
-use crate::utils::db::get_sqlite_pool;
+use crate::internal::auth;
+use crate::internal::connections::Connections;
 use crate::internal::db::DBX;
+use crate::internal::profiles;
+use crate::internal::secret::SecretKey;
+use crate::internal::settings;
+use crate::internal::temporal::TemporalFormat;
+use crate::utils::db::{get_sqlite_pool, PoolConfig};
 
 #[derive(Debug)]
 pub struct AppState {
-    dbx: Mutex<Option<DBX>>,
+    connections: Connections,
     sqlite_pool: sqlx::SqlitePool,
+    secret_key: SecretKey,
+    /// Used unless a request asks for another.
+    temporal_format: RwLock<TemporalFormat>,
+}
+
+impl AppState {
+    fn temporal_format(&self) -> TemporalFormat {
+        *self
+            .temporal_format
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn set_temporal_format(&self, format: TemporalFormat) {
+        *self
+            .temporal_format
+            .write()
+            .unwrap_or_else(|e| e.into_inner()) = format;
+    }
+}
+
+/// Reconnects to the profile marked as default, if any. Failures are logged
+/// rather than fatal so a down target database doesn't keep penkr from booting.
+async fn connect_default_profile(state: &AppState) {
+    let profile = match profiles::find_default(&state.sqlite_pool, &state.secret_key).await {
+        Ok(Some(profile)) => profile,
+        Ok(None) => return,
+        Err(e) => {
+            log::error!("Failed to load default connection profile: {}", e);
+            return;
+        }
+    };
+    match DBX::new(&profile.pool_config(), &profile.db_url).await {
+        Ok(dbx) => {
+            state.connections.insert(&profile.name, dbx).ok();
+            log::info!("Connected to default profile {}", profile.name);
+        }
+        Err(e) => log::error!("Failed to connect to profile {}: {}", profile.name, e),
+    }
 }
 
 #[get("/{tail:.*}")]
@@ -25,6 +70,8 @@ async fn index() -> impl Responder {
 
 #[actix_web::main]
 async fn main() -> std::io::Result<()> {
+    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+
     if !std::path::Path::new("db").exists() {
         std::fs::create_dir("db").expect("Failed to create db directory");
     }
@@ -33,14 +80,37 @@ async fn main() -> std::io::Result<()> {
         std::fs::File::create("db/pnkr.db").expect("Failed to create pnkr.db");
     }
 
-    let sqlite_pool = get_sqlite_pool(5, "sqlite://db/pnkr.db").await.expect("Failed to connect to application database");
+    let sqlite_pool = get_sqlite_pool(&PoolConfig::new(5), "sqlite://db/pnkr.db")
+        .await
+        .expect("Failed to connect to application database");
+
+    auth::upgrade_legacy_users(&sqlite_pool)
+        .await
+        .expect("Failed to upgrade the users table");
+
+    sqlx::migrate!()
+        .run(&sqlite_pool)
+        .await
+        .expect("Failed to migrate application database");
+
+    let secret_key = SecretKey::load("db/secret.key").expect("Failed to load secret key");
+
+    let temporal_format = match settings::find(&sqlite_pool, settings::TEMPORAL_FORMAT)
+        .await
+        .expect("Failed to load settings")
+    {
+        Some(format) => TemporalFormat::parse(&format).expect("Invalid saved temporal format"),
+        None => TemporalFormat::from_env().expect("Invalid temporal format"),
+    };
 
     let app_state = web::Data::new(AppState {
-        dbx: Mutex::new(None),
+        connections: Connections::default(),
         sqlite_pool,
+        secret_key,
+        temporal_format: RwLock::new(temporal_format),
     });
 
-    env_logger::init_from_env(env_logger::Env::default().default_filter_or("info"));
+    connect_default_profile(&app_state).await;
 
     HttpServer::new(move || {
         App::new()
@@ -1,19 +1,136 @@
 use serde::{Deserialize, Serialize};
-use sqlx::FromRow;
 
-#[derive(Serialize, Deserialize, FromRow)]
+/// What introspection found in the selected schemas of a database.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Database {
+    pub schemas: Vec<Schema>,
+}
+
+/// A schema as introspected, or as desired when diffing, where only the
+/// tables count.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Schema {
+    #[serde(default)]
+    pub name: String,
+    pub tables: Vec<Table>,
+    #[serde(default)]
+    pub views: Vec<Table>,
+    #[serde(default)]
+    pub materialized_views: Vec<Table>,
+}
+
+/// A table or view as found by introspection, with its keys and
+/// constraints. Also the declarative definition tables are created from,
+/// where everything but the name and columns is optional.
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Table {
     pub name: String,
+    pub columns: Vec<VColumn>,
+    #[serde(default)]
+    pub primary_key: Vec<String>,
+    #[serde(default)]
+    pub foreign_keys: Vec<ForeignKey>,
+    #[serde(default)]
+    pub indexes: Vec<Index>,
+    #[serde(default)]
+    pub checks: Vec<Check>,
 }
 
-#[derive(Serialize, Deserialize, FromRow)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct VColumn {
     pub name: String,
     pub data_type: String,
+    /// The type as the backend's `Dialect` expects it: the `udt_name` on
+    /// Postgres, the `data_type` on MySQL and the lowercased declared type
+    /// on SQLite.
+    #[serde(default)]
+    pub column_type: String,
+    /// `YES` or `NO`.
+    #[serde(default = "nullable")]
     pub is_nullable: String,
-    // pub is_primary_key: bool,
-    // pub is_unique: bool,
-    // pub is_auto_increment: bool,
+    #[serde(default)]
+    pub is_primary_key: bool,
+    /// Whether the column alone is unique, through the primary key or a
+    /// unique index.
+    #[serde(default)]
+    pub is_unique: bool,
+    #[serde(default)]
+    pub is_auto_increment: bool,
     pub maximum_length: Option<i32>,
+    /// An SQL expression, as the database reports it.
     pub default_value: Option<String>,
 }
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ForeignKey {
+    /// SQLite foreign keys have no name.
+    pub name: Option<String>,
+    #[serde(default)]
+    pub columns: Vec<String>,
+    /// Empty for the current schema.
+    #[serde(default)]
+    pub referenced_schema: String,
+    pub referenced_table: String,
+    /// Empty for the primary key of the referenced table.
+    #[serde(default)]
+    pub referenced_columns: Vec<String>,
+    #[serde(default = "no_action")]
+    pub on_delete: String,
+    #[serde(default = "no_action")]
+    pub on_update: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Index {
+    pub name: String,
+    pub columns: Vec<String>,
+    #[serde(default)]
+    pub is_unique: bool,
+    #[serde(default)]
+    pub is_primary: bool,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Check {
+    pub name: Option<String>,
+    pub expression: String,
+}
+
+/// How the live schema differs from a desired one.
+#[derive(Debug, Default, Serialize)]
+pub struct SchemaDiff {
+    pub added_tables: Vec<String>,
+    pub removed_tables: Vec<String>,
+    pub changed_tables: Vec<TableDiff>,
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct TableDiff {
+    pub name: String,
+    pub added_columns: Vec<String>,
+    pub removed_columns: Vec<String>,
+    pub changed_columns: Vec<ColumnDiff>,
+    pub added_foreign_keys: Vec<ForeignKey>,
+    pub removed_foreign_keys: Vec<ForeignKey>,
+    pub added_indexes: Vec<Index>,
+    pub removed_indexes: Vec<Index>,
+    pub added_checks: Vec<Check>,
+    pub removed_checks: Vec<Check>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ColumnDiff {
+    pub name: String,
+    /// The `VColumn` fields that differ.
+    pub changes: Vec<&'static str>,
+    pub from: VColumn,
+    pub to: VColumn,
+}
+
+fn nullable() -> String {
+    "YES".to_string()
+}
+
+fn no_action() -> String {
+    "NO ACTION".to_string()
+}
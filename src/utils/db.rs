@@ -1,15 +1,47 @@
-use sqlx::{Postgres, Sqlite, Pool, postgres::PgPoolOptions, sqlite::SqlitePoolOptions};
+use std::time::Duration;
 
-pub async fn get_pg_pool(max_conn: u32, url: &str) -> Result<Pool<Postgres>, sqlx::Error> {
-    PgPoolOptions::new()
-        .max_connections(max_conn)
-        .connect(url)
-        .await
+use sqlx::{
+    mysql::MySqlPoolOptions, postgres::PgPoolOptions, sqlite::SqlitePoolOptions, MySql, Pool,
+    Postgres, Sqlite,
+};
+
+#[derive(Debug, Clone, Copy)]
+pub struct PoolConfig {
+    pub max_connections: u32,
+    pub acquire_timeout: Option<Duration>,
+    pub idle_timeout: Option<Duration>,
+}
+
+impl PoolConfig {
+    pub fn new(max_connections: u32) -> Self {
+        Self {
+            max_connections,
+            acquire_timeout: None,
+            idle_timeout: None,
+        }
+    }
+}
+
+pub async fn get_pg_pool(config: &PoolConfig, url: &str) -> Result<Pool<Postgres>, sqlx::Error> {
+    let mut options = PgPoolOptions::new().max_connections(config.max_connections);
+    if let Some(timeout) = config.acquire_timeout {
+        options = options.acquire_timeout(timeout);
+    }
+    options.idle_timeout(config.idle_timeout).connect(url).await
+}
+
+pub async fn get_mysql_pool(config: &PoolConfig, url: &str) -> Result<Pool<MySql>, sqlx::Error> {
+    let mut options = MySqlPoolOptions::new().max_connections(config.max_connections);
+    if let Some(timeout) = config.acquire_timeout {
+        options = options.acquire_timeout(timeout);
+    }
+    options.idle_timeout(config.idle_timeout).connect(url).await
 }
 
-pub async fn get_sqlite_pool(max_conn: u32, url: &str) -> Result<Pool<Sqlite>, sqlx::Error> {
-    SqlitePoolOptions::new()
-        .max_connections(max_conn)
-        .connect(url)
-        .await
+pub async fn get_sqlite_pool(config: &PoolConfig, url: &str) -> Result<Pool<Sqlite>, sqlx::Error> {
+    let mut options = SqlitePoolOptions::new().max_connections(config.max_connections);
+    if let Some(timeout) = config.acquire_timeout {
+        options = options.acquire_timeout(timeout);
+    }
+    options.idle_timeout(config.idle_timeout).connect(url).await
 }